@@ -1,14 +1,102 @@
 use criterion::Criterion;
 use criterion::{criterion_group, criterion_main};
 
-use kuromoji::Tokenizer;
+use kuromoji::connection::ConnectionCostMatrix;
+use kuromoji::prefix_dict::PrefixDict;
+use kuromoji::prefix_index::PrefixIndexType;
+use kuromoji::{Dictionary, Mode, Tokenizer};
+use std::ops::Deref;
+use std::sync::Arc;
+
+const TEXT: &str = "検索エンジン（けんさくエンジン、英語: search engine）は、狭義にはインターネットに存在する情報（ウェブページ、ウェブサイト、画像ファイル、ネットニュースなど）を検索する機能およびそのプログラム。";
 
 fn bench_tokenize(c: &mut Criterion) {
     c.bench_function("bench-wiki", |b| {
-        let mut tokenizer = Tokenizer::new();
-        b.iter(|| tokenizer.tokenize("検索エンジン（けんさくエンジン、英語: search engine）は、狭義にはインターネットに存在する情報（ウェブページ、ウェブサイト、画像ファイル、ネットニュースなど）を検索する機能およびそのプログラム。"))
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        b.iter(|| tokenizer.tokenize(TEXT).len())
+    });
+}
+
+fn bench_tokenize_details(c: &mut Criterion) {
+    c.bench_function("bench-wiki-details", |b| {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        b.iter(|| {
+            let tokens = tokenizer.tokenize(TEXT);
+            tokens
+                .iter()
+                .map(|token| tokenizer.detail(token).reading.len())
+                .sum::<usize>()
+        })
+    });
+}
+
+fn bench_tokenize_compact_cost_matrix(c: &mut Criterion) {
+    c.bench_function("bench-wiki-compact-matrix", |b| {
+        let dictionary = Dictionary::load_default()
+            .unwrap()
+            .with_compact_cost_matrix()
+            .unwrap();
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary), Mode::Normal);
+        b.iter(|| tokenizer.tokenize(TEXT).len())
+    });
+}
+
+fn sum_costs<D: Deref<Target = [u8]>>(cost_matrix: &ConnectionCostMatrix<D>) -> i32 {
+    // Mimics the access pattern of the Viterbi algorithm: a fixed right word,
+    // and many left words.
+    let mut total = 0i32;
+    for forward_id in (0..cost_matrix.forward_size()).step_by(7) {
+        for backward_id in (0..cost_matrix.backward_size()).step_by(3) {
+            total = total.wrapping_add(cost_matrix.cost(backward_id, forward_id));
+        }
+    }
+    total
+}
+
+fn bench_cost_matrix(c: &mut Criterion) {
+    c.bench_function("bench-cost-matrix-dense", |b| {
+        let cost_matrix = ConnectionCostMatrix::load_default().unwrap();
+        b.iter(|| sum_costs(&cost_matrix))
+    });
+    c.bench_function("bench-cost-matrix-compact", |b| {
+        let cost_matrix = ConnectionCostMatrix::load_default()
+            .unwrap()
+            .to_compact()
+            .unwrap();
+        b.iter(|| sum_costs(&cost_matrix))
+    });
+}
+
+fn count_prefixes<D: Deref<Target = [u8]>>(prefix_dict: &PrefixDict<D>) -> usize {
+    let mut count = 0;
+    for (offset, _) in TEXT.char_indices() {
+        prefix_dict.for_each_prefix(&TEXT[offset..], |_, _| count += 1);
+    }
+    count
+}
+
+fn bench_prefix_index(c: &mut Criterion) {
+    c.bench_function("bench-prefix-fst", |b| {
+        let prefix_dict = PrefixDict::load_default().unwrap();
+        b.iter(|| count_prefixes(&prefix_dict))
+    });
+    c.bench_function("bench-prefix-double-array", |b| {
+        let prefix_dict = PrefixDict::load_default_double_array().unwrap();
+        b.iter(|| count_prefixes(&prefix_dict))
+    });
+    c.bench_function("bench-wiki-double-array", |b| {
+        let dictionary = Dictionary::load_default_with_index(PrefixIndexType::DoubleArray).unwrap();
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary), Mode::Normal);
+        b.iter(|| tokenizer.tokenize(TEXT).len())
     });
 }
 
-criterion_group!(benches, bench_tokenize);
+criterion_group!(
+    benches,
+    bench_tokenize,
+    bench_tokenize_details,
+    bench_tokenize_compact_cost_matrix,
+    bench_cost_matrix,
+    bench_prefix_index
+);
 criterion_main!(benches);
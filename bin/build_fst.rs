@@ -1,57 +1,35 @@
-use bincode;
-use byteorder::ByteOrder;
 use byteorder::{LittleEndian, WriteBytesExt};
-use encoding::all::UTF_16LE;
 use encoding::{DecoderTrap, Encoding};
-use kuromoji::character_definition::{CategoryData, CategoryId, LookupTable};
+use kuromoji::character_definition::CharacterDefinitionsBuilder;
+use kuromoji::connection::{ConnectionCostMatrix, COMPACT_MATRIX};
+use kuromoji::container::{ContainerWriter, Metadata};
+use kuromoji::prefix_dict::serialize_word_entries;
+use kuromoji::prefix_index::{DoubleArrayIndex, PrefixIndexType};
+use kuromoji::reading_index::{build_reading_index, READING_INDEX_FST, READING_INDEX_VALS};
 use kuromoji::unknown_dictionary::UnknownDictionary;
-use kuromoji::{CharacterDefinitions, WordId};
+use kuromoji::{CharacterDefinitions, KuromojiError, WordId};
 use kuromoji::{WordDetail, WordEntry};
-use std::collections::{BTreeMap, HashMap, BTreeSet};
-use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::num::ParseIntError;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::u32;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tantivy_fst::MapBuilder;
 
-fn read_mecab_file(filename: &'static str) -> Result<String, ParsingError> {
-    let path = Path::new("mecab-ipadic2").join(Path::new(filename));
+const DEFAULT_SOURCE_DIR: &str = "mecab-ipadic2";
+
+fn read_mecab_file(path: &Path) -> Result<String, KuromojiError> {
     let mut input_read = File::open(path)?;
     let mut buffer = Vec::new();
     input_read.read_to_end(&mut buffer)?;
     encoding::all::EUC_JP
         .decode(&buffer, DecoderTrap::Strict)
-        .map_err(|_| ParsingError::Encoding)
-}
-
-#[derive(Debug)]
-pub enum ParsingError {
-    Encoding,
-    IoError(io::Error),
-    ContentError(String),
-}
-
-impl ParsingError {
-    pub fn from_error<D: Debug>(error: D) -> ParsingError {
-        ParsingError::ContentError(format!("{:?}", error))
-    }
-}
-
-impl From<io::Error> for ParsingError {
-    fn from(io_err: io::Error) -> Self {
-        ParsingError::IoError(io_err)
-    }
-}
-
-impl From<ParseIntError> for ParsingError {
-    fn from(parse_err: ParseIntError) -> Self {
-        ParsingError::from_error(parse_err)
-    }
+        .map_err(|_| KuromojiError::Encoding)
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct CSVRow<'a> {
     surface_form: &'a str,
@@ -73,14 +51,20 @@ pub struct CSVRow<'a> {
 }
 
 impl<'a> CSVRow<'a> {
-    fn from_line(line: &'a String) -> CSVRow<'a> {
-        let fields: Vec<_> = line.split(",").collect();
-        let word_cost = i32::from_str(&fields[3]).expect("failed to parse wordost");
-        CSVRow {
+    fn from_line(line: &'a str) -> Result<CSVRow<'a>, KuromojiError> {
+        let fields: Vec<_> = line.split(',').collect();
+        if fields.len() < 13 {
+            return Err(KuromojiError::Format(format!(
+                "Invalid number of fields in {:?}. Expect at least 13, got {}",
+                line,
+                fields.len()
+            )));
+        }
+        Ok(CSVRow {
             surface_form: fields[0],
-            left_id: u32::from_str(&fields[1]).expect("failed to parse left_id"),
-            right_id: u32::from_str(&fields[2]).expect("failed to parse right_id"),
-            word_cost: i32::from_str(&fields[3]).expect("failed to parse wordost"),
+            left_id: u32::from_str(fields[1])?,
+            right_id: u32::from_str(fields[2])?,
+            word_cost: i32::from_str(fields[3])?,
             pos_level1: fields[4],
             pos_level2: fields[5],
             pos_level3: fields[6],
@@ -92,45 +76,27 @@ impl<'a> CSVRow<'a> {
             base_form: fields[10],
             reading: fields[11],
             pronunciation: fields[12],
-        }
+        })
     }
 }
 
-const FILENAMES: [&'static str; 26] = [
-    "Adj.csv",
-    "Noun.demonst.csv",
-    "Noun.others.csv",
-    "Postp-col.csv",
-    "Filler.csv",
-    "Verb.csv",
-    "Adnominal.csv",
-    "Interjection.csv",
-    "Noun.nai.csv",
-    "Noun.place.csv",
-    "Postp.csv",
-    "Adverb.csv",
-    "Noun.adjv.csv",
-    "Noun.name.csv",
-    "Noun.proper.csv",
-    "Prefix.csv",
-    "Auxil.csv",
-    "Noun.adverbal.csv",
-    "Noun.number.csv",
-    "Noun.verbal.csv",
-    "Suffix.csv",
-    "Conjunction.csv",
-    "Noun.csv",
-    "Noun.org.csv",
-    "Others.csv",
-    "Symbol.csv",
-];
-
-fn build_dict() -> Result<(), ParsingError> {
+/// Reads the CSV files of the source directory, in the order of their names.
+fn read_csv_files(source_dir: &Path) -> Result<Vec<String>, KuromojiError> {
+    let mut csv_paths: Vec<PathBuf> = std::fs::read_dir(source_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    csv_paths.retain(|path| path.extension().map(|ext| ext == "csv").unwrap_or(false));
+    csv_paths.sort();
+    csv_paths.iter().map(|path| read_mecab_file(path)).collect()
+}
+
+fn build_dict(
+    source_dir: &Path,
+    prefix_index_type: PrefixIndexType,
+    reading_index: bool,
+) -> Result<(), KuromojiError> {
     println!("BUILD DICT");
-    let files_data: Vec<String> = FILENAMES
-        .iter()
-        .map(|filename| read_mecab_file(filename))
-        .collect::<Result<Vec<String>, ParsingError>>()?;
+    let files_data: Vec<String> = read_csv_files(source_dir)?;
     println!("  - read files");
     let lines: Vec<String> = files_data
         .iter()
@@ -140,23 +106,29 @@ fn build_dict() -> Result<(), ParsingError> {
                 .map(|c| {
                     if c == '―' {
                         // yeah for EUC_JP and ambiguous unicode 8012 vs 8013
-                        return '—';
+                        '—'
                     } else if c == '～' {
                         // same bullshit as above between for 12316 vs 65374
-                        return '〜';
+                        '〜'
                     } else {
-                        return c;
+                        c
                     }
                 })
                 .collect::<String>()
         })
         .collect();
-    let mut rows: Vec<CSVRow> = lines.iter().map(CSVRow::from_line).collect();
+    let mut rows: Vec<CSVRow> = lines
+        .iter()
+        .map(|line| {
+            let row = CSVRow::from_line(line)?;
+            validate_row(&row)?;
+            Ok(row)
+        })
+        .collect::<Result<_, KuromojiError>>()?;
     println!("  - parsed csv");
-    rows.sort_by_key(|row| row.surface_form.clone());
+    rows.sort_by_key(|row| row.surface_form);
     println!("  - sorted csv");
 
-    let wtr_fst = io::BufWriter::new(File::create("dict/dict.fst")?);
     let mut wtr_vals = io::BufWriter::new(File::create("dict/dict.vals")?);
 
     let mut word_entry_map: BTreeMap<String, Vec<WordEntry>> = BTreeMap::new();
@@ -171,7 +143,7 @@ fn build_dict() -> Result<(), ParsingError> {
         }
         word_entry_map
             .entry(row.surface_form.to_string())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(WordEntry {
                 word_id: WordId(row_id as u32),
                 word_cost: row.word_cost as i16,
@@ -184,7 +156,15 @@ fn build_dict() -> Result<(), ParsingError> {
     let mut words_buffer = Vec::new();
     for row in rows.iter() {
         let word = WordDetail {
-            reading: row.reading.to_string(),
+            pos_level1: row.pos_level1,
+            pos_level2: row.pos_level2,
+            pos_level3: row.pos_level3,
+            pos_level4: row.pos_level4,
+            conjugation_type: row.conjugation_type,
+            conjugate_form: row.conjugate_form,
+            base_form: row.base_form,
+            reading: row.reading,
+            pronunciation: row.pronunciation,
         };
         let offset = words_buffer.len();
         wtr_words_idx.write_u32::<LittleEndian>(offset as u32)?;
@@ -195,32 +175,102 @@ fn build_dict() -> Result<(), ParsingError> {
     wtr_words.flush()?;
     wtr_words_idx.flush()?;
 
-    let mut id = 0u64;
-
-    println!("  - building fst");
-    let mut fst_build = MapBuilder::new(wtr_fst).unwrap();
+    let mut vals_data = Vec::new();
+    let mut key_values: Vec<(&str, u64)> = Vec::new();
     for (key, word_entries) in &word_entry_map {
-        let len = word_entries.len() as u64;
-        assert!(len < (1 << 5));
-        let val = (id << 5) | len;
-        fst_build.insert(&key, val).unwrap();
-        id += len;
+        if word_entries.len() > u32::MAX as usize {
+            return Err(KuromojiError::Format(format!(
+                "Surface form {:?} has {} entries. At most {} are supported.",
+                key,
+                word_entries.len(),
+                u32::MAX
+            )));
+        }
+        let val = serialize_word_entries(word_entries, &mut vals_data);
+        key_values.push((key, val));
     }
-    fst_build.finish().unwrap();
-    println!("  - built fst");
-    for word_entries in word_entry_map.values() {
-        for word_entry in word_entries {
-            word_entry.serialize(&mut wtr_vals)?;
+
+    println!("  - building {}", prefix_index_type);
+    let index_path = Path::new("dict").join(prefix_index_type.filename());
+    let mut wtr_index = io::BufWriter::new(File::create(index_path)?);
+    match prefix_index_type {
+        PrefixIndexType::Fst => {
+            let mut fst_build = MapBuilder::new(wtr_index).unwrap();
+            for &(key, val) in &key_values {
+                fst_build.insert(key, val).unwrap();
+            }
+            fst_build.finish().unwrap();
+        }
+        PrefixIndexType::DoubleArray => {
+            let double_array_index =
+                DoubleArrayIndex::build(&key_values)?;
+            wtr_index.write_all(double_array_index.as_bytes())?;
+            wtr_index.flush()?;
         }
     }
+    println!("  - built {}", prefix_index_type);
+    wtr_vals.write_all(&vals_data)?;
     wtr_vals.flush()?;
     println!(" - built values");
+
+    if reading_index {
+        let words = rows.iter().enumerate().map(|(row_id, row)| {
+            let word_entry = WordEntry {
+                word_id: WordId(row_id as u32),
+                word_cost: row.word_cost as i16,
+                cost_id: row.left_id as u16,
+            };
+            (row.reading, row.surface_form, word_entry)
+        });
+        let (fst_data, vals_data) = build_reading_index(words)?;
+        std::fs::write(Path::new("dict").join(READING_INDEX_FST), fst_data)?;
+        std::fs::write(Path::new("dict").join(READING_INDEX_VALS), vals_data)?;
+        println!(" - built reading index");
+    }
     Ok(())
 }
 
-fn build_cost_matrix() -> Result<(), ParsingError> {
+/// Checks that a row can be represented as a `WordEntry`.
+fn validate_row(row: &CSVRow) -> Result<(), KuromojiError> {
+    if row.surface_form.is_empty() {
+        return Err(KuromojiError::Format(
+            "Empty surface form in the dictionary".to_string(),
+        ));
+    }
+    validate_word_entry(row.surface_form, row.left_id, row.right_id, row.word_cost)
+}
+
+/// Checks that the costs of a word can be represented as a `WordEntry`.
+fn validate_word_entry(
+    surface: &str,
+    left_id: u32,
+    right_id: u32,
+    word_cost: i32,
+) -> Result<(), KuromojiError> {
+    if word_cost < i16::MIN as i32 || word_cost > i16::MAX as i32 {
+        return Err(KuromojiError::Format(format!(
+            "Word cost {} of {:?} does not fit in an i16",
+            word_cost, surface
+        )));
+    }
+    if left_id > u16::MAX as u32 {
+        return Err(KuromojiError::Format(format!(
+            "Context id {} of {:?} does not fit in a u16",
+            left_id, surface
+        )));
+    }
+    if left_id != right_id {
+        return Err(KuromojiError::Format(format!(
+            "Left id {} and right id {} of {:?} differ. Only identical ids are supported.",
+            left_id, right_id, surface
+        )));
+    }
+    Ok(())
+}
+
+fn build_cost_matrix(source_dir: &Path, compact_matrix: bool) -> Result<(), KuromojiError> {
     println!("BUILD COST MATRIX");
-    let matrix_data = read_mecab_file("matrix.def")?;
+    let matrix_data = read_mecab_file(&source_dir.join("matrix.def"))?;
     let mut lines = Vec::new();
     for line in matrix_data.lines() {
         let fields: Vec<i32> = line
@@ -234,7 +284,7 @@ fn build_cost_matrix() -> Result<(), ParsingError> {
     let forward_size = header[0] as u32;
     let backward_size = header[1] as u32;
     let len = 2 + (forward_size * backward_size) as usize;
-    let mut costs = vec![i16::max_value(); len];
+    let mut costs = vec![i16::MAX; len];
     costs[0] = forward_size as i16;
     costs[1] = backward_size as i16;
     for fields in lines_it {
@@ -249,158 +299,18 @@ fn build_cost_matrix() -> Result<(), ParsingError> {
         wtr.write_i16::<LittleEndian>(cost)?;
     }
     wtr.flush()?;
-    Ok(())
-}
-
-const DEFAULT_CATEGORY_NAME: &'static str = "DEFAULT";
-
-#[derive(Default)]
-pub struct CharacterDefinitionsBuilder {
-    category_definition: Vec<CategoryData>,
-    category_index: HashMap<String, CategoryId>,
-    char_ranges: Vec<(u32, u32, Vec<CategoryId>)>,
-}
-
-fn ucs2_to_unicode(ucs2_codepoint: u16) -> u32 {
-    let mut buf = [0u8; 2];
-    LittleEndian::write_u16(&mut buf[..], ucs2_codepoint);
-    let s: String = UTF_16LE.decode(&buf[..], DecoderTrap::Strict).unwrap();
-    let chrs: Vec<char> = s.chars().collect();
-    assert_eq!(chrs.len(), 1);
-    chrs[0] as u32
-}
-
-fn parse_hex_codepoint(s: &str) -> Result<u32, ParsingError> {
-    let removed_0x = s.trim_start_matches("0x");
-    let ucs2_codepoint = u16::from_str_radix(removed_0x, 16).map_err(ParsingError::from_error)?;
-    let utf8_str = ucs2_to_unicode(ucs2_codepoint);
-    Ok(utf8_str)
-}
-
-
-impl CharacterDefinitionsBuilder {
-    pub fn category_id(&mut self, category_name: &str) -> CategoryId {
-        let num_categories = self.category_index.len();
-        *self
-            .category_index
-            .entry(category_name.to_string())
-            .or_insert(CategoryId(num_categories))
-    }
-
-    fn lookup_categories(&self, c: u32, categories_buffer: &mut Vec<CategoryId>) {
-        categories_buffer.clear();
-        for (start, stop, category_ids) in &self.char_ranges {
-            if *start <= c && *stop >= c {
-                for cat in category_ids {
-                    if !categories_buffer.contains(cat) {
-                        categories_buffer.push(*cat);
-                    }
-                }
-            }
-        }
-        if categories_buffer.is_empty() {
-            let default_category =
-                self.category_index
-                    .get(DEFAULT_CATEGORY_NAME)
-                    .unwrap();
-            categories_buffer.push(*default_category);
-        }
-    }
-
-    fn build_lookup_table(&self) -> LookupTable<CategoryId> {
-        let mut boundaries_set: BTreeSet<u32> =
-            self.char_ranges
-                .iter()
-                .flat_map(|(low, high, _)| vec![*low, *high + 1u32])
-                .collect();
-        let boundaries: Vec<u32> = boundaries_set.into_iter().collect();
-        LookupTable::from_fn(boundaries, &|c, buff| self.lookup_categories(c, buff))
-    }
-
-    pub fn parse(&mut self, content: &String) -> Result<(), ParsingError> {
-        for line in content.lines() {
-            let line_str = line.split('#').next().unwrap().trim();
-            if line_str.is_empty() {
-                continue;
-            }
-            if line_str.starts_with("0x") {
-                self.parse_range(line_str)?;
-            } else {
-                self.parse_category(line_str)?;
-            }
-        }
-        Ok(())
-    }
-
-    fn parse_range(&mut self, line: &str) -> Result<(), ParsingError> {
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        let range_bounds: Vec<&str> = fields[0].split("..").collect();
-        let lower_bound: u32;
-        let higher_bound: u32;
-        match range_bounds.len() {
-            1 => {
-                lower_bound = parse_hex_codepoint(range_bounds[0])?;
-                higher_bound = lower_bound;
-            }
-            2 => {
-                lower_bound = parse_hex_codepoint(range_bounds[0])?;
-                // the right bound is included in the file.
-                higher_bound = parse_hex_codepoint(range_bounds[1])?;
-            }
-            _ => {
-                return Err(ParsingError::ContentError(format!(
-                    "Invalid line: {}",
-                    line
-                )));
-            }
-        }
-        let category_ids: Vec<CategoryId> = fields[1..]
-            .iter()
-            .map(|category| self.category_id(category))
-            .collect();
-        println!("{} - {} => {:?}", lower_bound, higher_bound, &fields[1..]);
-        self.char_ranges
-            .push((lower_bound, higher_bound, category_ids));
-        Ok(())
-    }
-
-    fn parse_category(&mut self, line: &str) -> Result<(), ParsingError> {
-        let fields = line.split_ascii_whitespace().collect::<Vec<&str>>();
-        if fields.len() != 4 {
-            return Err(ParsingError::ContentError(format!(
-                "Expected 4 fields. Got {} in {}",
-                fields.len(),
-                line
-            )));
-        }
-        let invoke = fields[1].parse::<u32>().map_err(ParsingError::from_error)? == 1;
-        let group = fields[2].parse::<u32>().map_err(ParsingError::from_error)? == 1;
-        let length = fields[3].parse::<u32>().map_err(ParsingError::from_error)?;
-        let category_data = CategoryData {
-            invoke,
-            group,
-            length,
-        };
-        // force a category_id allocation
-        self.category_id(fields[0]);
-        self.category_definition.push(category_data);
-        Ok(())
-    }
-
-    pub fn build(self) -> CharacterDefinitions {
-        let mut category_names: Vec<String> = (0..self.category_index.len())
-            .map(|_| String::new())
-            .collect();
-        for (category_name, category_id) in &self.category_index {
-            category_names[category_id.0] = category_name.clone();
-        }
-        let mapping = self.build_lookup_table();
-        CharacterDefinitions {
-            category_definitions: self.category_definition,
-            category_names,
-            mapping
-        }
+    drop(wtr);
+
+    // `Dictionary::open_dir` loads the compact matrix whenever it exists.
+    let compact_path = Path::new("dict").join(COMPACT_MATRIX);
+    if compact_matrix {
+        let matrix = ConnectionCostMatrix::from_data(std::fs::read("dict/matrix.mtx")?)?;
+        std::fs::write(&compact_path, matrix.to_compact_data()?)?;
+        println!("  - built compact matrix");
+    } else if compact_path.exists() {
+        std::fs::remove_file(&compact_path)?;
     }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -411,9 +321,9 @@ pub struct DictionaryEntry {
     word_cost: i32,
 }
 
-fn parse_dictionary_entry(fields: &[&str]) -> Result<DictionaryEntry, ParsingError> {
+fn parse_dictionary_entry(fields: &[&str]) -> Result<DictionaryEntry, KuromojiError> {
     if fields.len() != 11 {
-        return Err(ParsingError::ContentError(format!(
+        return Err(KuromojiError::Format(format!(
             "Invalid number of fields. Expect 11, got {}",
             fields.len()
         )));
@@ -430,16 +340,16 @@ fn parse_dictionary_entry(fields: &[&str]) -> Result<DictionaryEntry, ParsingErr
     })
 }
 
-fn make_costs_array(entries: &[DictionaryEntry]) -> Vec<WordEntry> {
+fn make_costs_array(entries: &[DictionaryEntry]) -> Result<Vec<WordEntry>, KuromojiError> {
     entries
         .iter()
         .map(|e| {
-            assert_eq!(e.left_id, e.right_id);
-            WordEntry {
-                word_id: WordId(std::u32::MAX),
+            validate_word_entry(&e.surface, e.left_id, e.right_id, e.word_cost)?;
+            Ok(WordEntry {
+                word_id: WordId(u32::MAX),
                 cost_id: e.left_id as u16,
                 word_cost: e.word_cost as i16,
-            }
+            })
         })
         .collect()
 }
@@ -467,8 +377,8 @@ fn make_category_references(categories: &[String], entries: &[DictionaryEntry])
 
 fn parse_unk(
     categories: &[String],
-    file_content: &String,
-) -> Result<UnknownDictionary, ParsingError> {
+    file_content: &str,
+) -> Result<UnknownDictionary, KuromojiError> {
     let mut unknown_dict_entries = Vec::new();
     for line in file_content.lines() {
         let fields: Vec<&str> = line.split(",").collect::<Vec<&str>>();
@@ -477,40 +387,134 @@ fn parse_unk(
     }
 
     let category_references = make_category_references(categories, &unknown_dict_entries[..]);
-    let costs = make_costs_array(&unknown_dict_entries[..]);
+    let costs = make_costs_array(&unknown_dict_entries[..])?;
     Ok(UnknownDictionary {
         category_references,
         costs,
     })
 }
 
-fn build_chardef() -> Result<CharacterDefinitions, ParsingError> {
+fn build_chardef(source_dir: &Path) -> Result<CharacterDefinitions, KuromojiError> {
     println!("BUILD CHARDEF");
     let mut char_definitions_builder = CharacterDefinitionsBuilder::default();
-    let char_def = read_mecab_file("char.def")?;
+    let char_def = read_mecab_file(&source_dir.join("char.def"))?;
     char_definitions_builder.parse(&char_def)?;
-    let char_definitions = char_definitions_builder.build();
+    let char_definitions = char_definitions_builder.build()?;
     let mut wtr_chardef = io::BufWriter::new(File::create("dict/char_def.bin")?);
     bincode::serialize_into(&mut wtr_chardef, &char_definitions)
-        .map_err(ParsingError::from_error)?;
+        .map_err(KuromojiError::from_error)?;
     wtr_chardef.flush()?;
     Ok(char_definitions)
 }
 
-fn build_unk(chardef: &CharacterDefinitions) -> Result<(), ParsingError> {
+fn build_unk(source_dir: &Path, chardef: &CharacterDefinitions) -> Result<(), KuromojiError> {
     println!("BUILD UNK");
-    let unk_data = crate::read_mecab_file("unk.def")?;
-    let unknown_dictionary = parse_unk(&chardef.categories(), &unk_data)?;
+    let unk_data = crate::read_mecab_file(&source_dir.join("unk.def"))?;
+    let unknown_dictionary = parse_unk(chardef.categories(), &unk_data)?;
     let mut wtr_unk = io::BufWriter::new(File::create("dict/unk.bin")?);
-    bincode::serialize_into(&mut wtr_unk, &unknown_dictionary).map_err(ParsingError::from_error)?;
+    bincode::serialize_into(&mut wtr_unk, &unknown_dictionary).map_err(KuromojiError::from_error)?;
     wtr_unk.flush()?;
     Ok(())
 }
 
-fn main() -> Result<(), ParsingError> {
-    let chardef = build_chardef()?;
-    build_unk(&chardef)?;
-    build_dict()?;
-    build_cost_matrix()?;
+/// Bundles the files built in `dict/` into a single container file.
+fn build_container(
+    source_dir: &Path,
+    prefix_index_type: PrefixIndexType,
+    reading_index: bool,
+    compact_matrix: bool,
+    container_path: &str,
+) -> Result<(), KuromojiError> {
+    println!("BUILD CONTAINER");
+    let build_date = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let metadata = Metadata {
+        source_dictionary: source_dir.display().to_string(),
+        encoding: "EUC-JP".to_string(),
+        build_date,
+        feature_schema: WordDetail::FEATURE_SCHEMA
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+    };
+    let mut container_writer = ContainerWriter::new(metadata);
+    let mut section_names = vec![
+        prefix_index_type.filename(),
+        "dict.vals",
+        "dict.words",
+        "dict.wordsidx",
+        if compact_matrix {
+            COMPACT_MATRIX
+        } else {
+            "matrix.mtx"
+        },
+        "char_def.bin",
+        "unk.bin",
+    ];
+    if reading_index {
+        section_names.extend_from_slice(&[READING_INDEX_FST, READING_INDEX_VALS]);
+    }
+    for section_name in &section_names {
+        let data = std::fs::read(Path::new("dict").join(section_name))?;
+        container_writer.add_section(section_name, data);
+    }
+    let mut wtr = io::BufWriter::new(File::create(container_path)?);
+    container_writer.write(&mut wtr)?;
+    println!("  - wrote {}", container_path);
+    Ok(())
+}
+
+/// Usage: `build-fst [--dicdir <dir>] [--prefix-index fst|double-array] [--reading-index]
+/// [--compact-matrix] [--container <path>]`
+///
+/// The source dictionary is read from `--dicdir`, `mecab-ipadic2` by default: every CSV
+/// file of the directory, `matrix.def`, `char.def` and `unk.def`, encoded in EUC-JP.
+/// The dictionary files are written to `dict/`, from where `Dictionary::open_dir` loads
+/// them, and bundled into a container, loaded by `Dictionary::open`. The container
+/// defaults to `dict/ipadic.dic`. With `--reading-index`, an index of the words by
+/// reading is also built, and added to the container. With `--compact-matrix`, the
+/// connection cost matrix is stored in its compact layout, see
+/// `ConnectionCostMatrix::to_compact_data`.
+fn main() -> Result<(), KuromojiError> {
+    let mut source_dir = PathBuf::from(DEFAULT_SOURCE_DIR);
+    let mut prefix_index_type = PrefixIndexType::Fst;
+    let mut reading_index = false;
+    let mut compact_matrix = false;
+    let mut container_path = "dict/ipadic.dic".to_string();
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        if arg == "--dicdir" {
+            source_dir = args_it.next().map(PathBuf::from).ok_or_else(|| {
+                KuromojiError::Format("Missing path after --dicdir".to_string())
+            })?;
+        } else if arg == "--prefix-index" {
+            let prefix_index_type_str = args_it.next().unwrap_or_default();
+            prefix_index_type =
+                PrefixIndexType::from_str(&prefix_index_type_str).map_err(KuromojiError::Format)?;
+        } else if arg == "--reading-index" {
+            reading_index = true;
+        } else if arg == "--compact-matrix" {
+            compact_matrix = true;
+        } else if arg == "--container" {
+            container_path = args_it.next().ok_or_else(|| {
+                KuromojiError::Format("Missing path after --container".to_string())
+            })?;
+        } else {
+            return Err(KuromojiError::Format(format!("Unknown argument {:?}", arg)));
+        }
+    }
+    let chardef = build_chardef(&source_dir)?;
+    build_unk(&source_dir, &chardef)?;
+    build_dict(&source_dir, prefix_index_type, reading_index)?;
+    build_cost_matrix(&source_dir, compact_matrix)?;
+    build_container(
+        &source_dir,
+        prefix_index_type,
+        reading_index,
+        compact_matrix,
+        &container_path,
+    )?;
     Ok(())
 }
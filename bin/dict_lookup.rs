@@ -0,0 +1,109 @@
+use kuromoji::{Dictionary, DictionaryEntry, KuromojiError};
+use std::env;
+use std::io::{self, BufWriter, Write};
+
+const USAGE: &str = "Usage: dict-lookup [--dictionary <path>] \
+                     [--exact | --prefix | --reading | --reading-prefix | --fuzzy <distance> | --regex | --all] \
+                     [<query>]";
+
+enum Lookup {
+    Exact,
+    Prefix,
+    Reading,
+    ReadingPrefix,
+    Fuzzy(u8),
+    Regex,
+    All,
+}
+
+/// Prints the words of the dictionary matching a query, one per line:
+/// surface form, word id, cost, context id, and features.
+///
+/// By default, the query is the exact surface form of the words. The dictionary
+/// embedded in the library is used, unless a container file is given with
+/// `--dictionary`. Fuzzy matches are printed by increasing edit distance. Lookups
+/// by reading scan the whole dictionary, unless it holds a reading index.
+fn main() -> Result<(), KuromojiError> {
+    let mut dictionary_path = None;
+    let mut lookup = Lookup::Exact;
+    let mut query = None;
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        let invalid = || KuromojiError::Format(format!("Invalid argument {:?}\n{}", arg, USAGE));
+        match arg.as_str() {
+            "--dictionary" => dictionary_path = Some(args_it.next().ok_or_else(invalid)?),
+            "--exact" => lookup = Lookup::Exact,
+            "--prefix" => lookup = Lookup::Prefix,
+            "--reading" => lookup = Lookup::Reading,
+            "--reading-prefix" => lookup = Lookup::ReadingPrefix,
+            "--fuzzy" => {
+                let max_distance = args_it
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(invalid)?;
+                lookup = Lookup::Fuzzy(max_distance);
+            }
+            "--regex" => lookup = Lookup::Regex,
+            "--all" => lookup = Lookup::All,
+            _ if query.is_none() && !arg.starts_with("--") => query = Some(arg.clone()),
+            _ => return Err(invalid()),
+        }
+    }
+    let dictionary = match dictionary_path {
+        Some(dictionary_path) => Dictionary::open(dictionary_path)?,
+        None => Dictionary::load_default()?,
+    };
+    let stdout = io::stdout();
+    let mut wtr = BufWriter::new(stdout.lock());
+    if let Lookup::All = lookup {
+        let mut result = Ok(());
+        dictionary.for_each_entry(|entry| {
+            if result.is_ok() {
+                result = write_entry(&mut wtr, &entry);
+            }
+        })?;
+        result?;
+    } else {
+        let query =
+            query.ok_or_else(|| KuromojiError::Format(format!("Missing query\n{}", USAGE)))?;
+        let entries = match lookup {
+            Lookup::Exact => dictionary.lookup(&query)?,
+            Lookup::Prefix => dictionary.lookup_prefix(&query)?,
+            Lookup::Reading => dictionary.lookup_by_reading(&query)?,
+            Lookup::ReadingPrefix => dictionary.lookup_by_reading_prefix(&query)?,
+            Lookup::Fuzzy(max_distance) => dictionary
+                .lookup_fuzzy(&query, max_distance)?
+                .into_iter()
+                .map(|(_distance, entry)| entry)
+                .collect(),
+            Lookup::Regex => dictionary.lookup_regex(&query)?,
+            Lookup::All => unreachable!(),
+        };
+        for entry in &entries {
+            write_entry(&mut wtr, entry)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_entry(wtr: &mut dyn Write, entry: &DictionaryEntry) -> io::Result<()> {
+    let detail = &entry.detail;
+    writeln!(
+        wtr,
+        "{}\t{}\t{}\t{}\t{},{},{},{},{},{},{},{},{}",
+        entry.surface,
+        entry.word_entry.word_id.0,
+        entry.word_entry.word_cost,
+        entry.word_entry.cost_id,
+        detail.pos_level1,
+        detail.pos_level2,
+        detail.pos_level3,
+        detail.pos_level4,
+        detail.conjugation_type,
+        detail.conjugate_form,
+        detail.base_form,
+        detail.reading,
+        detail.pronunciation
+    )
+}
@@ -0,0 +1,169 @@
+use encoding::label::encoding_from_whatwg_label;
+use encoding::EncodingRef;
+use kuromoji::training::estimate::{
+    ipadic_features, ContextIds, CostEstimator, ModelCostEstimator, PosStatistics,
+};
+use kuromoji::training::{
+    read_dictionary_file, DictionaryRow, FeatureExtractor, Model, Rewriter, TrainingDictionary,
+};
+use kuromoji::KuromojiError;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: estimate-cost [--dicdir <dir>] [--estimator model|pos] \
+                     [--encoding <encoding>] [<input>]";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Estimator {
+    Model,
+    Pos,
+}
+
+/// Assigns context ids and costs to new words.
+///
+/// Each input line (UTF-8, read from stdin if no input is given) is
+/// `surface<TAB>part-of-speech<TAB>reading`, where the part-of-speech holds the
+/// comma-separated POS levels, optionally followed by the conjugation type and form.
+/// Each output line is the corresponding dictionary CSV row.
+///
+/// Context ids come from `left-id.def` and `right-id.def`. Costs come from the
+/// `model.def` written by `train-costs` (`--estimator model`, the default when the
+/// dictionary directory has a `model.def`), or are the median cost of the
+/// dictionary words with the same part-of-speech (`--estimator pos`).
+fn main() -> Result<(), KuromojiError> {
+    let mut dicdir = PathBuf::from("mecab-ipadic");
+    let mut estimator = None;
+    let mut encoding: EncodingRef = encoding::all::EUC_JP;
+    let mut input = None;
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        let mut value = || {
+            args_it
+                .next()
+                .ok_or_else(|| KuromojiError::Format(format!("Missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--dicdir" => dicdir = PathBuf::from(value()?),
+            "--estimator" => {
+                estimator = match value()?.as_str() {
+                    "model" => Some(Estimator::Model),
+                    "pos" => Some(Estimator::Pos),
+                    other => {
+                        return Err(KuromojiError::Format(format!(
+                            "Unknown estimator {:?}\n{}",
+                            other, USAGE
+                        )))
+                    }
+                }
+            }
+            "--encoding" => {
+                let label = value()?;
+                encoding = encoding_from_whatwg_label(&label).ok_or_else(|| {
+                    KuromojiError::Format(format!("Unknown encoding {:?}", label))
+                })?;
+            }
+            _ if input.is_none() && !arg.starts_with("--") => input = Some(PathBuf::from(&arg)),
+            _ => {
+                return Err(KuromojiError::Format(format!(
+                    "Unknown argument {:?}\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+    let estimator = estimator.unwrap_or_else(|| {
+        if dicdir.join("model.def").exists() {
+            Estimator::Model
+        } else {
+            Estimator::Pos
+        }
+    });
+
+    let dictionary = TrainingDictionary::load(&dicdir, encoding)?;
+    let rewriter = Rewriter::load(&dicdir, encoding)?;
+    let context_ids = load_context_ids(&dicdir, encoding)?;
+    let pos_statistics = PosStatistics::new(dictionary.rows());
+    let model = if estimator == Estimator::Model {
+        let extractor = FeatureExtractor::load(&dicdir, encoding)?;
+        let model_data = read_dictionary_file(&dicdir.join("model.def"), encoding::all::UTF_8)?;
+        Some(Model::parse(extractor, &model_data)?)
+    } else {
+        None
+    };
+    let cost_estimator: &dyn CostEstimator = if let Some(model) = model.as_ref() {
+        &ModelCostEstimator {
+            model,
+            char_definitions: &dictionary.char_definitions,
+        }
+    } else {
+        &pos_statistics
+    };
+
+    let reader: Box<dyn BufRead> = if let Some(input) = input {
+        Box::new(BufReader::new(File::open(input)?))
+    } else {
+        Box::new(BufReader::new(io::stdin()))
+    };
+    let stdout = io::stdout();
+    let mut wtr = BufWriter::new(stdout.lock());
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 {
+            return Err(KuromojiError::Format(format!(
+                "Expected `surface<TAB>part-of-speech<TAB>reading`, got {:?}",
+                line
+            )));
+        }
+        let (surface, pos, reading) = (fields[0], fields[1], fields[2]);
+        let features = ipadic_features(surface, pos, reading)?;
+        let feature_refs: Vec<&str> = features.iter().map(String::as_str).collect();
+        let (left_id, right_id) = match &context_ids {
+            Some(context_ids) => {
+                let context_id = context_ids.lookup(&rewriter, &feature_refs)?;
+                (context_id, context_id)
+            }
+            None => pos_statistics.context_ids(&feature_refs).ok_or_else(|| {
+                KuromojiError::Format(format!("No context id for {:?} ({})", surface, pos))
+            })?,
+        };
+        let word_cost = cost_estimator
+            .estimate_cost(surface, &feature_refs)
+            .ok_or_else(|| {
+                KuromojiError::Format(format!("Cannot estimate the cost of {:?}", surface))
+            })?;
+        let row = DictionaryRow {
+            surface: surface.to_string(),
+            left_id,
+            right_id,
+            word_cost,
+            features,
+        };
+        writeln!(wtr, "{}", row.to_csv_line())?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Loads `left-id.def` and `right-id.def`, if the dictionary has them.
+fn load_context_ids(
+    dicdir: &Path,
+    encoding: EncodingRef,
+) -> Result<Option<ContextIds>, KuromojiError> {
+    let left_id_path = dicdir.join("left-id.def");
+    let right_id_path = dicdir.join("right-id.def");
+    if !left_id_path.exists() || !right_id_path.exists() {
+        eprintln!("No left-id.def/right-id.def: using the most frequent ids of each POS");
+        return Ok(None);
+    }
+    let context_ids = ContextIds::parse(
+        &read_dictionary_file(&left_id_path, encoding)?,
+        &read_dictionary_file(&right_id_path, encoding)?,
+    )?;
+    Ok(Some(context_ids))
+}
@@ -0,0 +1,102 @@
+use kuromoji::corpus::{parse_mecab_corpus, parse_wakati_corpus};
+use kuromoji::evaluation::{EvalToken, Evaluation, Level};
+use kuromoji::prefix_index::PrefixIndexType;
+use kuromoji::{Dictionary, KuromojiError, Mode, Penalty, Tokenizer};
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const USAGE: &str = "Usage: evaluate [--format mecab|wakati] [--mode normal|search] \
+                     [--prefix-index fst|double-array] [--compact-matrix] [--errors N] <gold>";
+
+/// Measures the segmentation accuracy of the tokenizer against a gold corpus.
+///
+/// The gold corpus is either in MeCab's output format (the default) or in the
+/// wakati format, with one sentence per line and space-separated tokens. In the
+/// latter case, only word boundaries are evaluated.
+fn main() -> Result<(), KuromojiError> {
+    let mut wakati = false;
+    let mut mode = Mode::Normal;
+    let mut prefix_index_type = PrefixIndexType::Fst;
+    let mut compact_matrix = false;
+    let mut num_errors = 20;
+    let mut gold_path = None;
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        let invalid = || KuromojiError::Format(format!("Invalid argument {:?}\n{}", arg, USAGE));
+        match arg.as_str() {
+            "--format" => match args_it.next().as_deref() {
+                Some("mecab") => wakati = false,
+                Some("wakati") => wakati = true,
+                _ => return Err(invalid()),
+            },
+            "--mode" => match args_it.next().as_deref() {
+                Some("normal") => mode = Mode::Normal,
+                Some("search") => mode = Mode::Search(Penalty::default()),
+                _ => return Err(invalid()),
+            },
+            "--prefix-index" => {
+                prefix_index_type = PrefixIndexType::from_str(&args_it.next().unwrap_or_default())
+                    .map_err(KuromojiError::Format)?;
+            }
+            "--compact-matrix" => compact_matrix = true,
+            "--errors" => {
+                num_errors = args_it
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(invalid)?;
+            }
+            _ if gold_path.is_none() && !arg.starts_with("--") => gold_path = Some(arg.clone()),
+            _ => return Err(invalid()),
+        }
+    }
+    let gold_path = gold_path
+        .ok_or_else(|| KuromojiError::Format(format!("Missing gold corpus\n{}", USAGE)))?;
+    let gold_data = fs::read_to_string(gold_path)?;
+    let sentences = if wakati {
+        parse_wakati_corpus(&gold_data)
+    } else {
+        parse_mecab_corpus(&gold_data)?
+    };
+
+    let mut dictionary = Dictionary::load_default_with_index(prefix_index_type)?;
+    if compact_matrix {
+        dictionary = dictionary.with_compact_cost_matrix()?;
+    }
+    let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary), mode);
+    let mut evaluation = Evaluation::default();
+    for sentence in &sentences {
+        let text = sentence.text();
+        let gold: Vec<EvalToken> = sentence.tokens.iter().map(EvalToken::from_gold).collect();
+        let tokens = tokenizer.tokenize(&text);
+        let predicted: Vec<EvalToken> = tokens
+            .iter()
+            .map(|token| EvalToken::from_token(&tokenizer, token))
+            .collect();
+        evaluation.add_sentence(&gold, &predicted);
+    }
+
+    println!("{} sentences", sentences.len());
+    println!(
+        "{:<22}{:>10}{:>10}{:>10}",
+        "level", "precision", "recall", "f1"
+    );
+    for &level in Level::ALL.iter() {
+        if let Some(scores) = evaluation.scores(level) {
+            println!(
+                "{:<22}{:>10.4}{:>10.4}{:>10.4}",
+                level.to_string(),
+                scores.precision(),
+                scores.recall(),
+                scores.f1()
+            );
+        }
+    }
+    println!();
+    println!("Most frequent errors (gold => predicted):");
+    for (gold, predicted, count) in evaluation.most_frequent_errors(num_errors) {
+        println!("{:>6}  {} => {}", count, gold, predicted);
+    }
+    Ok(())
+}
@@ -1,6 +1,8 @@
-use kuromoji::Tokenizer;
+use kuromoji::{KuromojiError, Tokenizer};
+use std::env;
+use std::process;
 
-const TEXT: &'static str = r#"与えられた検索式に従って、ウェブページ等を検索するサーバ、システムのこと。検索式は、最も単純な場合はキーワードとなる文字列のみであるが、複数のキーワードにAND（「かつ」、論理積）やOR（「または」、論理和）等の論理条件を組み合わせて指定することができるものが多い。
+const TEXT: &str = r#"与えられた検索式に従って、ウェブページ等を検索するサーバ、システムのこと。検索式は、最も単純な場合はキーワードとなる文字列のみであるが、複数のキーワードにAND（「かつ」、論理積）やOR（「または」、論理和）等の論理条件を組み合わせて指定することができるものが多い。
 ロボット型検索エンジンの大きな特徴の一つとして、クローラ（ロボット・スパイダー）を用いることが挙げられる。このことにより、WWW上にある多数の情報を効率よく収集（日本の著作権法では複製）することができる。大規模な検索エンジンでは、80億ページ以上のページから検索が可能になっている。
 収集したページの情報は、前もって解析し、索引情報（インデックス）を作成する（日本の著作権法では編集）。日本語などの言語では、自然言語処理機能が生成される索引の質に影響する。このため、多言語対応した検索エンジンの方が精度の高い検索が可能となる。
 検索結果の表示順は、検索エンジンの質が最も問われる部分である。ユーザーが期待したページを検索結果の上位に表示することができなければ、ユーザーが離れてしまうからである。そのため、多くの検索エンジンが、表示順を決定するアルゴリズムを非公開にし、その性能を競っている。検索エンジン最適化業者の存在も、アルゴリズムを公開しない要因になっている。Googleは、そのアルゴリズムの一部であるPageRankを公開しているが、やはり、多くの部分が非公開になっている。Googleの場合、創設初期におけるアルゴリズムについては、創設者自身がウェブ上で公表している論文でその一端を知ることができる。 参照 英語原文[1]日本語の解説[2]
@@ -20,9 +22,29 @@ P2P通信によってウェブコンテンツのインデックスを多数の
 メタ検索エンジン
 ひとつの検索ワードを複数の検索エンジンで検索することをメタ検索という（横断検索エンジンと呼ぶこともある）。 詳細は「メタ検索エンジン」を参照のこと。"#;
 
-fn main() {
-    let mut tokenizer = Tokenizer::for_search();
-    for _ in 0..10_000 {
-        tokenizer.tokenize(TEXT);
+/// Usage: `flamegraph [vec|reuse]`
+///
+/// `vec` (the default) allocates a new vector of tokens for each call
+/// to `tokenize`, while `reuse` tokenizes into the same buffer over and over.
+fn main() -> Result<(), KuromojiError> {
+    let mode = env::args().nth(1).unwrap_or_else(|| "vec".to_string());
+    let mut tokenizer = Tokenizer::for_search()?;
+    match mode.as_str() {
+        "vec" => {
+            for _ in 0..10_000 {
+                tokenizer.tokenize(TEXT);
+            }
+        }
+        "reuse" => {
+            let mut tokens = Vec::new();
+            for _ in 0..10_000 {
+                tokenizer.tokenize_into(TEXT, &mut tokens);
+            }
+        }
+        _ => {
+            eprintln!("Unknown mode {:?}. Expected `vec` or `reuse`.", mode);
+            process::exit(1);
+        }
     }
+    Ok(())
 }
@@ -1,30 +1,123 @@
-use kuromoji::Tokenizer;
+use kuromoji::{StreamToken, Tokenizer};
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 
+/// Usage: `tester [--threads N] <input> <output>`
+///
+/// Without `--threads`, the input is tokenized as a stream.
+/// With `--threads`, each line is tokenized as an independent document,
+/// and lines are tokenized in parallel. This requires the `rayon` feature.
 fn main() -> io::Result<()> {
-    let mut args_it = env::args();
-    let _ = args_it.next().unwrap();
-    let input = args_it.next().unwrap();
-    let output = args_it.next().unwrap();
-    let f = File::open(input)?;
+    let mut num_threads: Option<usize> = None;
+    let mut paths = Vec::new();
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        if arg == "--threads" {
+            let num_threads_str = args_it.next().expect("--threads expects a value");
+            num_threads = Some(num_threads_str.parse().expect("invalid number of threads"));
+        } else {
+            paths.push(arg);
+        }
+    }
+    assert_eq!(paths.len(), 2, "expected an input and an output path");
+    let f = File::open(&paths[0])?;
     let buff = BufReader::new(f);
 
-    let mut wtr = BufWriter::new(File::create(output)?);
-    let mut tokenizer = Tokenizer::normal();
-    let mut output_line = String::new();
-    for line_res in buff.lines() {
-        output_line.clear();
-        let line = line_res?;
-        for token in tokenizer.tokenize_str(&line) {
-            output_line.push_str(token);
-            output_line.push_str("---");
-        }
-        output_line.push_str("\n");
-        wtr.write_all(&output_line.as_bytes())?;
+    let mut wtr = BufWriter::new(File::create(&paths[1])?);
+    if let Some(num_threads) = num_threads {
+        tokenize_parallel(buff, &mut wtr, num_threads)?;
+    } else {
+        tokenize_stream(buff, &mut wtr)?;
     }
     wtr.flush()?;
     Ok(())
 }
+
+/// Writes the tokens of a line, followed by a line break.
+fn write_line<'a>(tokens: impl Iterator<Item = &'a str>, wtr: &mut dyn Write) -> io::Result<()> {
+    for token in tokens {
+        wtr.write_all(token.as_bytes())?;
+        wtr.write_all(b"---")?;
+    }
+    wtr.write_all(b"\n")
+}
+
+/// Tokenizes the input line by line, with the stream tokenizer. Line breaks,
+/// including a carriage return before them, are not part of the tokens, so that
+/// the output is the same as with `--threads`.
+fn tokenize_stream<R: BufRead>(buff: R, wtr: &mut dyn Write) -> io::Result<()> {
+    let mut tokenizer = Tokenizer::normal().map_err(io::Error::other)?;
+    for line in buff.lines() {
+        let line = line?;
+        let tokens: Vec<StreamToken> = tokenizer
+            .tokenize_reader(line.as_bytes())
+            .collect::<io::Result<_>>()?;
+        write_line(tokens.iter().map(|token| token.text.as_str()), wtr)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+fn tokenize_parallel(
+    buff: BufReader<File>,
+    wtr: &mut dyn Write,
+    num_threads: usize,
+) -> io::Result<()> {
+    const BATCH_SIZE: usize = 10_000;
+
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(io::Error::other)?;
+    let mut tokenizer = Tokenizer::normal().map_err(io::Error::other)?;
+    let mut lines_it = buff.lines();
+    loop {
+        let lines: Vec<String> = lines_it
+            .by_ref()
+            .take(BATCH_SIZE)
+            .collect::<io::Result<_>>()?;
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let texts: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let batch = thread_pool.install(|| tokenizer.tokenize_batch(&texts));
+        for tokens in batch {
+            write_line(tokens.iter().map(|token| token.text), wtr)?;
+        }
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn tokenize_parallel(
+    _buff: BufReader<File>,
+    _wtr: &mut dyn Write,
+    _num_threads: usize,
+) -> io::Result<()> {
+    Err(io::Error::other(
+        "--threads requires kuromoji to be compiled with the `rayon` feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize_stream, write_line};
+    use kuromoji::Tokenizer;
+
+    #[test]
+    fn test_tokenize_stream_crlf() {
+        let text = "東京に行く\r\n僕は ここでは。\r\n\r\n\rすもも\r\n";
+        let mut expected = Vec::new();
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        for line in ["東京に行く", "僕は ここでは。", "", "\rすもも"].iter() {
+            write_line(tokenizer.tokenize_str(line).into_iter(), &mut expected).unwrap();
+        }
+        let mut output = Vec::new();
+        tokenize_stream(text.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+}
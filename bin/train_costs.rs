@@ -0,0 +1,113 @@
+use encoding::label::encoding_from_whatwg_label;
+use encoding::EncodingRef;
+use kuromoji::corpus::parse_mecab_corpus;
+use kuromoji::training::{
+    self, read_dictionary_file, write_dictionary_file, FeatureExtractor, TrainingConfig,
+    TrainingDictionary,
+};
+use kuromoji::KuromojiError;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const USAGE: &str = "Usage: train-costs --corpus <corpus> --output <dir> [--dicdir <dir>] \
+                     [--encoding <encoding>] [--cost-factor <f>] [--c <f>] \
+                     [--max-iterations <n>] [--eta <f>]";
+
+struct Args {
+    dicdir: PathBuf,
+    corpus: PathBuf,
+    output: PathBuf,
+    encoding: EncodingRef,
+    config: TrainingConfig,
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, KuromojiError> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| KuromojiError::Format(format!("Invalid value for {}\n{}", flag, USAGE)))
+}
+
+fn parse_args() -> Result<Args, KuromojiError> {
+    let mut dicdir = PathBuf::from("mecab-ipadic");
+    let mut corpus = None;
+    let mut output = None;
+    let mut encoding: EncodingRef = encoding::all::EUC_JP;
+    let mut config = TrainingConfig::default();
+    let mut args_it = env::args().skip(1);
+    while let Some(arg) = args_it.next() {
+        match arg.as_str() {
+            "--dicdir" => dicdir = parse_value(&arg, args_it.next())?,
+            "--corpus" => corpus = Some(parse_value::<PathBuf>(&arg, args_it.next())?),
+            "--output" => output = Some(parse_value::<PathBuf>(&arg, args_it.next())?),
+            "--encoding" => {
+                let label: String = parse_value(&arg, args_it.next())?;
+                encoding = encoding_from_whatwg_label(&label).ok_or_else(|| {
+                    KuromojiError::Format(format!("Unknown encoding {:?}", label))
+                })?;
+            }
+            "--cost-factor" => config.cost_factor = parse_value(&arg, args_it.next())?,
+            "--c" => config.c = parse_value(&arg, args_it.next())?,
+            "--max-iterations" => config.max_iterations = parse_value(&arg, args_it.next())?,
+            "--eta" => config.eta = parse_value(&arg, args_it.next())?,
+            _ => {
+                return Err(KuromojiError::Format(format!(
+                    "Unknown argument {:?}\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+    let missing = |flag: &str| KuromojiError::Format(format!("Missing {}\n{}", flag, USAGE));
+    Ok(Args {
+        dicdir,
+        corpus: corpus.ok_or_else(|| missing("--corpus"))?,
+        output: output.ok_or_else(|| missing("--output"))?,
+        encoding,
+        config,
+    })
+}
+
+/// Re-estimates the costs of a MeCab dictionary from an annotated corpus.
+///
+/// The corpus is in MeCab's output format, encoded in UTF-8. The output directory
+/// receives the dictionary CSVs and `unk.def` with the new costs and context ids,
+/// `matrix.def`, `left-id.def`, `right-id.def`, a copy of `char.def`, `feature.def`
+/// and `rewrite.def`, and the trained weights in `model.def`. With the default EUC-JP
+/// encoding, it can be fed to `build-fst --dicdir`, and to `estimate-cost` to add new
+/// words.
+fn main() -> Result<(), KuromojiError> {
+    let args = parse_args()?;
+    println!("LOAD DICTIONARY");
+    let dictionary = TrainingDictionary::load(&args.dicdir, args.encoding)?;
+    println!("  - {} words", dictionary.rows().count());
+    let extractor = FeatureExtractor::load(&args.dicdir, args.encoding)?;
+    println!("LOAD CORPUS");
+    let corpus = parse_mecab_corpus(&read_dictionary_file(&args.corpus, encoding::all::UTF_8)?)?;
+    println!("  - {} sentences", corpus.len());
+    println!("TRAIN");
+    let model = training::train(&dictionary, &extractor, &corpus, &args.config, |progress| {
+        println!(
+            "  - iteration {}: objective={:.4}",
+            progress.iteration, progress.objective
+        )
+    })?;
+    println!("  - {} non-zero weights", model.num_features());
+    println!("GENERATE DICTIONARY");
+    let generated = model.generate_dictionary(&dictionary);
+    println!("  - {} context ids", generated.num_contexts());
+    generated.write_dir(&args.output, args.encoding)?;
+    write_dictionary_file(
+        &args.output.join("model.def"),
+        encoding::all::UTF_8,
+        |wtr| model.write(wtr),
+    )?;
+    for filename in &["char.def", "feature.def", "rewrite.def"] {
+        let path = args.dicdir.join(filename);
+        if path.exists() {
+            fs::copy(path, args.output.join(filename))?;
+        }
+    }
+    Ok(())
+}
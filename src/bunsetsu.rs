@@ -0,0 +1,224 @@
+//! Bunsetsu (phrase) chunking.
+//!
+//! A bunsetsu is a content word, followed by its suffixes, particles and auxiliaries,
+//! e.g. `東京に` or `行きます。`. Chunks are computed from the part-of-speech of
+//! the tokens.
+//!
+//! Words that are not in the dictionary have no part-of-speech: they are chunked as
+//! content words, or as whitespace if their text is blank.
+use crate::{Token, Tokenizer, WordDetail};
+use std::ops::Range;
+
+fn is_noun(detail: &WordDetail) -> bool {
+    detail.pos_level1 == "名詞"
+}
+
+fn is_whitespace(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(char::is_whitespace)
+}
+
+/// Returns true for the symbols and whitespace that close a bunsetsu. Brackets are
+/// attached to the words they enclose instead.
+fn ends_bunsetsu(detail: &WordDetail, text: &str) -> bool {
+    is_whitespace(text)
+        || (detail.pos_level1 == "記号"
+            && detail.pos_level2 != "括弧開"
+            && detail.pos_level2 != "括弧閉")
+}
+
+/// Returns true if the token of `detail` and `text` starts a new bunsetsu, rather than
+/// being attached to the bunsetsu of the previous token.
+fn starts_bunsetsu(
+    previous: &WordDetail,
+    previous_text: &str,
+    detail: &WordDetail,
+    text: &str,
+) -> bool {
+    // Prefixes and opening brackets are attached to the next word.
+    if previous.pos_level1 == "接頭詞" || previous.pos_level2 == "括弧開" {
+        return false;
+    }
+    // Punctuation and whitespace are attached to the previous word, and end its bunsetsu.
+    if is_whitespace(text) {
+        return false;
+    }
+    if detail.pos_level1 == "記号" {
+        return detail.pos_level2 == "括弧開";
+    }
+    if ends_bunsetsu(previous, previous_text) {
+        return true;
+    }
+    match (detail.pos_level1, detail.pos_level2) {
+        ("助詞", _) | ("助動詞", _) => false,
+        ("動詞", "非自立") | ("動詞", "接尾") | ("形容詞", "非自立") | ("形容詞", "接尾") => {
+            false
+        }
+        ("名詞", "接尾") => false,
+        // `勉強する`
+        ("動詞", _) if detail.base_form == "する" && previous.pos_level2 == "サ変接続" => {
+            false
+        }
+        // Compound nouns, e.g. `東京都庁`
+        _ if is_noun(detail) => !is_noun(previous),
+        _ => true,
+    }
+}
+
+/// Groups tokens into bunsetsu, and returns the range of tokens of each bunsetsu.
+///
+/// The tokens must come from `tokenizer`.
+pub fn bunsetsu_chunks(tokenizer: &Tokenizer, tokens: &[Token]) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = 0;
+    let mut previous: Option<(WordDetail, &str)> = None;
+    for (token_ord, token) in tokens.iter().enumerate() {
+        let detail = tokenizer.detail(token);
+        if let Some((previous_detail, previous_text)) = previous.as_ref() {
+            if starts_bunsetsu(previous_detail, previous_text, &detail, token.text) {
+                chunks.push(chunk_start..token_ord);
+                chunk_start = token_ord;
+            }
+        }
+        previous = Some((detail, token.text));
+    }
+    if chunk_start < tokens.len() {
+        chunks.push(chunk_start..tokens.len());
+    }
+    chunks
+}
+
+fn num_chars(tokens: &[Token]) -> usize {
+    tokens.iter().map(|token| token.text.chars().count()).sum()
+}
+
+/// Splits tokens into lines of at most `max_line_len` characters, and returns the
+/// range of tokens of each line.
+///
+/// Lines are only broken between bunsetsu, except for bunsetsu longer than
+/// `max_line_len`, which are broken between tokens. Tokens longer than
+/// `max_line_len` get a line of their own.
+pub fn break_lines(
+    tokenizer: &Tokenizer,
+    tokens: &[Token],
+    max_line_len: usize,
+) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    let mut line_len = 0;
+    for chunk in bunsetsu_chunks(tokenizer, tokens) {
+        let chunk_len = num_chars(&tokens[chunk.clone()]);
+        if line_len + chunk_len <= max_line_len {
+            line_len += chunk_len;
+            continue;
+        }
+        if line_start < chunk.start {
+            lines.push(line_start..chunk.start);
+        }
+        line_start = chunk.start;
+        line_len = 0;
+        if chunk_len <= max_line_len {
+            line_len = chunk_len;
+            continue;
+        }
+        for token_ord in chunk {
+            let token_len = num_chars(&tokens[token_ord..=token_ord]);
+            if line_len + token_len > max_line_len && line_start < token_ord {
+                lines.push(line_start..token_ord);
+                line_start = token_ord;
+                line_len = 0;
+            }
+            line_len += token_len;
+        }
+    }
+    if line_start < tokens.len() {
+        lines.push(line_start..tokens.len());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{break_lines, bunsetsu_chunks};
+    use crate::{Token, Tokenizer};
+    use std::ops::Range;
+
+    fn join(tokens: &[Token], ranges: Vec<Range<usize>>) -> Vec<String> {
+        ranges
+            .into_iter()
+            .map(|range| {
+                tokens[range]
+                    .iter()
+                    .map(|token| token.text)
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bunsetsu_chunks() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let tokens = tokenizer.tokenize("私は東京に行きます。");
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["私は", "東京に", "行きます。"]
+        );
+        let tokens = tokenizer.tokenize("彼は日本語を勉強しています。");
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["彼は", "日本語を", "勉強しています。"]
+        );
+        assert!(bunsetsu_chunks(&tokenizer, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_bunsetsu_chunks_boundaries() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        // Whitespace and symbols end a bunsetsu, even between nouns.
+        let tokens = tokenizer.tokenize("東京 大阪に行く");
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["東京 ", "大阪に", "行く"]
+        );
+        let tokens = tokenizer.tokenize("東京・大阪に行く");
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["東京・", "大阪に", "行く"]
+        );
+        // Brackets are attached to the words they enclose.
+        let tokens = tokenizer.tokenize("「東京」に行く");
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["「東京」に", "行く"]
+        );
+    }
+
+    #[test]
+    fn test_bunsetsu_chunks_unknown_words() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        // Unknown words are not compounded with the nouns around them.
+        let tokens = tokenizer.tokenize("東京ｘｙｚｗに行く");
+        assert!(tokens.iter().any(|token| token.word_id.is_unknown()));
+        assert_eq!(
+            join(&tokens, bunsetsu_chunks(&tokenizer, &tokens)),
+            vec!["東京", "ｘｙｚｗに", "行く"]
+        );
+    }
+
+    #[test]
+    fn test_break_lines() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let tokens = tokenizer.tokenize("私は東京に行きます。");
+        assert_eq!(
+            join(&tokens, break_lines(&tokenizer, &tokens, 6)),
+            vec!["私は東京に", "行きます。"]
+        );
+        assert_eq!(
+            join(&tokens, break_lines(&tokenizer, &tokens, 3)),
+            vec!["私は", "東京に", "行き", "ます。"]
+        );
+        assert_eq!(
+            join(&tokens, break_lines(&tokenizer, &tokens, 100)),
+            vec!["私は東京に行きます。"]
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Char filters, rewriting the text before it is tokenized.
+//!
+//! The filtered text keeps track of the offsets of the original text, so that
+//! tokens still refer to the original text. See `Tokenizer::with_char_filter`.
+use std::ops::Range;
+
+/// Text rewritten by char filters, aligned with the original text.
+#[derive(Clone, Debug, Default)]
+pub struct FilteredText {
+    text: String,
+    // range of the original text replaced by the part of `text` holding each byte.
+    original_spans: Vec<(usize, usize)>,
+    original_len: usize,
+}
+
+impl FilteredText {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the offset in the original text where a range of the filtered text
+    /// starting at `offset` starts.
+    ///
+    /// Offsets within a replacement map to the start of the original characters
+    /// it replaces, so that a range starting within a replacement covers all of them.
+    /// The end of the filtered text maps to the end of the original text.
+    pub fn original_start(&self, offset: usize) -> usize {
+        self.original_spans
+            .get(offset)
+            .map(|&(start, _)| start)
+            .unwrap_or(self.original_len)
+    }
+
+    /// Returns the offset in the original text where a range of the filtered text
+    /// ending at `offset` ends.
+    ///
+    /// Offsets within a replacement map to the end of the original characters
+    /// it replaces, so that a range ending within a replacement covers all of them.
+    pub fn original_end(&self, offset: usize) -> usize {
+        match offset
+            .checked_sub(1)
+            .and_then(|last| self.original_spans.get(last))
+        {
+            Some(&(_, end)) => end,
+            None => self.original_start(offset),
+        }
+    }
+
+    /// Appends `text`, an unchanged part of the original text starting at `original_start`.
+    pub fn push_unchanged(&mut self, text: &str, original_start: usize) {
+        debug_assert_eq!(original_start, self.original_len);
+        self.text.push_str(text);
+        self.original_spans.extend(
+            (original_start..original_start + text.len()).map(|offset| (offset, offset + 1)),
+        );
+        self.original_len = original_start + text.len();
+    }
+
+    /// Appends `replacement`, replacing the `original` range of the original text.
+    ///
+    /// The replacement may be empty, in which case the original range is removed.
+    pub fn push_replacement(&mut self, replacement: &str, original: Range<usize>) {
+        debug_assert_eq!(original.start, self.original_len);
+        self.text.push_str(replacement);
+        self.original_spans
+            .resize(self.text.len(), (original.start, original.end));
+        self.original_len = original.end;
+    }
+
+    /// Maps the offsets of `filtered`, the result of filtering this text, to the
+    /// original text of this one.
+    fn compose(&self, mut filtered: FilteredText) -> FilteredText {
+        for original_span in &mut filtered.original_spans {
+            let start = self.original_start(original_span.0);
+            let end = self.original_end(original_span.1).max(start);
+            *original_span = (start, end);
+        }
+        filtered.original_len = self.original_len;
+        filtered
+    }
+}
+
+/// Rewrites the text before it is tokenized, e.g. to normalize it.
+pub trait CharFilter: Send + Sync {
+    /// Appends the filtered `text` to `output`, which is empty.
+    ///
+    /// The text must be pushed in order, with each part of `output` covering the
+    /// range of `text` following the previous one, up to the end of `text`.
+    fn filter(&self, text: &str, output: &mut FilteredText);
+}
+
+/// Applies the char filters in sequence. The result maps to the offsets of `text`.
+pub(crate) fn apply_char_filters<F: AsRef<dyn CharFilter>>(
+    char_filters: &[F],
+    text: &str,
+) -> FilteredText {
+    let mut filtered = FilteredText::default();
+    filtered.push_unchanged(text, 0);
+    for char_filter in char_filters {
+        let mut output = FilteredText::default();
+        char_filter.as_ref().filter(filtered.text(), &mut output);
+        filtered = filtered.compose(output);
+    }
+    filtered
+}
+
+/// Half-width katakana U+FF65 to U+FF9F, as full-width katakana.
+const HALFWIDTH_KATAKANA: [char; 59] = [
+    '・', 'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ', 'ー', 'ア', 'イ', 'ウ', 'エ',
+    'オ', 'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ', 'ソ', 'タ', 'チ', 'ツ', 'テ', 'ト',
+    'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ', 'マ', 'ミ', 'ム', 'メ', 'モ', 'ヤ',
+    'ユ', 'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン', '\u{3099}', '\u{309A}',
+];
+
+const HALFWIDTH_VOICED_MARK: char = '\u{FF9E}';
+const HALFWIDTH_SEMI_VOICED_MARK: char = '\u{FF9F}';
+
+/// Returns the voiced variant of a kana, e.g. `ガ` for `カ`.
+fn voiced_kana(c: char) -> Option<char> {
+    let voiced = match c {
+        'う' => 'ゔ',
+        'ウ' => 'ヴ',
+        'ワ' => 'ヷ',
+        'ヲ' => 'ヺ',
+        'か' | 'き' | 'く' | 'け' | 'こ' | 'さ' | 'し' | 'す' | 'せ' | 'そ' | 'た' | 'ち'
+        | 'つ' | 'て' | 'と' | 'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' | 'カ' | 'キ' | 'ク' | 'ケ'
+        | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ' | 'ツ' | 'テ' | 'ト' | 'ハ'
+        | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => std::char::from_u32(c as u32 + 1)?,
+        _ => return None,
+    };
+    Some(voiced)
+}
+
+/// Returns the unvoiced variant of a voiced kana, e.g. `カ` for `ガ`.
+fn unvoiced_kana(c: char) -> Option<char> {
+    let unvoiced = match c {
+        'ゔ' => 'う',
+        'ヴ' => 'ウ',
+        'ヷ' => 'ワ',
+        'ヺ' => 'ヲ',
+        _ => {
+            let unvoiced = std::char::from_u32((c as u32).checked_sub(1)?)?;
+            if voiced_kana(unvoiced) != Some(c) {
+                return None;
+            }
+            unvoiced
+        }
+    };
+    Some(unvoiced)
+}
+
+/// Returns the semi-voiced variant of a full-width katakana, e.g. `パ` for `ハ`.
+fn semi_voiced_katakana(c: char) -> Option<char> {
+    match c {
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => std::char::from_u32(c as u32 + 2),
+        _ => None,
+    }
+}
+
+/// Folds the width of characters, as Lucene's `CJKWidthFilter`.
+///
+/// Full-width ASCII characters are folded to their half-width form, and half-width
+/// katakana to their full-width form. A half-width katakana followed by a
+/// half-width (semi-)voiced sound mark is folded to its (semi-)voiced form,
+/// e.g. `ｶﾞ` becomes `ガ`.
+///
+/// This is not an NFKC normalization: other characters, including the other
+/// compatibility characters such as `㈱`, `①`, `㌔` or `ﬁ`, the full-width
+/// symbols `￥` or `￠`, and the ideographic space, are left unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WidthFoldingCharFilter;
+
+impl CharFilter for WidthFoldingCharFilter {
+    fn filter(&self, text: &str, output: &mut FilteredText) {
+        let mut buffer = [0u8; 4];
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let stop = start + c.len_utf8();
+            let folded = match c as u32 {
+                0xFF01..=0xFF5E => std::char::from_u32(c as u32 - 0xFF01 + 0x21),
+                0xFF65..=0xFF9F => Some(HALFWIDTH_KATAKANA[(c as u32 - 0xFF65) as usize]),
+                _ => None,
+            };
+            let folded = match folded {
+                Some(folded) => folded,
+                None => {
+                    output.push_unchanged(&text[start..stop], start);
+                    continue;
+                }
+            };
+            let combined = match chars.peek() {
+                Some(&(_, HALFWIDTH_VOICED_MARK)) => voiced_kana(folded),
+                Some(&(_, HALFWIDTH_SEMI_VOICED_MARK)) => semi_voiced_katakana(folded),
+                _ => None,
+            };
+            if let Some(combined) = combined {
+                let (mark_start, mark) = chars.next().unwrap();
+                output.push_replacement(
+                    combined.encode_utf8(&mut buffer),
+                    start..mark_start + mark.len_utf8(),
+                );
+            } else {
+                output.push_replacement(folded.encode_utf8(&mut buffer), start..stop);
+            }
+        }
+    }
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c as u32, 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF)
+}
+
+fn is_hiragana(c: char) -> bool {
+    matches!(c as u32, 0x3041..=0x3096)
+}
+
+fn is_katakana(c: char) -> bool {
+    matches!(c as u32, 0x30A1..=0x30FA)
+}
+
+fn is_iteration_mark(c: char) -> bool {
+    matches!(c, '々' | 'ゝ' | 'ゞ' | 'ヽ' | 'ヾ')
+}
+
+/// Expands iteration marks, as Lucene's `JapaneseIterationMarkCharFilter`.
+///
+/// The kanji iteration mark `々` repeats the previous kanji, e.g. `時々` becomes `時時`.
+/// The kana iteration marks `ゝ` and `ヽ` repeat the previous hiragana and katakana
+/// respectively, unvoiced, while `ゞ` and `ヾ` repeat it voiced, e.g. `こゝろ` becomes
+/// `こころ` and `いすゞ` becomes `いすず`.
+///
+/// A run of `n` iteration marks repeats the `n` previous characters, e.g. `馬鹿々々`
+/// becomes `馬鹿馬鹿`. Iteration marks that do not follow a character of their
+/// script are left unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct IterationMarkCharFilter {
+    pub normalize_kanji: bool,
+    pub normalize_kana: bool,
+}
+
+impl Default for IterationMarkCharFilter {
+    fn default() -> Self {
+        IterationMarkCharFilter {
+            normalize_kanji: true,
+            normalize_kana: true,
+        }
+    }
+}
+
+impl IterationMarkCharFilter {
+    /// Returns the expansion of `mark`, repeating `source`.
+    fn expand(&self, source: char, mark: char) -> Option<char> {
+        match mark {
+            '々' if self.normalize_kanji && is_kanji(source) => Some(source),
+            'ゝ' | 'ヽ' if self.normalize_kana => {
+                let is_source_kana = if mark == 'ゝ' {
+                    is_hiragana(source)
+                } else {
+                    is_katakana(source)
+                };
+                if is_source_kana {
+                    Some(unvoiced_kana(source).unwrap_or(source))
+                } else {
+                    None
+                }
+            }
+            'ゞ' | 'ヾ' if self.normalize_kana => {
+                let is_source_kana = if mark == 'ゞ' {
+                    is_hiragana(source)
+                } else {
+                    is_katakana(source)
+                };
+                if is_source_kana {
+                    Some(voiced_kana(source).unwrap_or(source))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl CharFilter for IterationMarkCharFilter {
+    fn filter(&self, text: &str, output: &mut FilteredText) {
+        let mut buffer = [0u8; 4];
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        // the characters of the filtered text, one per character of `text`.
+        let mut expanded: Vec<char> = Vec::with_capacity(chars.len());
+        let mut char_ord = 0;
+        while char_ord < chars.len() {
+            let run_len = chars[char_ord..]
+                .iter()
+                .take_while(|&&(_, c)| is_iteration_mark(c))
+                .count()
+                .max(1);
+            for mark_ord in 0..run_len {
+                let (start, c) = chars[char_ord + mark_ord];
+                let expansion = if is_iteration_mark(c) && run_len <= char_ord {
+                    self.expand(expanded[char_ord - run_len + mark_ord], c)
+                } else {
+                    None
+                };
+                match expansion {
+                    Some(expansion) => output.push_replacement(
+                        expansion.encode_utf8(&mut buffer),
+                        start..start + c.len_utf8(),
+                    ),
+                    None => output.push_unchanged(&text[start..start + c.len_utf8()], start),
+                }
+                expanded.push(expansion.unwrap_or(c));
+            }
+            char_ord += run_len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_char_filters, CharFilter, FilteredText, IterationMarkCharFilter,
+        WidthFoldingCharFilter,
+    };
+
+    fn filter(text: &str) -> FilteredText {
+        let char_filters: Vec<Box<dyn CharFilter>> = vec![Box::new(WidthFoldingCharFilter)];
+        apply_char_filters(&char_filters, text)
+    }
+
+    fn expand_iteration_marks(text: &str) -> String {
+        let char_filters: Vec<Box<dyn CharFilter>> =
+            vec![Box::new(IterationMarkCharFilter::default())];
+        apply_char_filters(&char_filters, text).text().to_string()
+    }
+
+    #[test]
+    fn test_iteration_mark_kanji() {
+        assert_eq!(expand_iteration_marks("時々"), "時時");
+        assert_eq!(expand_iteration_marks("馬鹿々々しい"), "馬鹿馬鹿しい");
+        assert_eq!(expand_iteration_marks("々"), "々");
+        assert_eq!(expand_iteration_marks("時々々"), "時々々");
+        assert_eq!(expand_iteration_marks("あ々"), "あ々");
+    }
+
+    #[test]
+    fn test_iteration_mark_kana() {
+        assert_eq!(expand_iteration_marks("こゝろ"), "こころ");
+        assert_eq!(expand_iteration_marks("いすゞ"), "いすず");
+        assert_eq!(expand_iteration_marks("ぶゝ"), "ぶふ");
+        assert_eq!(expand_iteration_marks("がゞ"), "がが");
+        assert_eq!(expand_iteration_marks("バナヽ"), "バナナ");
+        assert_eq!(expand_iteration_marks("ウヾ"), "ウヴ");
+        assert_eq!(expand_iteration_marks("こヽ"), "こヽ");
+    }
+
+    #[test]
+    fn test_iteration_mark_options() {
+        let char_filter = IterationMarkCharFilter {
+            normalize_kanji: false,
+            normalize_kana: true,
+        };
+        let char_filters: Vec<Box<dyn CharFilter>> = vec![Box::new(char_filter)];
+        let filtered = apply_char_filters(&char_filters, "時々こゝ");
+        assert_eq!(filtered.text(), "時々ここ");
+        assert_eq!(filtered.original_start(9), 9);
+    }
+
+    #[test]
+    fn test_width_folding() {
+        assert_eq!(filter("ＡＢＣ１２３").text(), "ABC123");
+        assert_eq!(filter("ｶﾀｶﾅ").text(), "カタカナ");
+        assert_eq!(filter("ｶﾞｯｺｳ ﾊﾟﾝ").text(), "ガッコウ パン");
+        assert_eq!(filter("ｱﾞ").text(), "ア\u{3099}");
+        assert_eq!(filter("漢字").text(), "漢字");
+        assert_eq!(filter("㈱①㌔ﬁ").text(), "㈱①㌔ﬁ");
+    }
+
+    #[test]
+    fn test_width_folding_offsets() {
+        let filtered = filter("aｶﾞb");
+        assert_eq!(filtered.text(), "aガb");
+        let original_starts: Vec<usize> = (0..=filtered.text().len())
+            .map(|offset| filtered.original_start(offset))
+            .collect();
+        assert_eq!(original_starts, vec![0, 1, 1, 1, 7, 8]);
+        let original_ends: Vec<usize> = (0..=filtered.text().len())
+            .map(|offset| filtered.original_end(offset))
+            .collect();
+        assert_eq!(original_ends, vec![0, 1, 7, 7, 7, 8]);
+    }
+
+    #[test]
+    fn test_multi_char_replacement_offsets() {
+        // ｱﾞ expands to a katakana and a combining mark. Split across two tokens,
+        // each of them covers the whole replaced span.
+        let mut filtered = FilteredText::default();
+        filtered.push_replacement("ア\u{3099}", 0..6);
+        filtered.push_unchanged("を", 6);
+        assert_eq!(filtered.text(), "ア\u{3099}を");
+        let original_range =
+            |start, end| filtered.original_start(start)..filtered.original_end(end);
+        assert_eq!(original_range(0, 3), 0..6);
+        assert_eq!(original_range(3, 6), 0..6);
+        assert_eq!(original_range(6, 9), 6..9);
+        assert_eq!(original_range(9, 9), 9..9);
+    }
+
+    #[test]
+    fn test_compose() {
+        let char_filters: Vec<Box<dyn CharFilter>> = vec![
+            Box::new(WidthFoldingCharFilter),
+            Box::new(WidthFoldingCharFilter),
+        ];
+        let filtered = apply_char_filters(&char_filters, "ＡｶﾞＢ");
+        assert_eq!(filtered.text(), "AガB");
+        assert_eq!(filtered.original_start(1), 3);
+        assert_eq!(filtered.original_start(2), 3);
+        assert_eq!(filtered.original_end(2), 9);
+        assert_eq!(filtered.original_end(4), 9);
+        assert_eq!(filtered.original_start(5), 12);
+    }
+}
@@ -1,6 +1,11 @@
+use crate::KuromojiError;
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::UTF_16LE;
+use encoding::{DecoderTrap, Encoding};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
 
-const CHAR_DEFINITION_DATA: &'static [u8] = include_bytes!("../dict/char_def.bin");
+const CHAR_DEFINITION_DATA: &[u8] = include_bytes!("../dict/char_def.bin");
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct CategoryData {
@@ -56,16 +61,48 @@ impl CharacterDefinitions {
         &self.category_names[..]
     }
 
-    pub fn load() -> CharacterDefinitions {
-        bincode::deserialize(CHAR_DEFINITION_DATA)
-            .expect("Failed to deserialize char definition data")
+    pub fn load() -> Result<CharacterDefinitions, KuromojiError> {
+        CharacterDefinitions::from_bytes(CHAR_DEFINITION_DATA)
+    }
+
+    /// Loads character definitions in the format of `char_def.bin`.
+    pub fn from_bytes(data: &[u8]) -> Result<CharacterDefinitions, KuromojiError> {
+        let char_definitions: CharacterDefinitions = bincode::deserialize(data)?;
+        char_definitions.validate()?;
+        Ok(char_definitions)
+    }
+
+    /// Checks that all of the categories of the lookup table are defined.
+    fn validate(&self) -> Result<(), KuromojiError> {
+        if self.category_names.len() != self.category_definitions.len() {
+            return Err(KuromojiError::Format(format!(
+                "{} category names for {} category definitions",
+                self.category_names.len(),
+                self.category_definitions.len()
+            )));
+        }
+        let num_categories = self.category_definitions.len();
+        for categories in &self.mapping.values {
+            if categories.is_empty() {
+                return Err(KuromojiError::Format(
+                    "Character without any category".to_string(),
+                ));
+            }
+            if let Some(category_id) = categories.iter().find(|id| id.0 >= num_categories) {
+                return Err(KuromojiError::Format(format!(
+                    "Undefined category id {}",
+                    category_id.0
+                )));
+            }
+        }
+        Ok(())
     }
     pub fn lookup_definition(&self, category_id: CategoryId) -> &CategoryData {
         &self.category_definitions[category_id.0]
     }
 
     pub fn category_name(&self, category_id: CategoryId) -> &str {
-        &self.category_names[category_id.0 as usize]
+        &self.category_names[category_id.0]
     }
 
     pub fn lookup_categories(&self, c: char) -> &[CategoryId] {
@@ -73,6 +110,169 @@ impl CharacterDefinitions {
     }
 }
 
+const DEFAULT_CATEGORY_NAME: &str = "DEFAULT";
+
+/// Builds `CharacterDefinitions` from MeCab's `char.def`.
+#[derive(Default)]
+pub struct CharacterDefinitionsBuilder {
+    category_definition: Vec<CategoryData>,
+    category_index: HashMap<String, CategoryId>,
+    char_ranges: Vec<(u32, u32, Vec<CategoryId>)>,
+}
+
+fn ucs2_to_unicode(ucs2_codepoint: u16) -> Result<u32, KuromojiError> {
+    let mut buf = [0u8; 2];
+    LittleEndian::write_u16(&mut buf[..], ucs2_codepoint);
+    let s: String = UTF_16LE
+        .decode(&buf[..], DecoderTrap::Strict)
+        .map_err(|_| KuromojiError::Encoding)?;
+    let chrs: Vec<char> = s.chars().collect();
+    if chrs.len() != 1 {
+        return Err(KuromojiError::Format(format!(
+            "Invalid code point 0x{:04X}",
+            ucs2_codepoint
+        )));
+    }
+    Ok(chrs[0] as u32)
+}
+
+fn parse_hex_codepoint(s: &str) -> Result<u32, KuromojiError> {
+    let removed_0x = s.trim_start_matches("0x");
+    let ucs2_codepoint = u16::from_str_radix(removed_0x, 16).map_err(KuromojiError::from_error)?;
+    ucs2_to_unicode(ucs2_codepoint)
+}
+
+impl CharacterDefinitionsBuilder {
+    pub fn category_id(&mut self, category_name: &str) -> CategoryId {
+        let num_categories = self.category_index.len();
+        *self
+            .category_index
+            .entry(category_name.to_string())
+            .or_insert(CategoryId(num_categories))
+    }
+
+    fn lookup_categories(&self, c: u32, categories_buffer: &mut Vec<CategoryId>) {
+        categories_buffer.clear();
+        for (start, stop, category_ids) in &self.char_ranges {
+            if *start <= c && *stop >= c {
+                for cat in category_ids {
+                    if !categories_buffer.contains(cat) {
+                        categories_buffer.push(*cat);
+                    }
+                }
+            }
+        }
+        if categories_buffer.is_empty() {
+            // `build` checks that the default category is defined.
+            if let Some(&default_category) = self.category_index.get(DEFAULT_CATEGORY_NAME) {
+                categories_buffer.push(default_category);
+            }
+        }
+    }
+
+    fn build_lookup_table(&self) -> LookupTable<CategoryId> {
+        let boundaries_set: BTreeSet<u32> =
+            self.char_ranges
+                .iter()
+                .flat_map(|(low, high, _)| vec![*low, *high + 1u32])
+                .collect();
+        let boundaries: Vec<u32> = boundaries_set.into_iter().collect();
+        LookupTable::from_fn(boundaries, &|c, buff| self.lookup_categories(c, buff))
+    }
+
+    pub fn parse(&mut self, content: &str) -> Result<(), KuromojiError> {
+        for line in content.lines() {
+            let line_str = line.split('#').next().unwrap().trim();
+            if line_str.is_empty() {
+                continue;
+            }
+            if line_str.starts_with("0x") {
+                self.parse_range(line_str)?;
+            } else {
+                self.parse_category(line_str)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_range(&mut self, line: &str) -> Result<(), KuromojiError> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let range_bounds: Vec<&str> = fields[0].split("..").collect();
+        let lower_bound: u32;
+        let higher_bound: u32;
+        match range_bounds.len() {
+            1 => {
+                lower_bound = parse_hex_codepoint(range_bounds[0])?;
+                higher_bound = lower_bound;
+            }
+            2 => {
+                lower_bound = parse_hex_codepoint(range_bounds[0])?;
+                // the right bound is included in the file.
+                higher_bound = parse_hex_codepoint(range_bounds[1])?;
+            }
+            _ => {
+                return Err(KuromojiError::Format(format!(
+                    "Invalid line: {}",
+                    line
+                )));
+            }
+        }
+        let category_ids: Vec<CategoryId> = fields[1..]
+            .iter()
+            .map(|category| self.category_id(category))
+            .collect();
+        self.char_ranges
+            .push((lower_bound, higher_bound, category_ids));
+        Ok(())
+    }
+
+    fn parse_category(&mut self, line: &str) -> Result<(), KuromojiError> {
+        let fields = line.split_ascii_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 4 {
+            return Err(KuromojiError::Format(format!(
+                "Expected 4 fields. Got {} in {}",
+                fields.len(),
+                line
+            )));
+        }
+        let invoke = fields[1].parse::<u32>().map_err(KuromojiError::from_error)? == 1;
+        let group = fields[2].parse::<u32>().map_err(KuromojiError::from_error)? == 1;
+        let length = fields[3].parse::<u32>().map_err(KuromojiError::from_error)?;
+        let category_data = CategoryData {
+            invoke,
+            group,
+            length,
+        };
+        // force a category_id allocation
+        self.category_id(fields[0]);
+        self.category_definition.push(category_data);
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<CharacterDefinitions, KuromojiError> {
+        if !self.category_index.contains_key(DEFAULT_CATEGORY_NAME) {
+            return Err(KuromojiError::Format(format!(
+                "Missing {} category",
+                DEFAULT_CATEGORY_NAME
+            )));
+        }
+        let mut category_names: Vec<String> = (0..self.category_index.len())
+            .map(|_| String::new())
+            .collect();
+        for (category_name, category_id) in &self.category_index {
+            category_names[category_id.0] = category_name.clone();
+        }
+        let mapping = self.build_lookup_table();
+        let char_definitions = CharacterDefinitions {
+            category_definitions: self.category_definition,
+            category_names,
+            mapping
+        };
+        char_definitions.validate()?;
+        Ok(char_definitions)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,7 +296,7 @@ mod tests {
 
     #[test]
     fn test_bisa() {
-        let char_definitions = CharacterDefinitions::load();
+        let char_definitions = CharacterDefinitions::load().unwrap();
         let category_ids: Vec<&str> = char_definitions.lookup_categories('々')
             .iter()
             .map(|&category_id| char_definitions.category_name(category_id))
@@ -106,7 +306,7 @@ mod tests {
 
     #[test]
     fn test_jp_hyphen() {
-        let char_definitions = CharacterDefinitions::load();
+        let char_definitions = CharacterDefinitions::load().unwrap();
         let category_ids: Vec<&str> = char_definitions
             .lookup_categories('ー')
             .iter()
@@ -117,7 +317,7 @@ mod tests {
 
     #[test]
     fn test_char_definitions() {
-        let char_definitions = CharacterDefinitions::load();
+        let char_definitions = CharacterDefinitions::load().unwrap();
         {
             let v = char_definitions.lookup_categories('あ');
             assert_eq!(v.len(), 1);
@@ -1,23 +1,368 @@
+use crate::{DictData, KuromojiError};
 use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Deref;
 
-static CONNECTION_DATA: &'static [u8] = include_bytes!("../dict/matrix.mtx");
+static CONNECTION_DATA: &[u8] = include_bytes!("../dict/matrix.mtx");
 
-pub struct ConnectionCostMatrix {
-    costs_data: &'static [u8],
+/// Name of the dictionary file holding the matrix in the compact layout, used
+/// instead of `matrix.mtx` when present.
+pub const COMPACT_MATRIX: &str = "matrix.compact";
+
+/// Matrix of the costs of connecting two consecutive words.
+///
+/// The costs are indexed by the right context id of the left word (`backward_id`)
+/// and the left context id of the right word (`forward_id`).
+#[derive(Clone)]
+pub struct ConnectionCostMatrix<Data = DictData> {
+    layout: Layout<Data>,
+}
+
+#[derive(Clone)]
+enum Layout<Data> {
+    Dense(DenseLayout<Data>),
+    Compact(CompactLayout<Data>),
+}
+
+/// Layout of `matrix.mtx`, as written by `build-fst`: one row of `forward_size`
+/// costs per `backward_id`.
+#[derive(Clone)]
+struct DenseLayout<Data> {
+    // includes the 4 bytes of the header.
+    data: Data,
+    backward_size: u32,
+    forward_size: u32,
+}
+
+impl<Data: Deref<Target = [u8]>> DenseLayout<Data> {
+    fn cost(&self, backward_id: u32, forward_id: u32) -> i32 {
+        let cost_id = (forward_id + backward_id * self.forward_size) as usize;
+        LittleEndian::read_i16(&self.data[4 + cost_id * 2..]) as i32
+    }
+}
+
+/// Length of the header of `matrix.compact`.
+const COMPACT_HEADER_LEN: usize = 16;
+
+/// Deduplicated, row-major layout of `matrix.compact`, as written by `build-fst`.
+///
+/// The Viterbi algorithm looks up the costs between one right word and all of
+/// the words ending before it. Rows are therefore indexed by `forward_id`,
+/// so that these lookups hit the same row.
+///
+/// Identical rows and identical columns are only stored once. The header holds
+/// the backward and forward sizes and the numbers of distinct columns and rows,
+/// as `u32`. It is followed by the `u16` row id of each `forward_id`, the `u16`
+/// column id of each `backward_id`, and the `i16` costs of the distinct rows.
+#[derive(Clone)]
+struct CompactLayout<Data> {
+    // includes the header.
+    data: Data,
+    backward_size: u32,
+    forward_size: u32,
+    num_columns: u32,
+    column_ids_offset: usize,
+    costs_offset: usize,
+}
+
+impl<Data: Deref<Target = [u8]>> CompactLayout<Data> {
+    fn cost(&self, backward_id: u32, forward_id: u32) -> i32 {
+        let row_id_offset = COMPACT_HEADER_LEN + 2 * forward_id as usize;
+        let row_id = LittleEndian::read_u16(&self.data[row_id_offset..]) as usize;
+        let column_id_offset = self.column_ids_offset + 2 * backward_id as usize;
+        let column_id = LittleEndian::read_u16(&self.data[column_id_offset..]) as usize;
+        let cost_id = row_id * self.num_columns as usize + column_id;
+        LittleEndian::read_i16(&self.data[self.costs_offset + 2 * cost_id..]) as i32
+    }
+}
+
+/// Builds the data of `matrix.compact` for the costs `cost(backward_id, forward_id)`.
+fn build_compact(
     backward_size: u32,
+    forward_size: u32,
+    cost: impl Fn(u32, u32) -> i16,
+) -> Result<Vec<u8>, KuromojiError> {
+    let (column_ids, columns) = dedup((0..backward_size).map(|backward_id| {
+        (0..forward_size)
+            .map(|forward_id| cost(backward_id, forward_id))
+            .collect::<Vec<i16>>()
+    }))?;
+    let (row_ids, rows) = dedup((0..forward_size).map(|forward_id| {
+        columns
+            .iter()
+            .map(|column| column[forward_id as usize])
+            .collect::<Vec<i16>>()
+    }))?;
+    let mut data = Vec::new();
+    for &size in &[
+        backward_size,
+        forward_size,
+        columns.len() as u32,
+        rows.len() as u32,
+    ] {
+        data.extend_from_slice(&size.to_le_bytes());
+    }
+    for id in row_ids.iter().chain(&column_ids) {
+        data.extend_from_slice(&id.to_le_bytes());
+    }
+    for cost in rows.iter().flatten() {
+        data.extend_from_slice(&cost.to_le_bytes());
+    }
+    Ok(data)
+}
+
+/// Assigns an id to each distinct vector.
+///
+/// Returns the id of each vector, and the distinct vectors, in the order of their ids.
+/// Fails if the ids do not fit in a `u16`.
+fn dedup(
+    vectors: impl Iterator<Item = Vec<i16>>,
+) -> Result<(Vec<u16>, Vec<Vec<i16>>), KuromojiError> {
+    let mut vector_ids: HashMap<Vec<i16>, u16> = HashMap::new();
+    let mut distinct_vectors = Vec::new();
+    let mut ids = Vec::new();
+    for vector in vectors {
+        let id = match vector_ids.get(&vector) {
+            Some(&id) => id,
+            None => {
+                let id = u16::try_from(distinct_vectors.len()).map_err(|_| {
+                    KuromojiError::Format(format!(
+                        "Connection cost matrix with more than {} distinct rows or columns",
+                        u16::MAX as usize + 1
+                    ))
+                })?;
+                distinct_vectors.push(vector.clone());
+                vector_ids.insert(vector, id);
+                id
+            }
+        };
+        ids.push(id);
+    }
+    Ok((ids, distinct_vectors))
 }
 
-impl ConnectionCostMatrix {
-    pub fn load_default() -> ConnectionCostMatrix {
-        let backward_size = LittleEndian::read_i16(&CONNECTION_DATA[..2]);
-        ConnectionCostMatrix {
-            costs_data: &CONNECTION_DATA[4..],
-            backward_size: backward_size as u32,
+impl ConnectionCostMatrix<DictData> {
+    pub fn load_default() -> Result<ConnectionCostMatrix, KuromojiError> {
+        ConnectionCostMatrix::from_data(DictData::from_static(CONNECTION_DATA))
+    }
+}
+
+impl<Data: Deref<Target = [u8]>> ConnectionCostMatrix<Data> {
+    /// Loads a matrix in the format of `matrix.mtx`: the backward and forward sizes,
+    /// as `i16`, followed by the costs.
+    pub fn from_data(data: Data) -> Result<ConnectionCostMatrix<Data>, KuromojiError> {
+        if data.len() < 4 {
+            return Err(KuromojiError::Format(
+                "Connection cost matrix without header".to_string(),
+            ));
+        }
+        let backward_size = LittleEndian::read_i16(&data[..2]);
+        let forward_size = LittleEndian::read_i16(&data[2..4]);
+        if backward_size < 0
+            || forward_size < 0
+            || data.len() < 4 + 2 * backward_size as usize * forward_size as usize
+        {
+            return Err(KuromojiError::Format(format!(
+                "Connection cost matrix of {}x{} costs truncated to {} bytes",
+                backward_size,
+                forward_size,
+                data.len()
+            )));
+        }
+        Ok(ConnectionCostMatrix {
+            layout: Layout::Dense(DenseLayout {
+                data,
+                backward_size: backward_size as u32,
+                forward_size: forward_size as u32,
+            }),
+        })
+    }
+
+    /// Loads a matrix in the compact format of `matrix.compact`, as returned by
+    /// `to_compact_data`.
+    ///
+    /// The data is not copied. All of its row and column ids are checked, so that
+    /// lookups of ids within the sizes of the matrix cannot go out of bounds.
+    pub fn from_compact_data(data: Data) -> Result<ConnectionCostMatrix<Data>, KuromojiError> {
+        if data.len() < COMPACT_HEADER_LEN {
+            return Err(KuromojiError::Format(
+                "Compact connection cost matrix without header".to_string(),
+            ));
+        }
+        let header: Vec<usize> = data[..COMPACT_HEADER_LEN]
+            .chunks(4)
+            .map(|size| LittleEndian::read_u32(size) as usize)
+            .collect();
+        let (backward_size, forward_size, num_columns, num_rows) =
+            (header[0], header[1], header[2], header[3]);
+        let column_ids_offset = COMPACT_HEADER_LEN + 2 * forward_size;
+        let costs_offset = column_ids_offset + 2 * backward_size;
+        if data.len() != costs_offset + 2 * num_rows * num_columns {
+            return Err(KuromojiError::Format(format!(
+                "Compact connection cost matrix of {}x{} costs with {} bytes",
+                backward_size,
+                forward_size,
+                data.len()
+            )));
+        }
+        let ids = |offset: usize, len: usize| {
+            data[offset..offset + 2 * len]
+                .chunks(2)
+                .map(|id| LittleEndian::read_u16(id) as usize)
+        };
+        if ids(COMPACT_HEADER_LEN, forward_size).any(|row_id| row_id >= num_rows)
+            || ids(column_ids_offset, backward_size).any(|column_id| column_id >= num_columns)
+        {
+            return Err(KuromojiError::Format(
+                "Compact connection cost matrix with an invalid row or column id".to_string(),
+            ));
+        }
+        Ok(ConnectionCostMatrix {
+            layout: Layout::Compact(CompactLayout {
+                data,
+                backward_size: backward_size as u32,
+                forward_size: forward_size as u32,
+                num_columns: num_columns as u32,
+                column_ids_offset,
+                costs_offset,
+            }),
+        })
+    }
+
+    /// Returns the data of the same matrix in the compact format of `matrix.compact`.
+    ///
+    /// Identical rows and columns are only stored once, so that the matrix gets
+    /// smaller: only by about 5% for IPADIC, but much more for dictionaries with many
+    /// redundant context ids. In exchange, each lookup first reads a row id and
+    /// a column id, instead of computing the offset of the cost directly, so lookups
+    /// are only faster if the dense matrix does not fit in the CPU cache while
+    /// the compact one does.
+    ///
+    /// Fails if the matrix has more than 65536 distinct rows or columns.
+    pub fn to_compact_data(&self) -> Result<Vec<u8>, KuromojiError> {
+        match &self.layout {
+            Layout::Dense(dense) => build_compact(
+                dense.backward_size,
+                dense.forward_size,
+                |backward_id, forward_id| dense.cost(backward_id, forward_id) as i16,
+            ),
+            Layout::Compact(compact) => Ok(compact.data.to_vec()),
+        }
+    }
+
+    /// Returns the same matrix, using the compact layout.
+    ///
+    /// See `to_compact_data`. Prefer building the compact matrix ahead of time
+    /// with `build-fst --compact-matrix`, which saves building it at every load.
+    pub fn to_compact(&self) -> Result<ConnectionCostMatrix<Vec<u8>>, KuromojiError> {
+        ConnectionCostMatrix::from_compact_data(self.to_compact_data()?)
+    }
+
+    /// Number of right context ids of the left words.
+    pub fn backward_size(&self) -> u32 {
+        match &self.layout {
+            Layout::Dense(dense) => dense.backward_size,
+            Layout::Compact(compact) => compact.backward_size,
+        }
+    }
+
+    /// Number of left context ids of the right words.
+    pub fn forward_size(&self) -> u32 {
+        match &self.layout {
+            Layout::Dense(dense) => dense.forward_size,
+            Layout::Compact(compact) => compact.forward_size,
         }
     }
 
+    /// Size of the matrix data in memory, in bytes.
+    pub fn num_bytes(&self) -> usize {
+        match &self.layout {
+            Layout::Dense(dense) => dense.data.len() - 4,
+            Layout::Compact(compact) => compact.data.len() - COMPACT_HEADER_LEN,
+        }
+    }
+
+    #[inline]
     pub fn cost(&self, backward_id: u32, forward_id: u32) -> i32 {
-        let cost_id = (forward_id + backward_id * self.backward_size) as usize;
-        LittleEndian::read_i16(&self.costs_data[cost_id * 2..]) as i32
+        match &self.layout {
+            Layout::Dense(dense) => dense.cost(backward_id, forward_id),
+            Layout::Compact(compact) => compact.cost(backward_id, forward_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup, ConnectionCostMatrix};
+
+    #[test]
+    fn test_truncated_matrix() {
+        assert!(ConnectionCostMatrix::from_data(&[2, 0][..]).is_err());
+        assert!(ConnectionCostMatrix::from_data(&[2, 0, 2, 0, 0, 0][..]).is_err());
+        let matrix = ConnectionCostMatrix::from_data(&[1, 0, 1, 0, 7, 0][..]).unwrap();
+        assert_eq!(matrix.cost(0, 0), 7);
+    }
+
+    #[test]
+    fn test_compact_same_costs() {
+        let dense = ConnectionCostMatrix::load_default().unwrap();
+        let compact = dense.to_compact().unwrap();
+        assert_eq!(compact.backward_size(), dense.backward_size());
+        assert_eq!(compact.forward_size(), dense.forward_size());
+        for backward_id in 0..dense.backward_size() {
+            for forward_id in 0..dense.forward_size() {
+                assert_eq!(
+                    dense.cost(backward_id, forward_id),
+                    compact.cost(backward_id, forward_id)
+                );
+            }
+        }
+        assert!(compact.num_bytes() <= dense.num_bytes());
+        let reloaded = ConnectionCostMatrix::from_compact_data(compact.to_compact_data().unwrap());
+        assert_eq!(reloaded.unwrap().cost(12, 34), dense.cost(12, 34));
+    }
+
+    #[test]
+    fn test_invalid_compact_matrix() {
+        assert!(ConnectionCostMatrix::from_compact_data(&[0u8; 8][..]).is_err());
+        // 1x1 matrix with a single distinct row and column.
+        let mut data = Vec::new();
+        for size in [1u32, 1, 1, 1].iter() {
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0, 0, 0, 7, 0]);
+        let matrix = ConnectionCostMatrix::from_compact_data(&data[..]).unwrap();
+        assert_eq!(matrix.cost(0, 0), 7);
+        assert!(ConnectionCostMatrix::from_compact_data(&data[..data.len() - 1]).is_err());
+        // row id 1 of a matrix with a single row.
+        data[16] = 1;
+        assert!(ConnectionCostMatrix::from_compact_data(&data[..]).is_err());
+    }
+
+    #[test]
+    fn test_non_square_matrix() {
+        // 2 backward ids and 3 forward ids, cost = 10 * backward_id + forward_id.
+        let mut data = vec![2, 0, 3, 0];
+        for cost in [0i16, 1, 2, 10, 11, 12].iter() {
+            data.extend_from_slice(&cost.to_le_bytes());
+        }
+        let dense = ConnectionCostMatrix::from_data(&data[..]).unwrap();
+        let compact = dense.to_compact().unwrap();
+        assert_eq!((compact.backward_size(), compact.forward_size()), (2, 3));
+        for backward_id in 0..2 {
+            for forward_id in 0..3 {
+                let cost = 10 * backward_id as i32 + forward_id as i32;
+                assert_eq!(dense.cost(backward_id, forward_id), cost);
+                assert_eq!(compact.cost(backward_id, forward_id), cost);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dedup_too_many_vectors() {
+        let num_vectors = u16::MAX as i32 + 1;
+        assert!(dedup((0..num_vectors).map(|i| vec![i as i16])).is_ok());
+        assert!(dedup((0..num_vectors + 1).map(|i| vec![i as i16, (i >> 16) as i16])).is_err());
     }
 }
@@ -0,0 +1,332 @@
+//! Single-file dictionary container.
+//!
+//! A container bundles all of the files of a dictionary (`dict.fst`, `dict.vals`,
+//! `matrix.mtx`, ...) as named sections, together with metadata describing how
+//! it was built.
+//!
+//! Layout, all integers being little-endian:
+//!
+//! - `MAGIC` (8 bytes),
+//! - format version (`u32`),
+//! - header length (`u32`),
+//! - CRC-32 of the header (`u32`),
+//! - header: the bincode-serialized metadata and section table,
+//! - section data. Each section starts at an offset aligned on 8 bytes.
+//!
+//! The section table holds the offset, length and CRC-32 of each section.
+use crate::KuromojiError;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::ops::Range;
+
+/// Magic number at the start of every container.
+pub const MAGIC: [u8; 8] = *b"KRMJDICT";
+
+/// Version of the container format written by this version of the library.
+pub const FORMAT_VERSION: u32 = 1;
+
+const PREAMBLE_LEN: usize = MAGIC.len() + 12;
+const SECTION_ALIGNMENT: usize = 8;
+
+/// Description of the dictionary stored in a container.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Name of the source dictionary, e.g. `mecab-ipadic-2.7.0`.
+    pub source_dictionary: String,
+    /// Encoding of the source files.
+    pub encoding: String,
+    /// Build date, in seconds since the UNIX epoch.
+    pub build_date: u64,
+    /// Names of the fields of the word details.
+    pub feature_schema: Vec<String>,
+}
+
+/// Entry of the section table.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    pub name: String,
+    /// Offset of the section data from the start of the container, in bytes.
+    pub offset: u64,
+    pub len: u64,
+    /// CRC-32 of the section data.
+    pub checksum: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    metadata: Metadata,
+    sections: Vec<Section>,
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-32 (IEEE) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc as u8) ^ byte) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Builds a container.
+pub struct ContainerWriter {
+    metadata: Metadata,
+    sections: Vec<(String, Vec<u8>)>,
+}
+
+impl ContainerWriter {
+    pub fn new(metadata: Metadata) -> ContainerWriter {
+        ContainerWriter {
+            metadata,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn add_section(&mut self, name: &str, data: Vec<u8>) {
+        self.sections.push((name.to_string(), data));
+    }
+
+    fn header(&self) -> Result<Vec<u8>, KuromojiError> {
+        // The header length depends on the section offsets, which depend on the
+        // header length. Offsets are serialized as fixed-size integers, so
+        // computing the header with dummy offsets gives its final length.
+        let sections = self
+            .sections
+            .iter()
+            .map(|(name, data)| Section {
+                name: name.clone(),
+                offset: 0,
+                len: data.len() as u64,
+                checksum: crc32(data),
+            })
+            .collect();
+        let mut header = Header {
+            metadata: self.metadata.clone(),
+            sections,
+        };
+        let header_len = bincode::serialized_size(&header)? as usize;
+        let mut offset = PREAMBLE_LEN + header_len;
+        for section in &mut header.sections {
+            offset = align(offset);
+            section.offset = offset as u64;
+            offset += section.len as usize;
+        }
+        Ok(bincode::serialize(&header)?)
+    }
+
+    pub fn write<W: Write>(&self, wtr: &mut W) -> Result<(), KuromojiError> {
+        let header = self.header()?;
+        wtr.write_all(&MAGIC)?;
+        wtr.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+        wtr.write_u32::<LittleEndian>(header.len() as u32)?;
+        wtr.write_u32::<LittleEndian>(crc32(&header))?;
+        wtr.write_all(&header)?;
+        let mut offset = PREAMBLE_LEN + header.len();
+        for (_, data) in &self.sections {
+            let padding = align(offset) - offset;
+            wtr.write_all(&[0u8; SECTION_ALIGNMENT][..padding])?;
+            wtr.write_all(data)?;
+            offset += padding + data.len();
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(SECTION_ALIGNMENT) * SECTION_ALIGNMENT
+}
+
+/// Container read from a slice of bytes.
+pub struct Container<'a> {
+    metadata: Metadata,
+    sections: Vec<Section>,
+    data: &'a [u8],
+}
+
+impl<'a> Container<'a> {
+    /// Parses the header of a container, and checks that all of its sections
+    /// are within `data`.
+    ///
+    /// The checksums of the sections are only verified when they are accessed,
+    /// see `section`.
+    pub fn parse(data: &'a [u8]) -> Result<Container<'a>, KuromojiError> {
+        if data.len() < PREAMBLE_LEN || data[..MAGIC.len()] != MAGIC {
+            return Err(KuromojiError::Format(
+                "Not a dictionary container".to_string(),
+            ));
+        }
+        let version = LittleEndian::read_u32(&data[8..12]);
+        if version != FORMAT_VERSION {
+            return Err(KuromojiError::VersionMismatch {
+                expected: FORMAT_VERSION,
+                found: version,
+            });
+        }
+        let header_len = LittleEndian::read_u32(&data[12..16]) as usize;
+        let header_checksum = LittleEndian::read_u32(&data[16..20]);
+        let header_data = data
+            .get(PREAMBLE_LEN..PREAMBLE_LEN + header_len)
+            .ok_or_else(|| KuromojiError::Format("Truncated container header".to_string()))?;
+        if crc32(header_data) != header_checksum {
+            return Err(KuromojiError::Format(
+                "Corrupt container header".to_string(),
+            ));
+        }
+        let header: Header = bincode::deserialize(header_data)?;
+        for section in &header.sections {
+            let in_bounds = section
+                .offset
+                .checked_add(section.len)
+                .map(|end| end <= data.len() as u64)
+                .unwrap_or(false);
+            if !in_bounds {
+                return Err(KuromojiError::Format(format!(
+                    "Section {:?} is truncated",
+                    section.name
+                )));
+            }
+        }
+        Ok(Container {
+            metadata: header.metadata,
+            sections: header.sections,
+            data,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections[..]
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sections.iter().any(|section| section.name == name)
+    }
+
+    fn find_section(&self, name: &str) -> Result<&Section, KuromojiError> {
+        self.sections
+            .iter()
+            .find(|section| section.name == name)
+            .ok_or_else(|| KuromojiError::Format(format!("Missing section {:?}", name)))
+    }
+
+    /// Returns the byte range of the section named `name` within the container,
+    /// without verifying its checksum.
+    pub fn section_range(&self, name: &str) -> Result<Range<usize>, KuromojiError> {
+        let section = self.find_section(name)?;
+        let start = section.offset as usize;
+        Ok(start..start + section.len as usize)
+    }
+
+    /// Returns the data of the section named `name`, after verifying its checksum.
+    pub fn section(&self, name: &str) -> Result<&'a [u8], KuromojiError> {
+        let section = self.find_section(name)?;
+        let data = &self.data[self.section_range(name)?];
+        if crc32(data) != section.checksum {
+            return Err(KuromojiError::Format(format!(
+                "Checksum mismatch in section {:?}",
+                name
+            )));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Container, ContainerWriter, Metadata, FORMAT_VERSION};
+    use crate::KuromojiError;
+
+    fn container_data() -> Vec<u8> {
+        let metadata = Metadata {
+            source_dictionary: "test".to_string(),
+            encoding: "UTF-8".to_string(),
+            build_date: 1_600_000_000,
+            feature_schema: vec!["pos".to_string(), "reading".to_string()],
+        };
+        let mut writer = ContainerWriter::new(metadata);
+        writer.add_section("a", b"abc".to_vec());
+        writer.add_section("empty", Vec::new());
+        writer.add_section("b", b"0123456789".to_vec());
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_container() {
+        let data = container_data();
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.metadata().source_dictionary, "test");
+        assert_eq!(container.metadata().feature_schema.len(), 2);
+        assert_eq!(container.section("a").unwrap(), b"abc");
+        assert_eq!(container.section("empty").unwrap(), b"");
+        assert_eq!(container.section("b").unwrap(), b"0123456789");
+        assert!(container.contains("b"));
+        assert!(!container.contains("c"));
+        assert!(container.section("c").is_err());
+        assert!(container
+            .sections()
+            .iter()
+            .all(|section| section.offset % 8 == 0));
+    }
+
+    #[test]
+    fn test_invalid_container() {
+        let data = container_data();
+        assert!(Container::parse(&data[..10]).is_err());
+        assert!(Container::parse(b"not a container at all").is_err());
+
+        let mut newer_version = data.clone();
+        newer_version[8] += 1;
+        match Container::parse(&newer_version) {
+            Err(KuromojiError::VersionMismatch { expected, found }) => {
+                assert_eq!(expected, FORMAT_VERSION);
+                assert_eq!(found, FORMAT_VERSION + 1);
+            }
+            _ => panic!("expected a version mismatch"),
+        }
+
+        let mut corrupt_header = data.clone();
+        corrupt_header[25] ^= 1;
+        assert!(Container::parse(&corrupt_header).is_err());
+
+        let mut corrupt_section = data.clone();
+        let last = corrupt_section.len() - 1;
+        corrupt_section[last] ^= 1;
+        let container = Container::parse(&corrupt_section).unwrap();
+        assert!(container.section("a").is_ok());
+        assert!(container.section("b").is_err());
+
+        assert!(Container::parse(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Parsing of segmented (gold) corpora.
+use crate::KuromojiError;
+
+/// Token of an annotated corpus.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GoldToken {
+    pub surface: String,
+    /// Features of the token, in the same format as the dictionary:
+    /// part-of-speech, conjugation, base form, reading...
+    pub features: Vec<String>,
+}
+
+/// Sentence of an annotated corpus.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Sentence {
+    pub tokens: Vec<GoldToken>,
+}
+
+impl Sentence {
+    /// Returns the unsegmented text of the sentence.
+    pub fn text(&self) -> String {
+        self.tokens
+            .iter()
+            .map(|token| token.surface.as_str())
+            .collect()
+    }
+}
+
+/// Parses a corpus in MeCab's output format.
+///
+/// Each token is on its own line, as its surface form, a tab, and its comma-separated
+/// features. Sentences are terminated by a line containing `EOS`.
+pub fn parse_mecab_corpus(content: &str) -> Result<Vec<Sentence>, KuromojiError> {
+    let mut sentences = Vec::new();
+    let mut sentence = Sentence::default();
+    for (line_ord, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        if line == "EOS" {
+            sentences.push(std::mem::take(&mut sentence));
+            continue;
+        }
+        let mut fields = line.splitn(2, '\t');
+        let surface = fields.next().unwrap_or("");
+        let features = fields.next().ok_or_else(|| {
+            KuromojiError::Format(format!(
+                "Line {}: expected `surface<TAB>features`, got {:?}",
+                line_ord + 1,
+                line
+            ))
+        })?;
+        if surface.is_empty() {
+            return Err(KuromojiError::Format(format!(
+                "Line {}: empty surface form",
+                line_ord + 1
+            )));
+        }
+        sentence.tokens.push(GoldToken {
+            surface: surface.to_string(),
+            features: features.split(',').map(str::to_string).collect(),
+        });
+    }
+    if !sentence.tokens.is_empty() {
+        sentences.push(sentence);
+    }
+    Ok(sentences)
+}
+
+/// Parses a segmented corpus in the wakati format: one sentence per line,
+/// with tokens separated by whitespace.
+///
+/// Tokens have no features.
+pub fn parse_wakati_corpus(content: &str) -> Vec<Sentence> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Sentence {
+            tokens: line
+                .split_whitespace()
+                .map(|surface| GoldToken {
+                    surface: surface.to_string(),
+                    features: Vec::new(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_mecab_corpus, parse_wakati_corpus};
+
+    #[test]
+    fn test_parse_mecab_corpus() {
+        let corpus = "僕\t名詞,代名詞,一般,*,*,*,僕,ボク,ボク\n\
+                      は\t助詞,係助詞,*,*,*,*,は,ハ,ワ\n\
+                      EOS\n\
+                      \n\
+                      ここ\t名詞,代名詞,一般,*,*,*,ここ,ココ,ココ\n\
+                      EOS\n";
+        let sentences = parse_mecab_corpus(corpus).unwrap();
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(sentences[0].text(), "僕は");
+        assert_eq!(sentences[0].tokens[1].features[0], "助詞");
+        assert_eq!(sentences[1].tokens[0].features.len(), 9);
+    }
+
+    #[test]
+    fn test_parse_mecab_corpus_missing_features() {
+        assert!(parse_mecab_corpus("僕\nEOS\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_wakati_corpus() {
+        let sentences = parse_wakati_corpus("僕 は\n\nここ に いる\n");
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(sentences[1].text(), "ここにいる");
+        assert_eq!(sentences[1].tokens.len(), 3);
+        assert!(sentences[1].tokens[0].features.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+//! Adjustment of the cost of dictionary words at tokenization time.
+use crate::{Dictionary, WordEntry, WordId};
+use std::collections::HashMap;
+
+/// Cost adjustments applied to the words of the dictionary, and of the user
+/// dictionary, when they are added to the lattice.
+///
+/// Lower costs make words more likely to be part of the segmentation.
+///
+/// See `Tokenizer::with_cost_adjustments`.
+#[derive(Clone, Debug, Default)]
+pub struct CostAdjustments {
+    pos_deltas: Vec<(Vec<String>, i32)>,
+    word_costs: HashMap<WordId, i16>,
+}
+
+impl CostAdjustments {
+    /// Adds `delta` to the cost of the words whose part-of-speech starts with
+    /// the levels of `pos`. `*` matches any level.
+    ///
+    /// Deltas of several matching parts-of-speech are summed.
+    pub fn add_pos_cost(&mut self, pos: &[&str], delta: i32) {
+        self.pos_deltas
+            .push((pos.iter().map(|level| level.to_string()).collect(), delta));
+    }
+
+    /// Sets the cost of a word, overriding both its dictionary cost and the
+    /// deltas of its part-of-speech.
+    pub fn set_word_cost(&mut self, word_id: WordId, cost: i16) {
+        self.word_costs.insert(word_id, cost);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos_deltas.is_empty() && self.word_costs.is_empty()
+    }
+
+    /// Resolves the parts-of-speech to the context ids of `dictionary`.
+    ///
+    /// In IPADIC, the context id of a word determines its part-of-speech, which
+    /// makes it possible to adjust costs without reading the details of the words.
+    pub(crate) fn resolve(&self, dictionary: &Dictionary) -> ResolvedCostAdjustments {
+        let mut context_deltas: Vec<i32> = Vec::new();
+        for (pos, delta) in &self.pos_deltas {
+            let pos: Vec<&str> = pos.iter().map(String::as_str).collect();
+            for context_id in dictionary.context_ids(&pos) {
+                let context_id = context_id as usize;
+                if context_deltas.len() <= context_id {
+                    context_deltas.resize(context_id + 1, 0);
+                }
+                context_deltas[context_id] += delta;
+            }
+        }
+        ResolvedCostAdjustments {
+            context_deltas,
+            word_costs: self.word_costs.clone(),
+        }
+    }
+}
+
+/// `CostAdjustments` resolved for a given dictionary.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ResolvedCostAdjustments {
+    // cost delta, indexed by context id.
+    context_deltas: Vec<i32>,
+    word_costs: HashMap<WordId, i16>,
+}
+
+impl ResolvedCostAdjustments {
+    pub fn adjust(&self, word_entry: &mut WordEntry) {
+        if let Some(&word_cost) = self.word_costs.get(&word_entry.word_id) {
+            word_entry.word_cost = word_cost;
+            return;
+        }
+        if let Some(&delta) = self.context_deltas.get(word_entry.cost_id as usize) {
+            let word_cost = word_entry.word_cost as i32 + delta;
+            word_entry.word_cost = word_cost.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostAdjustments;
+    use crate::{Dictionary, WordEntry, WordId};
+
+    #[test]
+    fn test_resolve_cost_adjustments() {
+        let dictionary = Dictionary::load_default().unwrap();
+        let mut cost_adjustments = CostAdjustments::default();
+        assert!(cost_adjustments.is_empty());
+        cost_adjustments.add_pos_cost(&["名詞", "固有名詞"], 1000);
+        cost_adjustments.add_pos_cost(&["名詞", "*", "地域"], 30000);
+        cost_adjustments.set_word_cost(WordId(1), -5);
+        let resolved = cost_adjustments.resolve(&dictionary);
+
+        let proper_noun = dictionary
+            .context_id(&["名詞", "固有名詞", "人名"])
+            .unwrap();
+        let mut word_entry = WordEntry {
+            word_id: WordId(2),
+            word_cost: 100,
+            cost_id: proper_noun,
+        };
+        resolved.adjust(&mut word_entry);
+        assert_eq!(word_entry.word_cost, 1100);
+
+        let region = dictionary
+            .context_id(&["名詞", "固有名詞", "地域"])
+            .unwrap();
+        let mut word_entry = WordEntry {
+            word_id: WordId(2),
+            word_cost: 3000,
+            cost_id: region,
+        };
+        resolved.adjust(&mut word_entry);
+        assert_eq!(word_entry.word_cost, i16::MAX);
+
+        let common_noun = dictionary.context_id(&["名詞", "一般"]).unwrap();
+        let mut word_entry = WordEntry {
+            word_id: WordId(1),
+            word_cost: 100,
+            cost_id: common_noun,
+        };
+        resolved.adjust(&mut word_entry);
+        assert_eq!(word_entry.word_cost, -5);
+        word_entry.word_id = WordId(2);
+        resolved.adjust(&mut word_entry);
+        assert_eq!(word_entry.word_cost, -5);
+    }
+
+    #[test]
+    fn test_tokenizer_with_cost_adjustments() {
+        let mut tokenizer = crate::Tokenizer::normal().unwrap();
+        assert_eq!(
+            tokenizer.tokenize_str("東京都に行く"),
+            vec!["東京", "都", "に", "行く"]
+        );
+        let tokyo_word_id = tokenizer.tokenize("東京都に行く")[0].word_id;
+        let mut cost_adjustments = CostAdjustments::default();
+        cost_adjustments.set_word_cost(tokyo_word_id, i16::MAX);
+        let mut adjusted_tokenizer = tokenizer.clone().with_cost_adjustments(cost_adjustments);
+        let tokens = adjusted_tokenizer.tokenize("東京都に行く");
+        assert!(tokens.iter().all(|token| token.word_id != tokyo_word_id));
+
+        let mut cost_adjustments = CostAdjustments::default();
+        cost_adjustments.add_pos_cost(&["名詞", "固有名詞"], 20000);
+        let mut adjusted_tokenizer = tokenizer.with_cost_adjustments(cost_adjustments);
+        assert_eq!(
+            adjusted_tokenizer.tokenize_str("田中さん"),
+            vec!["田", "中", "さん"]
+        );
+    }
+}
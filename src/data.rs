@@ -0,0 +1,112 @@
+use crate::KuromojiError;
+use std::ops::{Deref, Range};
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Clone)]
+enum Source {
+    Static(&'static [u8]),
+    Owned(Arc<Vec<u8>>),
+    #[cfg(feature = "mmap")]
+    Mmap(Arc<memmap2::Mmap>),
+}
+
+/// Bytes of dictionary data: embedded in the library, loaded in memory,
+/// or memory-mapped from a file.
+///
+/// Cloning and slicing do not copy the bytes, so that all of the components of
+/// a dictionary can share the data of a single container.
+#[derive(Clone)]
+pub struct DictData {
+    source: Source,
+    range: Range<usize>,
+}
+
+impl DictData {
+    pub fn from_static(data: &'static [u8]) -> DictData {
+        DictData {
+            source: Source::Static(data),
+            range: 0..data.len(),
+        }
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> DictData {
+        DictData {
+            range: 0..data.len(),
+            source: Source::Owned(Arc::new(data)),
+        }
+    }
+
+    /// Memory-maps the file at `path`.
+    ///
+    /// The file must not be modified while it is mapped.
+    #[cfg(feature = "mmap")]
+    pub fn mmap<P: AsRef<Path>>(path: P) -> Result<DictData, KuromojiError> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the mapping is read-only. Like all mmap-based readers, we rely
+        // on the file not being truncated or modified while it is mapped.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(DictData {
+            range: 0..mmap.len(),
+            source: Source::Mmap(Arc::new(mmap)),
+        })
+    }
+
+    /// Returns the bytes in `range`, relative to the start of this data.
+    pub fn slice(&self, range: Range<usize>) -> Result<DictData, KuromojiError> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(KuromojiError::Format(format!(
+                "Range {:?} out of data of {} bytes",
+                range,
+                self.len()
+            )));
+        }
+        Ok(DictData {
+            source: self.source.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        })
+    }
+}
+
+impl Deref for DictData {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        let data: &[u8] = match &self.source {
+            Source::Static(data) => data,
+            Source::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            Source::Mmap(mmap) => mmap,
+        };
+        &data[self.range.clone()]
+    }
+}
+
+impl From<Vec<u8>> for DictData {
+    fn from(data: Vec<u8>) -> DictData {
+        DictData::from_vec(data)
+    }
+}
+
+impl From<&'static [u8]> for DictData {
+    fn from(data: &'static [u8]) -> DictData {
+        DictData::from_static(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DictData;
+
+    #[test]
+    fn test_slice() {
+        let data = DictData::from_vec(b"0123456789".to_vec());
+        let slice = data.slice(2..8).unwrap();
+        assert_eq!(&slice[..], b"234567");
+        assert_eq!(&slice.slice(1..3).unwrap()[..], b"34");
+        assert!(slice.slice(5..7).is_err());
+        assert_eq!(&DictData::from_static(b"abc").slice(3..3).unwrap()[..], b"");
+    }
+}
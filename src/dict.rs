@@ -4,7 +4,7 @@ use std::u32;
 use std::str::FromStr;
 use fst;
 
-const DICTIONARY_DATA: &'static [u8] = include_bytes!("../dict/dict.fst");
+const DICTIONARY_DATA: &[u8] = include_bytes!("../dict/dict.fst");
 
 pub struct Dict {
     pub fst: fst::Map,
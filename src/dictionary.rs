@@ -0,0 +1,759 @@
+use crate::connection::{ConnectionCostMatrix, COMPACT_MATRIX};
+use crate::container::{Container, Metadata};
+use crate::prefix_dict::PrefixDict;
+use crate::prefix_index::{KeyAutomaton, PrefixIndexType};
+use crate::reading_index::{
+    build_reading_index, ReadingIndex, READING_INDEX_FST, READING_INDEX_VALS,
+};
+use crate::unknown_dictionary::UnknownDictionary;
+use crate::word_entry::WordDictionary;
+use crate::{CharacterDefinitions, DictData, KuromojiError, WordDetail, WordEntry, WordId};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Read-only data required to tokenize a text.
+///
+/// A `Dictionary` is not modified by tokenization, and can be shared
+/// between several `Tokenizer`s, possibly living in different threads.
+pub struct Dictionary {
+    pub(crate) dict: PrefixDict,
+    pub(crate) cost_matrix: ConnectionCostMatrix,
+    pub(crate) char_definitions: CharacterDefinitions,
+    pub(crate) unknown_dictionary: UnknownDictionary,
+    pub(crate) words: WordDictionary,
+    reading_index: Option<ReadingIndex>,
+    metadata: Option<Metadata>,
+    // computed on first use, see `context_id`.
+    pos_context_ids: OnceLock<Vec<PosContextId>>,
+}
+
+/// A word of a dictionary, with its features.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DictionaryEntry<'a> {
+    pub surface: String,
+    pub word_entry: WordEntry,
+    pub detail: WordDetail<'a>,
+}
+
+/// Number of words of a part-of-speech using a given context id.
+struct PosContextId {
+    pos: [String; 4],
+    context_id: u16,
+    num_words: usize,
+}
+
+impl Dictionary {
+    fn new(
+        dict: PrefixDict,
+        cost_matrix: ConnectionCostMatrix,
+        char_definitions: CharacterDefinitions,
+        unknown_dictionary: UnknownDictionary,
+        words: WordDictionary,
+    ) -> Result<Dictionary, KuromojiError> {
+        if unknown_dictionary.category_references.len() < char_definitions.categories().len() {
+            return Err(KuromojiError::Format(format!(
+                "The unknown dictionary has {} categories, expected {}",
+                unknown_dictionary.category_references.len(),
+                char_definitions.categories().len()
+            )));
+        }
+        // the connection costs are looked up without bounds checks, so the
+        // context ids of all of the entries are checked once here. BOS and EOS
+        // use the context id 0.
+        let mut max_context_id = 0;
+        dict.for_each_entry(|word_entry| max_context_id = max_context_id.max(word_entry.cost_id));
+        for word_entry in &unknown_dictionary.costs {
+            max_context_id = max_context_id.max(word_entry.cost_id);
+        }
+        let num_context_ids = cost_matrix.backward_size().min(cost_matrix.forward_size());
+        if u32::from(max_context_id) >= num_context_ids {
+            return Err(KuromojiError::Format(format!(
+                "Context id {} out of the {}x{} connection cost matrix",
+                max_context_id,
+                cost_matrix.backward_size(),
+                cost_matrix.forward_size()
+            )));
+        }
+        Ok(Dictionary {
+            dict,
+            cost_matrix,
+            char_definitions,
+            unknown_dictionary,
+            words,
+            reading_index: None,
+            metadata: None,
+            pos_context_ids: OnceLock::new(),
+        })
+    }
+
+    /// Loads the IPADIC dictionary embedded in the library.
+    pub fn load_default() -> Result<Dictionary, KuromojiError> {
+        Dictionary::load_default_with_index(PrefixIndexType::Fst)
+    }
+
+    /// Loads the IPADIC dictionary embedded in the library, using the given
+    /// type of prefix index.
+    ///
+    /// The double-array trie is only embedded with the `double-array` feature.
+    /// Otherwise, it is built from the FST, which takes a few seconds.
+    pub fn load_default_with_index(
+        prefix_index_type: PrefixIndexType,
+    ) -> Result<Dictionary, KuromojiError> {
+        let dict = match prefix_index_type {
+            PrefixIndexType::Fst => PrefixDict::load_default()?,
+            PrefixIndexType::DoubleArray => PrefixDict::load_default_double_array()?,
+        };
+        Dictionary::new(
+            dict,
+            ConnectionCostMatrix::load_default()?,
+            CharacterDefinitions::load()?,
+            UnknownDictionary::load()?,
+            WordDictionary::load_default(),
+        )
+    }
+
+    /// Loads a dictionary from a container file, as written by `build-fst`.
+    ///
+    /// See `from_container`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Dictionary, KuromojiError> {
+        let data = fs::read(path)?;
+        Dictionary::from_container(DictData::from_vec(data))
+    }
+
+    /// Memory-maps a container file, as written by `build-fst`.
+    ///
+    /// The dictionary data is not copied, and its pages are shared with the other
+    /// processes mapping the same file. Unlike `open`, only the checksum of the
+    /// container header is verified, so that most sections are only read on demand.
+    /// The word entries are still read once, to check their context ids.
+    #[cfg(feature = "mmap")]
+    pub fn mmap<P: AsRef<Path>>(path: P) -> Result<Dictionary, KuromojiError> {
+        Dictionary::load_container(DictData::mmap(path)?, false)
+    }
+
+    /// Loads a dictionary from the data of a container.
+    ///
+    /// The container holds one section per dictionary file: either `dict.fst` or
+    /// `dict.da`, and `dict.vals`, `dict.words`, `dict.wordsidx`, either `matrix.mtx`
+    /// or `matrix.compact`, `char_def.bin` and `unk.bin`. It may also hold a reading index, in the
+    /// `dict.readings.fst` and `dict.readings.vals` sections. The checksums of all of the sections are
+    /// verified, and the feature schema must be the one of `WordDetail`.
+    pub fn from_container(data: DictData) -> Result<Dictionary, KuromojiError> {
+        Dictionary::load_container(data, true)
+    }
+
+    fn load_container(data: DictData, verify_checksums: bool) -> Result<Dictionary, KuromojiError> {
+        let container = Container::parse(&data)?;
+        let feature_schema = &container.metadata().feature_schema;
+        if !feature_schema.iter().eq(WordDetail::FEATURE_SCHEMA.iter()) {
+            return Err(KuromojiError::Format(format!(
+                "Unsupported feature schema {:?}",
+                feature_schema
+            )));
+        }
+        let index_type = if container.contains(PrefixIndexType::Fst.filename()) {
+            PrefixIndexType::Fst
+        } else {
+            PrefixIndexType::DoubleArray
+        };
+        let mut dictionary = Dictionary::from_files(
+            index_type,
+            |name| container.contains(name),
+            |name| {
+                if verify_checksums {
+                    container.section(name)?;
+                }
+                data.slice(container.section_range(name)?)
+            },
+        )?;
+        dictionary.metadata = Some(container.metadata().clone());
+        Ok(dictionary)
+    }
+
+    /// Loads a dictionary from the directory where `build-fst` writes the
+    /// dictionary files, `dict` by default.
+    ///
+    /// The directory holds the same files as the sections of a container, see
+    /// `from_container`. As it may hold both types of prefix index, the one to
+    /// load is given by `prefix_index_type`. Unlike with a container, the files
+    /// are not checked against each other.
+    pub fn open_dir<P: AsRef<Path>>(
+        dir: P,
+        prefix_index_type: PrefixIndexType,
+    ) -> Result<Dictionary, KuromojiError> {
+        let dir = dir.as_ref();
+        Dictionary::from_files(
+            prefix_index_type,
+            |name| dir.join(name).exists(),
+            |name| Ok(DictData::from_vec(fs::read(dir.join(name))?)),
+        )
+    }
+
+    /// Loads a dictionary from its files, `contains(name)` telling whether
+    /// a file exists, and `file(name)` returning its data.
+    fn from_files(
+        index_type: PrefixIndexType,
+        contains: impl Fn(&str) -> bool,
+        file: impl Fn(&str) -> Result<DictData, KuromojiError>,
+    ) -> Result<Dictionary, KuromojiError> {
+        let mut dictionary = Dictionary::new(
+            PrefixDict::from_data(index_type, file(index_type.filename())?, file("dict.vals")?)?,
+            if contains(COMPACT_MATRIX) {
+                ConnectionCostMatrix::from_compact_data(file(COMPACT_MATRIX)?)?
+            } else {
+                ConnectionCostMatrix::from_data(file("matrix.mtx")?)?
+            },
+            CharacterDefinitions::from_bytes(&file("char_def.bin")?)?,
+            UnknownDictionary::from_bytes(&file("unk.bin")?)?,
+            WordDictionary::from_data(file("dict.wordsidx")?, file("dict.words")?)?,
+        )?;
+        if contains(READING_INDEX_FST) {
+            dictionary.reading_index = Some(ReadingIndex::from_data(
+                file(READING_INDEX_FST)?,
+                file(READING_INDEX_VALS)?,
+            )?);
+        }
+        Ok(dictionary)
+    }
+
+    /// Switches the connection cost matrix to its compact layout.
+    ///
+    /// See `ConnectionCostMatrix::to_compact_data`. The compact matrix is built from
+    /// the dense one, unless it was already loaded from a dictionary built with
+    /// `build-fst --compact-matrix`.
+    pub fn with_compact_cost_matrix(mut self) -> Result<Dictionary, KuromojiError> {
+        let compact_data = self.cost_matrix.to_compact_data()?;
+        self.cost_matrix =
+            ConnectionCostMatrix::from_compact_data(DictData::from_vec(compact_data))?;
+        Ok(self)
+    }
+
+    /// Builds the index of the words by reading, if the dictionary has none.
+    ///
+    /// The index is not embedded in the library. Building it takes about a second,
+    /// and it can also be built ahead of time in a container file by `build-fst`.
+    pub fn with_reading_index(mut self) -> Result<Dictionary, KuromojiError> {
+        if self.reading_index.is_some() {
+            return Ok(self);
+        }
+        let (fst_data, vals_data) =
+            {
+                let mut words = Vec::new();
+                self.for_each_entry(|entry| {
+                    words.push((entry.detail.reading, entry.surface, entry.word_entry))
+                })?;
+                build_reading_index(words.iter().map(|(reading, surface, word_entry)| {
+                    (*reading, surface.as_str(), *word_entry)
+                }))?
+            };
+        self.reading_index = Some(ReadingIndex::from_data(
+            DictData::from_vec(fst_data),
+            DictData::from_vec(vals_data),
+        )?);
+        Ok(self)
+    }
+
+    pub fn has_reading_index(&self) -> bool {
+        self.reading_index.is_some()
+    }
+
+    pub fn cost_matrix(&self) -> &ConnectionCostMatrix {
+        &self.cost_matrix
+    }
+
+    /// Returns the metadata of the container the dictionary was loaded from, if any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    fn pos_context_ids(&self) -> &[PosContextId] {
+        self.pos_context_ids.get_or_init(|| {
+            let mut num_words: HashMap<([&str; 4], u16), usize> = HashMap::new();
+            self.dict.for_each_entry(|word_entry| {
+                if let Ok(word_detail) = self.words.load_word_id(word_entry.word_id) {
+                    *num_words
+                        .entry((word_detail.pos(), word_entry.cost_id))
+                        .or_default() += 1;
+                }
+            });
+            num_words
+                .into_iter()
+                .map(|((pos, context_id), num_words)| PosContextId {
+                    pos: pos.map(str::to_string),
+                    context_id,
+                    num_words,
+                })
+                .collect()
+        })
+    }
+
+    /// Returns the context id used by the most words whose part-of-speech starts
+    /// with the levels of `pos`.
+    ///
+    /// The first call scans the whole dictionary.
+    pub(crate) fn context_id(&self, pos: &[&str]) -> Option<u16> {
+        if pos.len() > 4 {
+            return None;
+        }
+        self.pos_context_ids()
+            .iter()
+            .filter(|pos_context_id| pos_context_id.pos.iter().zip(pos).all(|(a, b)| a == b))
+            .max_by_key(|pos_context_id| {
+                (pos_context_id.num_words, Reverse(pos_context_id.context_id))
+            })
+            .map(|pos_context_id| pos_context_id.context_id)
+    }
+
+    /// Returns the context ids of the words whose part-of-speech starts with the
+    /// levels of `pos`. `*` matches any level.
+    ///
+    /// The first call scans the whole dictionary.
+    pub(crate) fn context_ids(&self, pos: &[&str]) -> Vec<u16> {
+        let mut context_ids: Vec<u16> = self
+            .pos_context_ids()
+            .iter()
+            .filter(|pos_context_id| {
+                pos.len() <= 4
+                    && pos_context_id
+                        .pos
+                        .iter()
+                        .zip(pos)
+                        .all(|(a, b)| *b == "*" || a == b)
+            })
+            .map(|pos_context_id| pos_context_id.context_id)
+            .collect();
+        context_ids.sort_unstable();
+        context_ids.dedup();
+        context_ids
+    }
+
+    /// Returns the words whose surface form is `surface`.
+    pub fn lookup(&self, surface: &str) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        self.dict
+            .get(surface)
+            .into_iter()
+            .map(|word_entry| self.entry(surface, word_entry))
+            .collect()
+    }
+
+    /// Returns the words whose surface form starts with `prefix`, in the
+    /// lexicographic order of their surface forms.
+    pub fn lookup_prefix(&self, prefix: &str) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        let mut entries = Vec::new();
+        self.for_each_entry_with_prefix(prefix, |entry| entries.push(entry))?;
+        Ok(entries)
+    }
+
+    /// Returns the words whose surface form is within `max_distance` edits of `surface`,
+    /// with their edit distance, by increasing distance.
+    ///
+    /// See `KeyAutomaton::levenshtein` for the definition of the edit distance.
+    pub fn lookup_fuzzy(
+        &self,
+        surface: &str,
+        max_distance: u8,
+    ) -> Result<Vec<(u8, DictionaryEntry<'_>)>, KuromojiError> {
+        let automaton = KeyAutomaton::levenshtein(surface, max_distance)?;
+        let mut entries = Vec::new();
+        for entry in self.lookup_matching(&automaton)? {
+            if let Some(distance) = automaton.distance(entry.surface.as_bytes()) {
+                entries.push((distance, entry));
+            }
+        }
+        entries.sort_by_key(|&(distance, _)| distance);
+        Ok(entries)
+    }
+
+    /// Returns the words whose surface form is matched by the regular expression
+    /// `pattern`, in the lexicographic order of their surface forms.
+    ///
+    /// See `KeyAutomaton::regex` for the supported syntax.
+    pub fn lookup_regex(&self, pattern: &str) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        self.lookup_matching(&KeyAutomaton::regex(pattern)?)
+    }
+
+    fn lookup_matching(
+        &self,
+        automaton: &KeyAutomaton,
+    ) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        let mut entries = Vec::new();
+        let mut result = Ok(());
+        self.dict
+            .for_each_word_matching(automaton, |surface, word_entry| {
+                if result.is_ok() {
+                    match self.entry(surface, word_entry) {
+                        Ok(entry) => entries.push(entry),
+                        Err(err) => result = Err(err),
+                    }
+                }
+            });
+        result.map(|()| entries)
+    }
+
+    /// Returns the words whose reading, in katakana, is `reading`.
+    ///
+    /// Without a reading index, this scans the whole dictionary.
+    /// See `with_reading_index`.
+    pub fn lookup_by_reading(
+        &self,
+        reading: &str,
+    ) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        if let Some(reading_index) = self.reading_index.as_ref() {
+            return reading_index
+                .get(reading)
+                .into_iter()
+                .map(|(surface, word_entry)| self.entry(surface, word_entry))
+                .collect();
+        }
+        self.scan_readings(|word_reading| word_reading == reading)
+    }
+
+    /// Returns the words whose reading, in katakana, starts with `prefix`.
+    ///
+    /// Without a reading index, this scans the whole dictionary.
+    /// See `with_reading_index`.
+    pub fn lookup_by_reading_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        if let Some(reading_index) = self.reading_index.as_ref() {
+            let mut entries = Vec::new();
+            let mut result = Ok(());
+            reading_index.for_each_word_with_prefix(prefix, |surface, word_entry| {
+                if result.is_ok() {
+                    match self.entry(surface, word_entry) {
+                        Ok(entry) => entries.push(entry),
+                        Err(err) => result = Err(err),
+                    }
+                }
+            });
+            return result.map(|()| entries);
+        }
+        self.scan_readings(|reading| reading.starts_with(prefix))
+    }
+
+    fn scan_readings(
+        &self,
+        predicate: impl Fn(&str) -> bool,
+    ) -> Result<Vec<DictionaryEntry<'_>>, KuromojiError> {
+        let mut entries = Vec::new();
+        self.for_each_entry(|entry| {
+            if predicate(entry.detail.reading) {
+                entries.push(entry);
+            }
+        })?;
+        Ok(entries)
+    }
+
+    /// Calls `callback` for each word of the dictionary, in the lexicographic
+    /// order of their surface forms.
+    pub fn for_each_entry<'a>(
+        &'a self,
+        callback: impl FnMut(DictionaryEntry<'a>),
+    ) -> Result<(), KuromojiError> {
+        self.for_each_entry_with_prefix("", callback)
+    }
+
+    fn for_each_entry_with_prefix<'a>(
+        &'a self,
+        prefix: &str,
+        mut callback: impl FnMut(DictionaryEntry<'a>),
+    ) -> Result<(), KuromojiError> {
+        let mut result = Ok(());
+        self.dict
+            .for_each_word_with_prefix(prefix, |surface, word_entry| {
+                if result.is_ok() {
+                    match self.entry(surface, word_entry) {
+                        Ok(entry) => callback(entry),
+                        Err(err) => result = Err(err),
+                    }
+                }
+            });
+        result
+    }
+
+    fn entry(
+        &self,
+        surface: &str,
+        word_entry: WordEntry,
+    ) -> Result<DictionaryEntry<'_>, KuromojiError> {
+        Ok(DictionaryEntry {
+            surface: surface.to_string(),
+            word_entry,
+            detail: self.word_detail(word_entry.word_id)?,
+        })
+    }
+
+    /// Returns the details of a word.
+    pub fn word_detail(&self, word_id: WordId) -> Result<WordDetail<'_>, KuromojiError> {
+        self.words.load_word_id(word_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dictionary, DictionaryEntry};
+    use crate::connection::{ConnectionCostMatrix, COMPACT_MATRIX};
+    use crate::container::{ContainerWriter, Metadata};
+    use crate::prefix_index::{DoubleArrayIndex, FstIndex, PrefixIndexType};
+    use crate::reading_index::{build_reading_index, READING_INDEX_FST, READING_INDEX_VALS};
+    use crate::{DictData, KuromojiError, Mode, Tokenizer, WordDetail};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn container_data(feature_schema: &[&str]) -> Vec<u8> {
+        container_data_with_sections(feature_schema, Vec::new())
+    }
+
+    fn container_data_with_sections(
+        feature_schema: &[&str],
+        extra_sections: Vec<(&str, Vec<u8>)>,
+    ) -> Vec<u8> {
+        let metadata = Metadata {
+            source_dictionary: "mecab-ipadic".to_string(),
+            encoding: "EUC-JP".to_string(),
+            build_date: 0,
+            feature_schema: feature_schema.iter().map(|name| name.to_string()).collect(),
+        };
+        let mut writer = ContainerWriter::new(metadata);
+        let sections: [(&str, &[u8]); 7] = [
+            ("dict.fst", include_bytes!("../dict/dict.fst")),
+            ("dict.vals", include_bytes!("../dict/dict.vals")),
+            ("dict.words", include_bytes!("../dict/dict.words")),
+            ("dict.wordsidx", include_bytes!("../dict/dict.wordsidx")),
+            ("matrix.mtx", include_bytes!("../dict/matrix.mtx")),
+            ("char_def.bin", include_bytes!("../dict/char_def.bin")),
+            ("unk.bin", include_bytes!("../dict/unk.bin")),
+        ];
+        // extra sections replace the default ones of the same name.
+        for &(name, data) in &sections {
+            if extra_sections
+                .iter()
+                .all(|(extra_name, _)| *extra_name != name)
+            {
+                writer.add_section(name, data.to_vec());
+            }
+        }
+        for (name, data) in extra_sections {
+            writer.add_section(name, data);
+        }
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_dictionary_from_container() {
+        let data = container_data(&WordDetail::FEATURE_SCHEMA);
+        let dictionary = Dictionary::from_container(DictData::from_vec(data)).unwrap();
+        assert_eq!(
+            dictionary.metadata().unwrap().source_dictionary,
+            "mecab-ipadic"
+        );
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary), Mode::Normal);
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "スモモ");
+        assert!(Dictionary::load_default().unwrap().metadata().is_none());
+    }
+
+    #[test]
+    fn test_dictionary_lookup() {
+        let dictionary = Dictionary::load_default().unwrap();
+        let entries = dictionary.lookup("は").unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.surface == "は"));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.detail.pos() == ["助詞", "係助詞", "*", "*"]));
+        assert!(dictionary.lookup("東京すもも").unwrap().is_empty());
+
+        let entries = dictionary.lookup_prefix("関西国際").unwrap();
+        let surfaces: Vec<&str> = entries.iter().map(|entry| entry.surface.as_str()).collect();
+        assert_eq!(surfaces, vec!["関西国際空港", "関西国際空港会社連絡橋"]);
+        assert_eq!(dictionary.lookup("関西国際空港").unwrap()[0], entries[0]);
+    }
+
+    #[test]
+    fn test_dictionary_lookup_fuzzy() {
+        let dictionary = Dictionary::load_default().unwrap();
+        let entries = dictionary.lookup_fuzzy("関西国債空港", 1).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, 1);
+        assert_eq!(entries[0].1.surface, "関西国際空港");
+        assert_eq!(entries[0].1.detail.reading, "カンサイコクサイクウコウ");
+
+        let entries = dictionary.lookup_fuzzy("すもも", 1).unwrap();
+        assert_eq!(entries[0].0, 0);
+        assert_eq!(entries[0].1.surface, "すもも");
+        assert!(entries
+            .iter()
+            .any(|(distance, entry)| *distance == 1 && entry.surface == "すもう"));
+        assert!(entries.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(dictionary.lookup_fuzzy("すもも", 4).is_err());
+    }
+
+    #[test]
+    fn test_dictionary_lookup_regex() {
+        let dictionary = Dictionary::load_default().unwrap();
+        let entries = dictionary.lookup_regex("関西国際空港.*").unwrap();
+        let surfaces: Vec<&str> = entries.iter().map(|entry| entry.surface.as_str()).collect();
+        assert_eq!(surfaces, vec!["関西国際空港", "関西国際空港会社連絡橋"]);
+        let entries = dictionary.lookup_regex("東京(都|湾)").unwrap();
+        let mut surfaces: Vec<&str> = entries.iter().map(|entry| entry.surface.as_str()).collect();
+        surfaces.dedup();
+        assert_eq!(surfaces, vec!["東京湾"]);
+        assert!(dictionary.lookup_regex("東京(").is_err());
+    }
+
+    fn word_ids(entries: Vec<DictionaryEntry<'_>>) -> Vec<(u32, String)> {
+        let mut word_ids: Vec<(u32, String)> = entries
+            .into_iter()
+            .map(|entry| (entry.word_entry.word_id.0, entry.surface))
+            .collect();
+        word_ids.sort();
+        word_ids
+    }
+
+    #[test]
+    fn test_dictionary_lookup_by_reading() {
+        let dictionary = Dictionary::load_default().unwrap();
+        assert!(!dictionary.has_reading_index());
+        let entries = dictionary.lookup_by_reading("トウキョウ").unwrap();
+        assert!(entries.iter().any(|entry| entry.surface == "東京"));
+        assert!(entries
+            .iter()
+            .all(|entry| entry.detail.reading == "トウキョウ"));
+        let scanned_word_ids = word_ids(entries);
+        let prefix_entries = dictionary.lookup_by_reading_prefix("トウキョウ").unwrap();
+        assert!(prefix_entries.len() > scanned_word_ids.len());
+        let scanned_prefix_word_ids = word_ids(prefix_entries);
+
+        let dictionary = Dictionary::load_default()
+            .unwrap()
+            .with_reading_index()
+            .unwrap();
+        assert!(dictionary.has_reading_index());
+        assert_eq!(
+            word_ids(dictionary.lookup_by_reading("トウキョウ").unwrap()),
+            scanned_word_ids
+        );
+        assert_eq!(
+            word_ids(dictionary.lookup_by_reading_prefix("トウキョウ").unwrap()),
+            scanned_prefix_word_ids
+        );
+        assert!(dictionary.lookup_by_reading("トウキョ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dictionary_from_container_with_reading_index() {
+        let dictionary = Dictionary::load_default().unwrap();
+        let surface = "東京";
+        let word_entry = dictionary.lookup(surface).unwrap()[0].word_entry;
+        let (fst_data, vals_data) =
+            build_reading_index(vec![("トーキョー", surface, word_entry)]).unwrap();
+        let data = container_data_with_sections(
+            &WordDetail::FEATURE_SCHEMA,
+            vec![
+                (READING_INDEX_FST, fst_data),
+                (READING_INDEX_VALS, vals_data),
+            ],
+        );
+        let dictionary = Dictionary::from_container(DictData::from_vec(data)).unwrap();
+        assert!(dictionary.has_reading_index());
+        let entries = dictionary.lookup_by_reading("トーキョー").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].surface, "東京");
+        assert_eq!(entries[0].detail.reading, "トウキョウ");
+    }
+
+    #[test]
+    fn test_dictionary_unknown_feature_schema() {
+        let data = container_data(&["pos", "reading"]);
+        assert!(matches!(
+            Dictionary::from_container(DictData::from_vec(data)),
+            Err(KuromojiError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_dictionary_context_id_out_of_matrix() {
+        // a 2x2 connection cost matrix, too small for the IPADIC context ids.
+        let mut matrix = vec![2, 0, 2, 0];
+        matrix.extend_from_slice(&[0; 8]);
+        let data =
+            container_data_with_sections(&WordDetail::FEATURE_SCHEMA, vec![("matrix.mtx", matrix)]);
+        assert!(matches!(
+            Dictionary::from_container(DictData::from_vec(data)),
+            Err(KuromojiError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_dictionary_from_container_with_compact_matrix() {
+        let compact_data = Dictionary::load_default()
+            .unwrap()
+            .cost_matrix
+            .to_compact_data()
+            .unwrap();
+        let data = container_data_with_sections(
+            &WordDetail::FEATURE_SCHEMA,
+            vec![(COMPACT_MATRIX, compact_data)],
+        );
+        let dictionary = Dictionary::from_container(DictData::from_vec(data)).unwrap();
+        assert!(
+            dictionary.cost_matrix.num_bytes()
+                < ConnectionCostMatrix::load_default().unwrap().num_bytes()
+        );
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary), Mode::Normal);
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert_eq!(tokens.len(), 7);
+    }
+
+    #[test]
+    fn test_dictionary_open_dir_with_double_array() {
+        let dir = std::env::temp_dir().join(format!("kuromoji-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &[
+            "dict.vals",
+            "dict.words",
+            "dict.wordsidx",
+            "matrix.mtx",
+            "char_def.bin",
+            "unk.bin",
+        ] {
+            std::fs::copy(Path::new("dict").join(name), dir.join(name)).unwrap();
+        }
+        let fst_index = FstIndex::new(&include_bytes!("../dict/dict.fst")[..]).unwrap();
+        let double_array_index = DoubleArrayIndex::build(&fst_index.key_values()).unwrap();
+        std::fs::write(
+            dir.join(PrefixIndexType::DoubleArray.filename()),
+            double_array_index.as_bytes(),
+        )
+        .unwrap();
+        let dictionary = Dictionary::open_dir(&dir, PrefixIndexType::DoubleArray);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary.unwrap()), Mode::Normal);
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "スモモ");
+        assert!(Dictionary::open_dir(Path::new("dict"), PrefixIndexType::Fst).is_ok());
+        assert!(Dictionary::open_dir(Path::new("no-such-dir"), PrefixIndexType::Fst).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_dictionary_mmap() {
+        let path = std::env::temp_dir().join(format!("kuromoji-{}.dic", std::process::id()));
+        std::fs::write(&path, container_data(&WordDetail::FEATURE_SCHEMA)).unwrap();
+        let dictionary = Dictionary::mmap(&path);
+        std::fs::remove_file(&path).unwrap();
+        let mut tokenizer = Tokenizer::with_dictionary(Arc::new(dictionary.unwrap()), Mode::Normal);
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "スモモ");
+    }
+}
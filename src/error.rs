@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::io;
+use std::num::ParseIntError;
+
+/// Error returned when reading, parsing or loading dictionary data.
+#[derive(Debug)]
+pub enum KuromojiError {
+    Io(io::Error),
+    /// The data is not valid in its expected text encoding.
+    Encoding,
+    /// The data is malformed or corrupt.
+    Format(String),
+    /// The data was written with an unsupported version of its format.
+    VersionMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// The constraints of a tokenization cannot be applied to its text.
+    InvalidConstraints(String),
+}
+
+impl KuromojiError {
+    pub fn from_error<D: Debug>(error: D) -> KuromojiError {
+        KuromojiError::Format(format!("{:?}", error))
+    }
+}
+
+impl fmt::Display for KuromojiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KuromojiError::Io(io_err) => write!(f, "IO error: {}", io_err),
+            KuromojiError::Encoding => write!(f, "Invalid text encoding"),
+            KuromojiError::Format(msg) => write!(f, "Invalid data: {}", msg),
+            KuromojiError::VersionMismatch { expected, found } => write!(
+                f,
+                "Unsupported format version {} (expected {})",
+                found, expected
+            ),
+            KuromojiError::InvalidConstraints(msg) => write!(f, "Invalid constraints: {}", msg),
+        }
+    }
+}
+
+impl Error for KuromojiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KuromojiError::Io(io_err) => Some(io_err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KuromojiError {
+    fn from(io_err: io::Error) -> Self {
+        KuromojiError::Io(io_err)
+    }
+}
+
+impl From<ParseIntError> for KuromojiError {
+    fn from(parse_err: ParseIntError) -> Self {
+        KuromojiError::from_error(parse_err)
+    }
+}
+
+impl From<bincode::Error> for KuromojiError {
+    fn from(bincode_err: bincode::Error) -> Self {
+        KuromojiError::Format(bincode_err.to_string())
+    }
+}
+
+impl From<tantivy_fst::Error> for KuromojiError {
+    fn from(fst_err: tantivy_fst::Error) -> Self {
+        KuromojiError::Format(fst_err.to_string())
+    }
+}
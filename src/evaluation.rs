@@ -0,0 +1,325 @@
+//! Segmentation accuracy, measured against a gold corpus.
+use crate::corpus::GoldToken;
+use crate::{Token, Tokenizer};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Token, as compared by the evaluation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvalToken {
+    pub surface: String,
+    /// The four part-of-speech levels, comma-separated.
+    pub pos: Option<String>,
+    pub reading: Option<String>,
+}
+
+impl EvalToken {
+    pub fn from_gold(gold_token: &GoldToken) -> EvalToken {
+        let features = &gold_token.features;
+        let pos = if features.len() >= 4 {
+            Some(features[..4].join(","))
+        } else {
+            None
+        };
+        EvalToken {
+            surface: gold_token.surface.clone(),
+            pos,
+            reading: features.get(7).cloned(),
+        }
+    }
+
+    pub fn from_token(tokenizer: &Tokenizer, token: &Token) -> EvalToken {
+        let detail = tokenizer.detail(token);
+        EvalToken {
+            surface: token.text.to_string(),
+            pos: Some(detail.pos().join(",")),
+            reading: Some(detail.reading.to_string()),
+        }
+    }
+}
+
+/// Criteria for a predicted token to be correct.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Level {
+    /// Same boundaries as a gold token.
+    Boundary,
+    /// Same boundaries and part-of-speech.
+    BoundaryPos,
+    /// Same boundaries, part-of-speech and reading.
+    BoundaryPosReading,
+}
+
+impl Level {
+    pub const ALL: [Level; 3] = [
+        Level::Boundary,
+        Level::BoundaryPos,
+        Level::BoundaryPosReading,
+    ];
+
+    fn matches(self, gold: &EvalToken, predicted: &EvalToken) -> bool {
+        match self {
+            Level::Boundary => true,
+            Level::BoundaryPos => gold.pos == predicted.pos,
+            Level::BoundaryPosReading => {
+                gold.pos == predicted.pos && gold.reading == predicted.reading
+            }
+        }
+    }
+
+    fn is_annotated(self, gold: &EvalToken) -> bool {
+        match self {
+            Level::Boundary => true,
+            Level::BoundaryPos => gold.pos.is_some(),
+            Level::BoundaryPosReading => gold.pos.is_some() && gold.reading.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Boundary => write!(f, "boundary"),
+            Level::BoundaryPos => write!(f, "boundary+pos"),
+            Level::BoundaryPosReading => write!(f, "boundary+pos+reading"),
+        }
+    }
+}
+
+/// Token counts of one level.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Scores {
+    pub num_gold: usize,
+    pub num_predicted: usize,
+    pub num_correct: usize,
+}
+
+impl Scores {
+    pub fn precision(&self) -> f64 {
+        ratio(self.num_correct, self.num_predicted)
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(self.num_correct, self.num_gold)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0f64 {
+            0f64
+        } else {
+            2f64 * precision * recall / (precision + recall)
+        }
+    }
+}
+
+fn ratio(num: usize, denom: usize) -> f64 {
+    if denom == 0 {
+        0f64
+    } else {
+        num as f64 / denom as f64
+    }
+}
+
+/// Accumulates the scores of each level, and the errors, over a corpus.
+///
+/// A level is only reported if the gold corpus is annotated for it: a wakati corpus
+/// only gets boundary scores.
+#[derive(Default)]
+pub struct Evaluation {
+    scores: HashMap<Level, Scores>,
+    errors: HashMap<(String, String), usize>,
+}
+
+fn describe(tokens: &[EvalToken], with_pos: bool) -> String {
+    tokens
+        .iter()
+        .map(|token| match (&token.pos, with_pos) {
+            (Some(pos), true) => format!("{}({})", token.surface, pos),
+            _ => token.surface.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+impl Evaluation {
+    /// Compares the tokens of a sentence. Both segmentations must cover the same text.
+    pub fn add_sentence(&mut self, gold: &[EvalToken], predicted: &[EvalToken]) {
+        // Levels for which the whole sentence is annotated. The others are not scored.
+        let levels: Vec<Level> = Level::ALL
+            .iter()
+            .cloned()
+            .filter(|level| gold.iter().all(|token| level.is_annotated(token)))
+            .collect();
+        for &level in &levels {
+            let scores = self.scores.entry(level).or_default();
+            scores.num_gold += gold.len();
+            scores.num_predicted += predicted.len();
+        }
+        // Walks both segmentations, one region at a time. Regions end at the
+        // boundaries shared by both segmentations.
+        let (mut gold_id, mut predicted_id) = (0, 0);
+        while gold_id < gold.len() && predicted_id < predicted.len() {
+            let (gold_start, predicted_start) = (gold_id, predicted_id);
+            let mut gold_offset = gold[gold_id].surface.len();
+            let mut predicted_offset = predicted[predicted_id].surface.len();
+            gold_id += 1;
+            predicted_id += 1;
+            while gold_offset != predicted_offset {
+                if gold_offset < predicted_offset && gold_id < gold.len() {
+                    gold_offset += gold[gold_id].surface.len();
+                    gold_id += 1;
+                } else if predicted_id < predicted.len() {
+                    predicted_offset += predicted[predicted_id].surface.len();
+                    predicted_id += 1;
+                } else {
+                    break;
+                }
+            }
+            let gold_region = &gold[gold_start..gold_id];
+            let predicted_region = &predicted[predicted_start..predicted_id];
+            if gold_region.len() != 1 || predicted_region.len() != 1 {
+                self.add_error(
+                    describe(gold_region, false),
+                    describe(predicted_region, false),
+                );
+                continue;
+            }
+            let (gold_token, predicted_token) = (&gold_region[0], &predicted_region[0]);
+            for &level in &levels {
+                if level.matches(gold_token, predicted_token) {
+                    self.scores.entry(level).or_default().num_correct += 1;
+                }
+            }
+            if gold_token.pos.is_some() && gold_token.pos != predicted_token.pos {
+                self.add_error(
+                    describe(gold_region, true),
+                    describe(predicted_region, true),
+                );
+            }
+        }
+    }
+
+    fn add_error(&mut self, gold: String, predicted: String) {
+        *self.errors.entry((gold, predicted)).or_default() += 1;
+    }
+
+    /// Returns the scores of a level, or `None` if the gold corpus is not annotated
+    /// for this level.
+    pub fn scores(&self, level: Level) -> Option<Scores> {
+        self.scores.get(&level).cloned()
+    }
+
+    /// Returns the `limit` most frequent `(gold, predicted, count)` errors.
+    ///
+    /// Segmentation errors list the surfaces of the tokens of both segmentations,
+    /// part-of-speech errors also list their part-of-speech.
+    pub fn most_frequent_errors(&self, limit: usize) -> Vec<(&str, &str, usize)> {
+        let mut errors: Vec<(&str, &str, usize)> = self
+            .errors
+            .iter()
+            .map(|((gold, predicted), &count)| (gold.as_str(), predicted.as_str(), count))
+            .collect();
+        errors.sort_by(|left, right| {
+            right
+                .2
+                .cmp(&left.2)
+                .then_with(|| (left.0, left.1).cmp(&(right.0, right.1)))
+        });
+        errors.truncate(limit);
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EvalToken, Evaluation, Level};
+    use crate::corpus::parse_mecab_corpus;
+
+    fn token(surface: &str, pos: &str, reading: &str) -> EvalToken {
+        EvalToken {
+            surface: surface.to_string(),
+            pos: Some(pos.to_string()),
+            reading: Some(reading.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let gold = vec![
+            token("くるま", "名詞", "クルマ"),
+            token("で", "助詞", "デ"),
+            token("待つ", "動詞", "マツ"),
+        ];
+        let predicted = vec![
+            token("くる", "動詞", "クル"),
+            token("まで", "助詞", "マデ"),
+            token("待つ", "動詞", "マチ"),
+        ];
+        let mut evaluation = Evaluation::default();
+        evaluation.add_sentence(&gold, &predicted);
+        evaluation.add_sentence(&gold, &gold);
+        let boundary = evaluation.scores(Level::Boundary).unwrap();
+        assert_eq!(boundary.num_gold, 6);
+        assert_eq!(boundary.num_predicted, 6);
+        assert_eq!(boundary.num_correct, 4);
+        assert!((boundary.f1() - 4f64 / 6f64).abs() < 1e-9);
+        assert_eq!(
+            evaluation.scores(Level::BoundaryPos).unwrap().num_correct,
+            4
+        );
+        assert_eq!(
+            evaluation
+                .scores(Level::BoundaryPosReading)
+                .unwrap()
+                .num_correct,
+            3
+        );
+        assert_eq!(
+            evaluation.most_frequent_errors(10),
+            vec![("くるま / で", "くる / まで", 1)]
+        );
+    }
+
+    #[test]
+    fn test_evaluation_without_reading() {
+        let gold = parse_mecab_corpus(
+            "くるま\t名詞,一般,*,*,*,*,くるま,クルマ,クルマ\n\
+             EOS\n\
+             ピカチュウ\t名詞,固有名詞,一般,*,*,*,*\n\
+             EOS\n",
+        )
+        .unwrap();
+        let mut evaluation = Evaluation::default();
+        for sentence in &gold {
+            let tokens: Vec<EvalToken> = sentence.tokens.iter().map(EvalToken::from_gold).collect();
+            evaluation.add_sentence(&tokens, &tokens);
+        }
+        let boundary_pos = evaluation.scores(Level::BoundaryPos).unwrap();
+        assert_eq!((boundary_pos.num_gold, boundary_pos.num_correct), (2, 2));
+        let reading = evaluation.scores(Level::BoundaryPosReading).unwrap();
+        assert_eq!(
+            (reading.num_gold, reading.num_predicted, reading.num_correct),
+            (1, 1, 1)
+        );
+        assert_eq!((reading.precision(), reading.recall()), (1f64, 1f64));
+    }
+
+    #[test]
+    fn test_evaluation_wakati() {
+        let surfaces = |surfaces: &[&str]| -> Vec<EvalToken> {
+            surfaces
+                .iter()
+                .map(|surface| EvalToken {
+                    surface: surface.to_string(),
+                    pos: None,
+                    reading: None,
+                })
+                .collect()
+        };
+        let mut evaluation = Evaluation::default();
+        evaluation.add_sentence(&surfaces(&["僕", "は"]), &surfaces(&["僕は"]));
+        let boundary = evaluation.scores(Level::Boundary).unwrap();
+        assert_eq!((boundary.precision(), boundary.recall()), (0f64, 0f64));
+        assert!(evaluation.scores(Level::BoundaryPos).is_none());
+    }
+}
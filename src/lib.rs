@@ -1,68 +1,69 @@
+pub mod bunsetsu;
+pub mod char_filter;
 pub mod character_definition;
-mod connection;
-mod prefix_dict;
+pub mod connection;
+pub mod cost_adjustment;
+pub mod container;
+pub mod corpus;
+mod data;
+mod dictionary;
+mod error;
+pub mod evaluation;
+pub mod number_filter;
+pub mod prefix_dict;
+pub mod prefix_index;
+pub mod reading_index;
+mod stream;
+pub mod training;
+pub mod typo_tolerance;
 pub mod unknown_dictionary;
+pub mod user_dictionary;
 mod viterbi;
 mod word_entry;
+pub mod word_mask;
 
+use crate::char_filter::{apply_char_filters, CharFilter, FilteredText};
 pub use crate::character_definition::CharacterDefinitions;
-use crate::connection::ConnectionCostMatrix;
-use crate::prefix_dict::PrefixDict;
-use crate::unknown_dictionary::UnknownDictionary;
+use crate::cost_adjustment::{CostAdjustments, ResolvedCostAdjustments};
+pub use crate::data::DictData;
+pub use crate::dictionary::{Dictionary, DictionaryEntry};
+pub use crate::error::KuromojiError;
+pub use crate::stream::{StreamToken, TokenStream};
+use crate::typo_tolerance::TypoTolerance;
+use crate::user_dictionary::{UserDictionary, UserWords};
 use crate::viterbi::{Edge, Lattice};
-use crate::word_entry::WordDictionary;
+pub use crate::viterbi::Constraints;
 pub use crate::word_entry::{WordDetail, WordEntry};
+use crate::word_mask::WordMask;
 use encoding::DecoderTrap;
 use encoding::Encoding;
 use std::fmt::Debug;
 use std::fs::File;
-use std::io;
-use std::io::Read;
-use std::num::ParseIntError;
+use std::io::{BufRead, Read};
 use std::path::Path;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct WordId(pub u32);
 
 impl WordId {
     pub fn is_unknown(&self) -> bool {
-        self.0 == std::u32::MAX
+        self.0 == u32::MAX
     }
-}
-
-impl Default for WordId {
-    fn default() -> Self {
-        WordId(std::u32::MAX)
-    }
-}
-
-#[derive(Debug)]
-pub enum ParsingError {
-    Encoding,
-    IoError(io::Error),
-    ContentError(String),
-}
 
-impl ParsingError {
-    fn from_error<D: Debug>(error: D) -> ParsingError {
-        ParsingError::ContentError(format!("{:?}", error))
+    /// Returns true for the words of a `UserDictionary`.
+    pub fn is_user(&self) -> bool {
+        !self.is_unknown() && self.0 & (1 << 31) != 0
     }
 }
 
-impl From<io::Error> for ParsingError {
-    fn from(io_err: io::Error) -> Self {
-        ParsingError::IoError(io_err)
-    }
-}
-
-impl From<ParseIntError> for ParsingError {
-    fn from(parse_err: ParseIntError) -> Self {
-        ParsingError::from_error(parse_err)
+impl Default for WordId {
+    fn default() -> Self {
+        WordId(u32::MAX)
     }
 }
 
-
 #[derive(Clone, Debug)]
 pub struct Penalty {
     kanji_penalty_length_threshold: usize,
@@ -121,53 +122,204 @@ impl Mode {
     }
 }
 
-pub fn read_mecab_file(filename: &'static str) -> Result<String, ParsingError> {
+pub fn read_mecab_file(filename: &'static str) -> Result<String, KuromojiError> {
     let path = Path::new("mecab-ipadic").join(Path::new(filename));
     let mut input_read = File::open(path)?;
     let mut buffer = Vec::new();
     input_read.read_to_end(&mut buffer)?;
     encoding::all::EUC_JP
         .decode(&buffer, DecoderTrap::Strict)
-        .map_err(|_| ParsingError::Encoding)
+        .map_err(|_| KuromojiError::Encoding)
 }
 
+/// Token of a text.
+///
+/// Tokens only hold their text and word id, so that creating them is free.
+/// Their dictionary details are decoded by `Tokenizer::detail`.
 pub struct Token<'a> {
     pub text: &'a str,
-    pub detail: WordDetail,
+    pub word_id: WordId,
+    corrected_text: Option<String>,
+    // requested part-of-speech, if the token was inserted by a constraint.
+    inserted_pos: Option<Vec<String>>,
+}
+
+impl<'a> Token<'a> {
+    /// Returns the surface form of the dictionary word of the token, if the text
+    /// of the token is a typo of this word.
+    ///
+    /// Only tokenizers with a typo tolerance find such tokens,
+    /// see `Tokenizer::with_typo_tolerance`.
+    pub fn corrected_text(&self) -> Option<&str> {
+        self.corrected_text.as_deref()
+    }
 }
 
+#[derive(Clone)]
 pub struct Tokenizer {
-    dict: PrefixDict<&'static [u8]>,
-    cost_matrix: ConnectionCostMatrix,
+    dictionary: Arc<Dictionary>,
     lattice: Lattice,
-    char_definitions: CharacterDefinitions,
-    unknown_dictionary: UnknownDictionary,
     mode: Mode,
+    char_filters: Vec<Arc<dyn CharFilter>>,
+    user_dictionary: Option<UserDictionary>,
+    // words of the user dictionary, as of the last tokenization.
+    user_words: Arc<UserWords>,
+    word_mask: Arc<WordMask>,
+    cost_adjustments: Arc<ResolvedCostAdjustments>,
+    typo_tolerance: Option<TypoTolerance>,
     offsets: Vec<(usize, WordId)>,
+    // start offsets and corrected surface forms of the typos, as of the last tokenization.
+    corrections: Vec<(usize, String)>,
+    // start offsets and requested parts-of-speech of the inserted forced tokens,
+    // as of the last tokenization.
+    insertions: Vec<(usize, Vec<String>)>,
 }
 
 impl Tokenizer {
-    pub fn new(mode: Mode) -> Tokenizer {
-        let dict = PrefixDict::default();
-        let cost_matrix = ConnectionCostMatrix::load_default();
-        let char_definitions = CharacterDefinitions::load();
-        let unknown_dictionary = UnknownDictionary::load();
+    pub fn new(mode: Mode) -> Result<Tokenizer, KuromojiError> {
+        let dictionary = Dictionary::load_default()?;
+        Ok(Tokenizer::with_dictionary(Arc::new(dictionary), mode))
+    }
+
+    /// Creates a tokenizer using the given dictionary.
+    ///
+    /// Tokenizers sharing the same dictionary only own their own lattice,
+    /// which makes them cheap to create, e.g. one per thread.
+    pub fn with_dictionary(dictionary: Arc<Dictionary>, mode: Mode) -> Tokenizer {
         Tokenizer {
-            dict,
-            cost_matrix,
+            dictionary,
             lattice: Lattice::default(),
-            char_definitions,
-            unknown_dictionary,
             mode,
+            char_filters: Vec::new(),
+            user_dictionary: None,
+            user_words: Arc::default(),
+            word_mask: Arc::default(),
+            cost_adjustments: Arc::default(),
+            typo_tolerance: None,
             offsets: Vec::new(),
+            corrections: Vec::new(),
+            insertions: Vec::new(),
         }
     }
 
-    pub fn for_search() -> Tokenizer {
+    /// Adds a char filter, rewriting the text before it is tokenized.
+    ///
+    /// Char filters are applied in the order they are added. The text of the tokens
+    /// is always taken from the original text.
+    pub fn with_char_filter<F: CharFilter + 'static>(mut self, char_filter: F) -> Tokenizer {
+        self.char_filters.push(Arc::new(char_filter));
+        self
+    }
+
+    /// Overlays the words of a user dictionary on the dictionary of the tokenizer.
+    ///
+    /// The user dictionary must have been created for the same dictionary.
+    pub fn with_user_dictionary(mut self, user_dictionary: UserDictionary) -> Tokenizer {
+        self.user_dictionary = Some(user_dictionary);
+        self
+    }
+
+    /// Masks words of the dictionary of the tokenizer.
+    ///
+    /// The masked words are never used for tokenization. See `WordMask`.
+    pub fn with_word_mask(mut self, word_mask: WordMask) -> Tokenizer {
+        self.word_mask = Arc::new(word_mask);
+        self
+    }
+
+    /// Adjusts the cost of the words of the dictionary, and of the user dictionary.
+    ///
+    /// See `CostAdjustments`.
+    pub fn with_cost_adjustments(mut self, cost_adjustments: CostAdjustments) -> Tokenizer {
+        self.cost_adjustments = Arc::new(cost_adjustments.resolve(&self.dictionary));
+        self
+    }
+
+    /// Makes the tokenizer tolerant to typos.
+    ///
+    /// Spans of the text within one edit of a word of the dictionary can then be
+    /// tokenized as this word, at a higher cost. See `TypoTolerance`
+    /// and `Token::corrected_text`.
+    pub fn with_typo_tolerance(mut self, typo_tolerance: TypoTolerance) -> Tokenizer {
+        self.typo_tolerance = Some(typo_tolerance);
+        self
+    }
+
+    pub fn dictionary(&self) -> &Arc<Dictionary> {
+        &self.dictionary
+    }
+
+    /// Returns the dictionary details of a token of this tokenizer.
+    ///
+    /// Details are decoded lazily, on each call, so that callers that
+    /// only need the token text or offsets do not pay for them.
+    ///
+    /// The words of the user dictionary are looked up in the words as of the last
+    /// tokenization, so the tokens of a word removed since then have no details.
+    ///
+    /// The tokens inserted by a constraint with a part-of-speech have this
+    /// part-of-speech, their text as base form and no reading.
+    ///
+    /// Returns `WordDetail::UNKNOWN` if the details cannot be decoded, see `try_detail`.
+    pub fn detail<'t>(&'t self, token: &'t Token) -> WordDetail<'t> {
+        self.try_detail(token).unwrap_or(WordDetail::UNKNOWN)
+    }
+
+    /// Same as `detail`, but fails if the dictionary data is corrupt.
+    pub fn try_detail<'t>(&'t self, token: &'t Token) -> Result<WordDetail<'t>, KuromojiError> {
+        if let Some(pos) = token.inserted_pos.as_ref() {
+            let pos_level = |level: usize| pos.get(level).map(String::as_str).unwrap_or("*");
+            return Ok(WordDetail {
+                pos_level1: pos_level(0),
+                pos_level2: pos_level(1),
+                pos_level3: pos_level(2),
+                pos_level4: pos_level(3),
+                conjugation_type: "*",
+                conjugate_form: "*",
+                base_form: token.text,
+                reading: "*",
+                pronunciation: "*",
+            });
+        }
+        if let Some(user_word) = self.user_words.word(token.word_id) {
+            return Ok(user_word.detail());
+        }
+        self.dictionary.word_detail(token.word_id)
+    }
+
+    /// Takes a snapshot of the words of the user dictionary, used until the next
+    /// tokenization.
+    fn update_user_words(&mut self) {
+        if let Some(user_dictionary) = self.user_dictionary.as_ref() {
+            self.user_words = user_dictionary.snapshot();
+        }
+    }
+
+    /// Returns the token of the last tokenization starting at `start`.
+    fn token<'a>(&self, start: usize, text: &'a str, word_id: WordId) -> Token<'a> {
+        let corrected_text = self
+            .corrections
+            .binary_search_by_key(&start, |&(correction_start, _)| correction_start)
+            .ok()
+            .map(|correction_ord| self.corrections[correction_ord].1.clone());
+        let inserted_pos = self
+            .insertions
+            .binary_search_by_key(&start, |&(insertion_start, _)| insertion_start)
+            .ok()
+            .map(|insertion_ord| self.insertions[insertion_ord].1.clone());
+        Token {
+            text,
+            word_id,
+            corrected_text,
+            inserted_pos,
+        }
+    }
+
+    pub fn for_search() -> Result<Tokenizer, KuromojiError> {
         Self::new(Mode::Search(Penalty::default()))
     }
 
-    pub fn normal() -> Tokenizer {
+    pub fn normal() -> Result<Tokenizer, KuromojiError> {
         Self::new(Mode::Normal)
     }
 
@@ -184,75 +336,203 @@ impl Tokenizer {
     ///
     /// Whitespaces also count as tokens.
     pub(crate) fn tokenize_offsets(&mut self, text: &str) -> &[(usize, WordId)] {
+        self.tokenize_offsets_with_constraints(text, &Constraints::default())
+    }
+
+    fn tokenize_offsets_with_constraints(
+        &mut self,
+        text: &str,
+        constraints: &Constraints,
+    ) -> &[(usize, WordId)] {
         if text.is_empty() {
             return &[];
         }
+        let dictionary = &*self.dictionary;
         self.lattice.set_text(
-            &self.dict,
-            &self.char_definitions,
-            &self.unknown_dictionary,
+            dictionary,
+            &self.user_words,
+            &self.word_mask,
+            &self.cost_adjustments,
+            self.typo_tolerance.as_ref(),
             text,
             &self.mode,
+            constraints,
         );
         self.lattice
-            .calculate_path_costs(&self.cost_matrix, &self.mode);
+            .calculate_path_costs(&dictionary.cost_matrix, &self.mode);
         self.lattice.tokens_offset(&mut self.offsets);
+        self.lattice.corrections(&mut self.corrections);
+        self.lattice.insertions(&mut self.insertions);
         &self.offsets[..]
     }
 
-    fn tokenize_without_split<'a>(&mut self, text: &'a str, tokens: &mut Vec<Token<'a>>) {
-        let offsets = self.tokenize_offsets(text);
-        for i in 0..offsets.len() {
-            let (token_start, word_id) = offsets[i];
-            let token_stop = if i == offsets.len() - 1 {
-                text.len()
-            } else {
-                let (next_start, _) = offsets[i + 1];
-                next_start
-            };
-            tokens.push(Token {
-                text: &text[token_start..token_stop],
-                detail: WordDictionary::load_word_id(word_id),
-            })
-        }
+    /// Tokenizes `text` and appends the resulting tokens to `tokens`.
+    ///
+    /// The vector is cleared first. Reusing the same vector across calls
+    /// makes tokenization allocation-free in steady state.
+    pub fn tokenize_into<'a>(&mut self, text: &'a str, tokens: &mut Vec<Token<'a>>) {
+        tokens.clear();
+        tokens.extend(self.tokenize_iter(text));
     }
 
-    fn tokenize_without_split_str<'a>(&mut self, text: &'a str, tokens: &mut Vec<&'a str>) {
-        let offsets = self.tokenize_offsets(text);
-        for i in 0..offsets.len() {
-            let (token_start, _word_detail) = offsets[i];
-            let token_stop = if i == offsets.len() - 1 {
-                text.len()
-            } else {
-                let (next_start, _) = offsets[i + 1];
-                next_start
-            };
-            tokens.push(&text[token_start..token_stop]);
-        }
+    /// Same as `tokenize_into`, but only returns the text of the tokens.
+    pub fn tokenize_str_into<'a>(&mut self, text: &'a str, tokens: &mut Vec<&'a str>) {
+        tokens.clear();
+        tokens.extend(self.tokenize_iter(text).map(|token| token.text));
     }
 
-    pub fn tokenize<'a>(&'a mut self, mut text: &'a str) -> Vec<Token> {
-        let mut tokens = Vec::new();
-        while let Some(split_idx) = text.find(|c| c == '。' || c == '、') {
-            self.tokenize_without_split(&text[..split_idx + 3], &mut tokens);
-            text = &text[split_idx + 3..];
-        }
-        if !text.is_empty() {
-            self.tokenize_without_split(&text, &mut tokens);
+    /// Returns an iterator over the tokens of `text`.
+    ///
+    /// The text is tokenized lazily, one sentence at a time, and the iterator
+    /// reuses the buffers of the tokenizer. It does not allocate, unless the
+    /// tokenizer has char filters.
+    pub fn tokenize_iter<'t, 'a>(&'t mut self, text: &'a str) -> TokenIter<'t, 'a> {
+        self.update_user_words();
+        self.offsets.clear();
+        let filtered = if self.char_filters.is_empty() {
+            None
+        } else {
+            Some(apply_char_filters(&self.char_filters, text))
+        };
+        TokenIter {
+            tokenizer: self,
+            text,
+            filtered,
+            sentence_start: 0,
+            sentence_stop: 0,
+            offset_idx: 0,
         }
-        tokens
     }
 
-    pub fn tokenize_str<'a>(&'a mut self, mut text: &'a str) -> Vec<&'a str> {
-        let mut tokens = Vec::new();
-        while let Some(split_idx) = text.find(|c| c == '。' || c == '、') {
-            self.tokenize_without_split_str(&text[..split_idx + 3], &mut tokens);
-            text = &text[split_idx + 3..];
-        }
-        if !text.is_empty() {
-            self.tokenize_without_split_str(&text, &mut tokens);
+    /// Returns an iterator over the tokens of a stream.
+    ///
+    /// Only a bounded part of the stream is held in memory. See `TokenStream`.
+    pub fn tokenize_reader<R: BufRead>(&mut self, reader: R) -> TokenStream<'_, R> {
+        TokenStream::new(self, reader)
+    }
+
+    pub fn tokenize<'a>(&mut self, text: &'a str) -> Vec<Token<'a>> {
+        self.tokenize_iter(text).collect()
+    }
+
+    pub fn tokenize_str<'a>(&mut self, text: &'a str) -> Vec<&'a str> {
+        self.tokenize_iter(text).map(|token| token.text).collect()
+    }
+
+    /// Tokenizes `text`, following the boundaries and tokens fixed by `constraints`,
+    /// like MeCab's partial parsing. The rest of the segmentation is resolved as usual.
+    ///
+    /// Unlike `tokenize`, the text is not split into sentences, and the char filters
+    /// of the tokenizer are not applied.
+    ///
+    /// Fails with `KuromojiError::InvalidConstraints` if the constraints cannot be
+    /// applied to `text`, see `Constraints::validate`.
+    pub fn tokenize_with_constraints<'a>(
+        &mut self,
+        text: &'a str,
+        constraints: &Constraints,
+    ) -> Result<Vec<Token<'a>>, KuromojiError> {
+        constraints.validate(text)?;
+        self.update_user_words();
+        self.tokenize_offsets_with_constraints(text, constraints);
+        let offsets = &self.offsets;
+        let tokens = offsets
+            .iter()
+            .enumerate()
+            .map(|(token_ord, &(token_start, word_id))| {
+                let token_stop = offsets
+                    .get(token_ord + 1)
+                    .map(|&(next_start, _)| next_start)
+                    .unwrap_or(text.len());
+                self.token(token_start, &text[token_start..token_stop], word_id)
+            })
+            .collect();
+        Ok(tokens)
+    }
+
+    /// Tokenizes a batch of texts in parallel.
+    ///
+    /// Each worker thread uses its own tokenizer, sharing the dictionary of
+    /// this one. The output is in the same order as `texts`.
+    #[cfg(feature = "rayon")]
+    pub fn tokenize_batch<'a>(&mut self, texts: &[&'a str]) -> Vec<Vec<Token<'a>>> {
+        use rayon::prelude::*;
+        self.update_user_words();
+        // the workers keep the snapshot of the user words of this tokenizer, so that
+        // it can give the details of their tokens.
+        let mut worker = self.clone();
+        worker.user_dictionary = None;
+        texts
+            .par_iter()
+            .map_init(
+                || worker.clone(),
+                |tokenizer, text| tokenizer.tokenize(text),
+            )
+            .collect()
+    }
+}
+
+/// Splits the text after the first `。` or `、`.
+///
+/// Both are safe boundaries, so that the lattice can be computed
+/// independently on each side.
+fn split_sentence(text: &str) -> (&str, &str) {
+    if let Some(split_idx) = text.find(['。', '、']) {
+        text.split_at(split_idx + 3)
+    } else {
+        (text, "")
+    }
+}
+
+/// Iterator over the tokens of a text.
+///
+/// See `Tokenizer::tokenize_iter`.
+pub struct TokenIter<'t, 'a> {
+    tokenizer: &'t mut Tokenizer,
+    text: &'a str,
+    // the text rewritten by the char filters, if the tokenizer has any.
+    filtered: Option<FilteredText>,
+    // range of the current sentence in the (filtered) text.
+    sentence_start: usize,
+    sentence_stop: usize,
+    offset_idx: usize,
+}
+
+impl<'t, 'a> Iterator for TokenIter<'t, 'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let offsets = &self.tokenizer.offsets;
+            if let Some(&(token_start, word_id)) = offsets.get(self.offset_idx) {
+                self.offset_idx += 1;
+                let token_stop = offsets
+                    .get(self.offset_idx)
+                    .map(|&(next_start, _)| next_start)
+                    .unwrap_or(self.sentence_stop - self.sentence_start);
+                let mut start = self.sentence_start + token_start;
+                let mut stop = self.sentence_start + token_stop;
+                if let Some(filtered) = self.filtered.as_ref() {
+                    start = filtered.original_start(start);
+                    stop = filtered.original_end(stop);
+                }
+                let text: &'a str = self.text;
+                return Some(self.tokenizer.token(token_start, &text[start..stop], word_id));
+            }
+            let text = self
+                .filtered
+                .as_ref()
+                .map(FilteredText::text)
+                .unwrap_or(self.text);
+            if self.sentence_stop == text.len() {
+                return None;
+            }
+            let (sentence, _) = split_sentence(&text[self.sentence_stop..]);
+            self.sentence_start = self.sentence_stop;
+            self.sentence_stop += sentence.len();
+            self.offset_idx = 0;
+            self.tokenizer.tokenize_offsets(sentence);
         }
-        tokens
     }
 }
 
@@ -260,11 +540,14 @@ impl Tokenizer {
 mod tests {
 
     use super::Tokenizer;
-    use crate::WordId;
+    use crate::char_filter::{
+        CharFilter, FilteredText, IterationMarkCharFilter, WidthFoldingCharFilter,
+    };
+    use crate::{Constraints, WordDetail, WordId};
 
     #[test]
     fn test_empty() {
-        let mut tokenizer = Tokenizer::for_search();
+        let mut tokenizer = Tokenizer::for_search().unwrap();
         let tokens = tokenizer.tokenize_offsets("");
         assert_eq!(tokens, &[]);
     }
@@ -272,7 +555,7 @@ mod tests {
 
     #[test]
     fn test_space() {
-        let mut tokenizer = Tokenizer::for_search();
+        let mut tokenizer = Tokenizer::for_search().unwrap();
         let tokens = tokenizer.tokenize_offsets(" ");
         assert_eq!(tokens, &[(0, WordId(4294967295))]);
     }
@@ -280,49 +563,49 @@ mod tests {
 
     #[test]
     fn test_boku_ha() {
-        let mut tokenizer = Tokenizer::for_search();
+        let mut tokenizer = Tokenizer::for_search().unwrap();
         let tokens = tokenizer.tokenize_offsets("僕は");
         assert_eq!(tokens, &[(0, WordId(132629)), (3, WordId(57065))]);
     }
     /*
         #[test]
         fn test_tokenize() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens = tokenizer.tokenize_offsets("俺はまだ本気出してないだけ。");
             assert_eq!(tokens, &[0, 3, 6, 12, 18, 24, 27, 33, 39]);
         }
 
         #[test]
         fn test_tokenize2() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens: Vec<&str> = tokenizer.tokenize_str("私の名前はマズレル野恵美です。");
             assert_eq!(tokens, vec!["私", "の", "名前", "は", "マズレル", "野", "恵美", "です", "。"]);
         }
 
         #[test]
         fn test_tokenize_junk() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens: Vec<&str> = tokenizer.tokenize_str("関西国werwerママママ空港");
             assert_eq!(tokens, vec!["関西", "国", "werwer", "ママ", "ママ", "空港"]);
         }
 
         #[test]
         fn test_tokenize_search_mode() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens: Vec<&str> = tokenizer.tokenize_str("関西国際空港");
             assert_eq!(tokens, vec!["関西", "国際", "空港"]);
         }
 
         #[test]
         fn test_tokenize_sumomomomo() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens: Vec<&str> = tokenizer.tokenize_str("すもももももももものうち");
             assert_eq!(tokens, vec!["すもも", "も", "もも", "も", "もも", "の", "うち"]);
         }
 
         #[test]
         fn test_mukigen_search() {
-            let mut tokenizer = Tokenizer::for_search();
+            let mut tokenizer = Tokenizer::for_search().unwrap();
             let tokens: Vec<&str> = tokenizer.tokenize_str("無期限に—でもどの種を?");
             assert_eq!(tokens, vec!["無", "期限", "に", "—", "でも", "どの", "種", "を", "?"]);
         }
@@ -330,42 +613,42 @@ mod tests {
 
     #[test]
     fn test_gyoi() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("御意。 御意〜。");
         assert_eq!(tokens, vec!["御意", "。", " ", "御意", "〜", "。"]);
     }
 
     #[test]
     fn test_demoyorokobi() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("〜でも喜び");
         assert_eq!(tokens, vec!["〜", "でも", "喜び"]);
     }
 
     #[test]
     fn test_mukigen_normal2() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("—でも");
         assert_eq!(tokens, vec!["—", "でも"]);
     }
 
     #[test]
     fn test_atodedenwa() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("後で");
         assert_eq!(tokens, vec!["後で"]);
     }
 
     #[test]
     fn test_ikkagetsu() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("ーヶ月");
         assert_eq!(tokens, vec!["ーヶ", "月"]);
     }
 
     #[test]
     fn test_mukigen_normal() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("無期限に—でもどの種を?");
         assert_eq!(
             tokens,
@@ -375,28 +658,28 @@ mod tests {
 
     #[test]
     fn test_demo() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("――!!?");
         assert_eq!(tokens, vec!["――!!?"]);
     }
 
     #[test]
     fn test_kaikeishi() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("ジム・コガン");
         assert_eq!(tokens, vec!["ジム・コガン"]);
     }
 
     #[test]
     fn test_bruce() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("ブルース・モラン");
         assert_eq!(tokens, vec!["ブルース・モラン"]);
     }
 
     #[test]
     fn test_tokenize_real() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str(
             "本項で解説する地方病とは、山梨県における日本住血吸虫症の呼称であり、\
              長い間その原因が明らかにならず住民を苦しめた感染症である。",
@@ -452,22 +735,220 @@ mod tests {
 
     #[test]
     fn test_hitobito() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize_str("満々!");
         assert_eq!(tokens, &["満々", "!"]);
     }
 
     #[test]
     fn test_tokenize_short() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize(
             "日本住").into_iter().map(|token| token.text).collect();
         assert_eq!(tokens, vec!["日本", "住"]);
     }
 
+    #[test]
+    fn test_tokenize_detail() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let tokens = tokenizer.tokenize("僕は");
+        let readings: Vec<&str> = tokens
+            .iter()
+            .map(|token| tokenizer.detail(token).reading)
+            .collect();
+        assert_eq!(readings, vec!["ボク", "ハ"]);
+    }
+
+    #[test]
+    fn test_tokenize_iter() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let text = "御意。 御意〜。";
+        let expected: Vec<&str> = tokenizer.tokenize_str(text);
+        let tokens: Vec<&str> = tokenizer
+            .tokenize_iter(text)
+            .map(|token| token.text)
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_tokenize_into_reuses_buffer() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let mut tokens = Vec::new();
+        tokenizer.tokenize_str_into("僕は", &mut tokens);
+        assert_eq!(tokens, vec!["僕", "は"]);
+        tokenizer.tokenize_str_into("ここでは", &mut tokens);
+        assert_eq!(tokens, vec!["ここ", "で", "は"]);
+        tokenizer.tokenize_str_into("", &mut tokens);
+        assert!(tokens.is_empty());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_tokenize_batch() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let texts = ["僕は", "ここでは", "", "日本住"];
+        let batch: Vec<Vec<&str>> = tokenizer
+            .tokenize_batch(&texts)
+            .into_iter()
+            .map(|tokens| tokens.into_iter().map(|token| token.text).collect())
+            .collect();
+        let expected: Vec<Vec<&str>> = texts
+            .iter()
+            .map(|text| tokenizer.clone().tokenize_str(text))
+            .collect();
+        assert_eq!(batch, expected);
+    }
+
+    #[test]
+    fn test_tokenize_with_constraints() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let text = "関西国際空港";
+        let mut constraints = Constraints::default();
+        constraints.add_boundary(3);
+        let tokens: Vec<&str> = tokenizer
+            .tokenize_with_constraints(text, &constraints)
+            .unwrap()
+            .iter()
+            .map(|token| token.text)
+            .collect();
+        assert_eq!(tokens[0], "関");
+        assert_eq!(tokens.concat(), text);
+
+        let mut constraints = Constraints::default();
+        constraints.add_token(3..12, &[]);
+        let tokens: Vec<&str> = tokenizer
+            .tokenize_with_constraints(text, &constraints)
+            .unwrap()
+            .iter()
+            .map(|token| token.text)
+            .collect();
+        assert_eq!(tokens, vec!["関", "西国際", "空港"]);
+    }
+
+    #[test]
+    fn test_validate_constraints() {
+        let is_valid = |boundaries: &[usize], tokens: &[(usize, usize)]| {
+            let mut constraints = Constraints::default();
+            for &boundary in boundaries {
+                constraints.add_boundary(boundary);
+            }
+            for &(start, stop) in tokens {
+                constraints.add_token(start..stop, &[]);
+            }
+            constraints.validate("関西国際空港").is_ok()
+        };
+        assert!(is_valid(&[], &[]));
+        assert!(is_valid(&[0, 18], &[(0, 6)]));
+        assert!(is_valid(&[], &[(3, 9), (9, 18)]));
+        assert!(!is_valid(&[1], &[]));
+        assert!(!is_valid(&[21], &[]));
+        assert!(!is_valid(&[], &[(3, 3)]));
+        assert!(!is_valid(&[], &[(6, 3)]));
+        assert!(!is_valid(&[], &[(15, 21)]));
+        assert!(!is_valid(&[], &[(3, 9), (6, 12)]));
+    }
+
+    #[test]
+    fn test_tokenize_with_overlapping_constraints() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let mut constraints = Constraints::default();
+        constraints.add_token(0..6, &[]);
+        constraints.add_token(3..9, &[]);
+        assert!(tokenizer
+            .tokenize_with_constraints("関西国際空港", &constraints)
+            .is_err());
+    }
+
+    #[test]
+    fn test_tokenize_with_constraints_pos() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let mut constraints = Constraints::default();
+        constraints.add_token(0..6, &["名詞", "固有名詞"]);
+        let tokens = tokenizer
+            .tokenize_with_constraints("東京に行く", &constraints)
+            .unwrap();
+        assert_eq!(tokens[0].text, "東京");
+        assert_eq!(tokenizer.detail(&tokens[0]).pos()[..2], ["名詞", "固有名詞"]);
+
+        // No dictionary word matches: the token is inserted, with the requested
+        // part-of-speech.
+        let mut constraints = Constraints::default();
+        constraints.add_token(0..6, &["動詞", "自立"]);
+        let tokens = tokenizer
+            .tokenize_with_constraints("東京に行く", &constraints)
+            .unwrap();
+        assert_eq!(tokens[0].text, "東京");
+        let detail = tokenizer.detail(&tokens[0]);
+        assert_eq!(detail.pos(), ["動詞", "自立", "*", "*"]);
+        assert_eq!(detail.base_form, "東京");
+        assert_eq!(detail.reading, "*");
+
+        // Without a part-of-speech, the inserted token is unknown.
+        let mut constraints = Constraints::default();
+        constraints.add_token(0..9, &[]);
+        let tokens = tokenizer
+            .tokenize_with_constraints("東京に行く", &constraints)
+            .unwrap();
+        assert_eq!(tokens[0].text, "東京に");
+        assert_eq!(tokenizer.detail(&tokens[0]), WordDetail::UNKNOWN);
+    }
+
+    #[test]
+    fn test_tokenize_with_char_filter() {
+        let mut tokenizer = Tokenizer::normal().unwrap().with_char_filter(WidthFoldingCharFilter);
+        let tokens = tokenizer.tokenize("ｶﾀｶﾅを書く。ﾃﾞｰﾀ");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, vec!["ｶﾀｶﾅ", "を", "書く", "。", "ﾃﾞｰﾀ"]);
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "カタカナ");
+        assert_eq!(tokenizer.detail(&tokens[4]).reading, "データ");
+
+        // The voiced kana has no precomposed form, and folds to a katakana and a
+        // combining mark.
+        let texts = tokenizer.tokenize_str("ｱﾞを書く");
+        assert_eq!(texts, vec!["ｱ", "ﾞを", "書く"]);
+    }
+
+    #[test]
+    fn test_tokenize_with_expanding_char_filter() {
+        struct ExpandLimitedCompanyMark;
+
+        impl CharFilter for ExpandLimitedCompanyMark {
+            fn filter(&self, text: &str, output: &mut FilteredText) {
+                for (start, c) in text.char_indices() {
+                    let stop = start + c.len_utf8();
+                    if c == '㈲' {
+                        output.push_replacement("有限会社", start..stop);
+                    } else {
+                        output.push_unchanged(&text[start..stop], start);
+                    }
+                }
+            }
+        }
+
+        // The replacement is split across two tokens, both covering the original
+        // character.
+        let mut tokenizer = Tokenizer::normal().unwrap().with_char_filter(ExpandLimitedCompanyMark);
+        let tokens = tokenizer.tokenize("㈲東京");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, vec!["㈲", "㈲", "東京"]);
+        assert_eq!(tokenizer.detail(&tokens[0]).base_form, "有限");
+        assert_eq!(tokenizer.detail(&tokens[1]).base_form, "会社");
+    }
+
+    #[test]
+    fn test_tokenize_with_iteration_marks() {
+        let mut tokenizer =
+            Tokenizer::normal().unwrap().with_char_filter(IterationMarkCharFilter::default());
+        let tokens = tokenizer.tokenize("こゝろ");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].text, "こゝろ");
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "ココロ");
+    }
+
     #[test]
     fn test_tokenize_short2() {
-        let mut tokenizer = Tokenizer::normal();
+        let mut tokenizer = Tokenizer::normal().unwrap();
         let tokens: Vec<&str> = tokenizer.tokenize(
             "ここでは").into_iter().map(|token| token.text).collect();
         assert_eq!(tokens, vec!["ここ", "で", "は"]);
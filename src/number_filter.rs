@@ -0,0 +1,377 @@
+//! Normalization of Japanese numerals into Arabic numbers, as Lucene's
+//! `JapaneseNumberFilter`.
+//!
+//! Kanji numerals (`三十五万`), positional kanji numerals (`二〇一〇`), full-width
+//! digits (`１２３`), and mixed forms (`3.5億`) are all normalized, e.g. to `350000`.
+use crate::{Token, WordId};
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::Peekable;
+
+/// Non-negative decimal number, `mantissa / 10^scale`.
+#[derive(Clone, Copy, Debug, Default)]
+struct Decimal {
+    mantissa: u128,
+    scale: u32,
+}
+
+impl Decimal {
+    fn from_int(value: u32) -> Decimal {
+        Decimal {
+            mantissa: u128::from(value),
+            scale: 0,
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        self.mantissa == 0
+    }
+
+    fn mul_pow10(self, exponent: u32) -> Option<Decimal> {
+        if self.scale >= exponent {
+            return Some(Decimal {
+                mantissa: self.mantissa,
+                scale: self.scale - exponent,
+            });
+        }
+        let factor = 10u128.checked_pow(exponent - self.scale)?;
+        Some(Decimal {
+            mantissa: self.mantissa.checked_mul(factor)?,
+            scale: 0,
+        })
+    }
+
+    fn with_scale(self, scale: u32) -> Option<u128> {
+        self.mantissa
+            .checked_mul(10u128.checked_pow(scale - self.scale)?)
+    }
+
+    fn add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        Some(Decimal {
+            mantissa: self
+                .with_scale(scale)?
+                .checked_add(other.with_scale(scale)?)?,
+            scale,
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!(
+            "{:0>width$}",
+            self.mantissa,
+            width = self.scale as usize + 1
+        );
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}", integer)
+        } else {
+            write!(f, "{}.{}", integer, fraction)
+        }
+    }
+}
+
+fn digit_value(c: char) -> Option<u32> {
+    match c {
+        '0'..='9' => c.to_digit(10),
+        '０'..='９' => Some(c as u32 - '０' as u32),
+        '〇' => Some(0),
+        '一' => Some(1),
+        '二' => Some(2),
+        '三' => Some(3),
+        '四' => Some(4),
+        '五' => Some(5),
+        '六' => Some(6),
+        '七' => Some(7),
+        '八' => Some(8),
+        '九' => Some(9),
+        _ => None,
+    }
+}
+
+/// Returns the exponent of the units within a group of four digits.
+fn small_unit_exponent(c: char) -> Option<u32> {
+    match c {
+        '十' => Some(1),
+        '百' => Some(2),
+        '千' => Some(3),
+        _ => None,
+    }
+}
+
+/// Returns the exponent of the units of the groups of four digits.
+fn large_unit_exponent(c: char) -> Option<u32> {
+    match c {
+        '万' => Some(4),
+        '億' => Some(8),
+        '兆' => Some(12),
+        _ => None,
+    }
+}
+
+fn is_decimal_point(c: char) -> bool {
+    c == '.' || c == '．'
+}
+
+fn is_thousands_separator(c: char) -> bool {
+    c == ',' || c == '，'
+}
+
+/// Returns whether `c` is a digit or a unit read by `normalize_number`.
+fn is_numeral(c: char) -> bool {
+    digit_value(c).is_some() || small_unit_exponent(c).is_some() || large_unit_exponent(c).is_some()
+}
+
+/// Returns the Arabic form of a Japanese number, or `None` if `text` is not a
+/// well-formed number.
+///
+/// Trailing zeros of the decimals are removed, e.g. `1.50` is normalized to `1.5`.
+pub fn normalize_number(text: &str) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut total = Decimal::default();
+    // the value of the current group of four digits, and of the digits being read.
+    let mut group = Decimal::default();
+    let mut digits: Option<Decimal> = None;
+    let mut is_fraction = false;
+    let mut last_small_unit = u32::MAX;
+    let mut last_large_unit = u32::MAX;
+    let mut char_ord = 0;
+    while char_ord < chars.len() {
+        let c = chars[char_ord];
+        let next_is_digit = chars
+            .get(char_ord + 1)
+            .map(|&next| digit_value(next).is_some())
+            .unwrap_or(false);
+        if let Some(value) = digit_value(c) {
+            let current = digits.unwrap_or_default();
+            digits = Some(Decimal {
+                mantissa: current
+                    .mantissa
+                    .checked_mul(10)?
+                    .checked_add(u128::from(value))?,
+                scale: current.scale + if is_fraction { 1 } else { 0 },
+            });
+        } else if is_decimal_point(c) {
+            if digits.is_none() || is_fraction || !next_is_digit {
+                return None;
+            }
+            is_fraction = true;
+        } else if is_thousands_separator(c) {
+            // Only valid between Arabic digits, followed by exactly three of them.
+            let group_len = chars[char_ord + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit() || ('０'..='９').contains(*c))
+                .count();
+            if digits.is_none() || is_fraction || group_len != 3 {
+                return None;
+            }
+        } else if let Some(exponent) = small_unit_exponent(c) {
+            if exponent >= last_small_unit {
+                return None;
+            }
+            let multiplier = digits.take().unwrap_or_else(|| Decimal::from_int(1));
+            group = group.add(multiplier.mul_pow10(exponent)?)?;
+            is_fraction = false;
+            last_small_unit = exponent;
+        } else if let Some(exponent) = large_unit_exponent(c) {
+            if exponent >= last_large_unit {
+                return None;
+            }
+            let mut multiplier = group.add(digits.take().unwrap_or_default())?;
+            if multiplier.is_zero() {
+                multiplier = Decimal::from_int(1);
+            }
+            total = total.add(multiplier.mul_pow10(exponent)?)?;
+            group = Decimal::default();
+            is_fraction = false;
+            last_small_unit = u32::MAX;
+            last_large_unit = exponent;
+        } else {
+            return None;
+        }
+        char_ord += 1;
+    }
+    let total = total.add(group)?.add(digits.unwrap_or_default())?;
+    Some(total.to_string())
+}
+
+/// Token output by the `NumberFilter`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NumberToken<'a> {
+    pub text: &'a str,
+    /// Word id of the first of the merged tokens.
+    pub word_id: WordId,
+    /// Arabic form of the number, if the token is a number.
+    pub number: Option<String>,
+}
+
+impl<'a> NumberToken<'a> {
+    fn from_token(token: &Token<'a>) -> NumberToken<'a> {
+        NumberToken {
+            text: token.text,
+            word_id: token.word_id,
+            number: None,
+        }
+    }
+}
+
+fn is_numeral_token(token: &Token) -> bool {
+    !token.text.is_empty() && token.text.chars().all(is_numeral)
+}
+
+fn is_separator_token(token: &Token) -> bool {
+    let mut chars = token.text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => is_decimal_point(c) || is_thousands_separator(c),
+        _ => false,
+    }
+}
+
+/// Merges the adjacent tokens forming a number, and normalizes it.
+///
+/// Numbers are sequences of tokens made of numerals, possibly separated by decimal
+/// points and thousands separators. Numerals are the fixed set of characters that
+/// `normalize_number` reads: ASCII and full-width digits, the kanji digits and `〇`,
+/// and the units from `十` to `兆`. This is a subset of the `NUMERIC` and
+/// `KANJINUMERIC` categories of the character definitions, which also include
+/// characters without a value here, such as superscripts or roman numerals.
+/// Sequences that do not form a well-formed number are left as they are.
+///
+/// The tokens must cover `text`, as the tokens returned by `Tokenizer::tokenize_iter(text)`.
+pub struct NumberFilter<'a, I: Iterator<Item = Token<'a>>> {
+    text: &'a str,
+    tokens: Peekable<I>,
+    // offset of the next token in `text`.
+    offset: usize,
+    pending: VecDeque<NumberToken<'a>>,
+}
+
+impl<'a, I: Iterator<Item = Token<'a>>> NumberFilter<'a, I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(text: &'a str, tokens: T) -> NumberFilter<'a, I> {
+        NumberFilter {
+            text,
+            tokens: tokens.into_iter().peekable(),
+            offset: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.next()?;
+        self.offset += token.text.len();
+        Some(token)
+    }
+}
+
+impl<'a, I: Iterator<Item = Token<'a>>> Iterator for NumberFilter<'a, I> {
+    type Item = NumberToken<'a>;
+
+    fn next(&mut self) -> Option<NumberToken<'a>> {
+        if let Some(token) = self.pending.pop_front() {
+            return Some(token);
+        }
+        let start = self.offset;
+        let token = self.next_token()?;
+        if !is_numeral_token(&token) {
+            return Some(NumberToken::from_token(&token));
+        }
+        let mut run = vec![token];
+        let mut stop = self.offset;
+        // separator that turned out not to be part of the number.
+        let mut trailing_separator = None;
+        while let Some(next_token) = self.tokens.peek() {
+            if is_numeral_token(next_token) {
+                run.push(self.next_token().unwrap());
+                stop = self.offset;
+            } else if is_separator_token(next_token) {
+                let separator = self.next_token().unwrap();
+                if self.tokens.peek().map(is_numeral_token).unwrap_or(false) {
+                    run.push(separator);
+                } else {
+                    trailing_separator = Some(separator);
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+        let text = &self.text[start..stop];
+        if let Some(number) = normalize_number(text) {
+            self.pending
+                .extend(trailing_separator.as_ref().map(NumberToken::from_token));
+            return Some(NumberToken {
+                text,
+                word_id: run[0].word_id,
+                number: Some(number),
+            });
+        }
+        self.pending.extend(
+            run.iter()
+                .chain(trailing_separator.as_ref())
+                .map(NumberToken::from_token),
+        );
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_number, NumberFilter};
+    use crate::Tokenizer;
+
+    #[test]
+    fn test_normalize_number() {
+        let normalize = |text: &str| normalize_number(text);
+        assert_eq!(normalize("三十五万").as_deref(), Some("350000"));
+        assert_eq!(normalize("35万").as_deref(), Some("350000"));
+        assert_eq!(normalize("350000").as_deref(), Some("350000"));
+        assert_eq!(normalize("３５万").as_deref(), Some("350000"));
+        assert_eq!(normalize("二〇一〇").as_deref(), Some("2010"));
+        assert_eq!(normalize("千二").as_deref(), Some("1002"));
+        assert_eq!(normalize("一億二千万").as_deref(), Some("120000000"));
+        assert_eq!(normalize("1万5千").as_deref(), Some("15000"));
+        assert_eq!(normalize("万").as_deref(), Some("10000"));
+        assert_eq!(normalize("3.5億").as_deref(), Some("350000000"));
+        assert_eq!(normalize("1.50").as_deref(), Some("1.5"));
+        assert_eq!(normalize("0.25").as_deref(), Some("0.25"));
+        assert_eq!(normalize("12,345").as_deref(), Some("12345"));
+        assert_eq!(normalize("二兆").as_deref(), Some("2000000000000"));
+        assert_eq!(normalize("1,2"), None);
+        assert_eq!(normalize("十百"), None);
+        assert_eq!(normalize("万億"), None);
+        assert_eq!(normalize("1..2"), None);
+        assert_eq!(normalize("円"), None);
+    }
+
+    #[test]
+    fn test_number_filter() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let normalize = |tokenizer: &mut Tokenizer, text: &str| -> Vec<(String, Option<String>)> {
+            NumberFilter::new(text, tokenizer.tokenize_iter(text))
+                .map(|token| (token.text.to_string(), token.number))
+                .collect()
+        };
+        assert_eq!(
+            normalize(&mut tokenizer, "三十五万円"),
+            vec![
+                ("三十五万".to_string(), Some("350000".to_string())),
+                ("円".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            normalize(&mut tokenizer, "3.5億円です。"),
+            vec![
+                ("3.5億".to_string(), Some("350000000".to_string())),
+                ("円".to_string(), None),
+                ("です".to_string(), None),
+                ("。".to_string(), None),
+            ]
+        );
+        let tokens = normalize(&mut tokenizer, "１２３.");
+        assert_eq!(tokens[0], ("１２３".to_string(), Some("123".to_string())));
+        assert_eq!(tokens[1], (".".to_string(), None));
+    }
+}
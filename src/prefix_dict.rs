@@ -1,104 +1,302 @@
-use crate::WordEntry;
+use crate::prefix_index::{DoubleArrayIndex, FstIndex, KeyAutomaton, PrefixIndex, PrefixIndexType};
+use crate::{DictData, KuromojiError, WordEntry};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 use std::ops::Deref;
-use tantivy_fst;
-use tantivy_fst::raw::Output;
 
-const IPAD_DATA: &'static [u8] = include_bytes!("../dict/dict.fst");
-const IPAD_VALS: &'static [u8] = include_bytes!("../dict/dict.vals");
+const IPAD_DATA: &[u8] = include_bytes!("../dict/dict.fst");
+const IPAD_VALS: &[u8] = include_bytes!("../dict/dict.vals");
+#[cfg(feature = "double-array")]
+const IPAD_DOUBLE_ARRAY: &[u8] = include_bytes!("../dict/dict.da");
 
-pub struct PrefixDict<Data = &'static [u8]> {
-    pub fst: tantivy_fst::raw::Fst<Data>,
-    vals_data: Data,
+/// Appends a group of word entries, sharing the same surface form, to the values
+/// data of a `PrefixDict`.
+///
+/// A group is serialized as its number of entries, as a `u32`, followed by the entries.
+/// The returned value is the offset of the group within `vals_data`. It is the value to
+/// associate to the surface form in the prefix index.
+pub fn serialize_word_entries(word_entries: &[WordEntry], vals_data: &mut Vec<u8>) -> u64 {
+    let offset = vals_data.len() as u64;
+    vals_data
+        .write_u32::<LittleEndian>(word_entries.len() as u32)
+        .unwrap();
+    for word_entry in word_entries {
+        // writing to a `Vec` cannot fail.
+        word_entry.serialize(vals_data).unwrap();
+    }
+    offset
 }
 
-impl Default for PrefixDict<&'static [u8]> {
-    fn default() -> PrefixDict<&'static [u8]> {
-        PrefixDict::from_static_slice(IPAD_DATA, IPAD_VALS).unwrap()
-    }
+/// Returns the group of word entries serialized at `offset` by `serialize_word_entries`.
+///
+/// Corrupt groups, pointing out of `vals_data`, are returned as empty.
+fn word_entries(vals_data: &[u8], offset: u64) -> impl Iterator<Item = WordEntry> + '_ {
+    let entries_data = vals_data
+        .get(offset as usize..)
+        .filter(|data| data.len() >= 4)
+        .and_then(|data| {
+            let len = LittleEndian::read_u32(data) as usize;
+            data[4..].get(..len.checked_mul(WordEntry::SERIALIZED_LEN)?)
+        })
+        .unwrap_or(&[]);
+    entries_data
+        .chunks_exact(WordEntry::SERIALIZED_LEN)
+        .map(WordEntry::deserialize)
+}
+
+pub struct PrefixDict<Data = DictData> {
+    index: Box<dyn PrefixIndex>,
+    vals_data: Data,
 }
 
 impl PrefixDict<&'static [u8]> {
     pub fn from_static_slice(
         fst_data: &'static [u8],
         vals_data: &'static [u8],
-    ) -> tantivy_fst::Result<PrefixDict> {
-        let fst = tantivy_fst::raw::Fst::new(fst_data)?;
-        Ok(PrefixDict { fst, vals_data })
+    ) -> Result<PrefixDict<&'static [u8]>, KuromojiError> {
+        let fst_index = FstIndex::new(fst_data)?;
+        Ok(PrefixDict::new(Box::new(fst_index), vals_data))
+    }
+}
+
+impl PrefixDict<DictData> {
+    /// Returns the dictionary embedded in the library.
+    pub fn load_default() -> Result<PrefixDict, KuromojiError> {
+        PrefixDict::from_data(
+            PrefixIndexType::Fst,
+            DictData::from_static(IPAD_DATA),
+            DictData::from_static(IPAD_VALS),
+        )
+    }
+
+    /// Returns the dictionary embedded in the library, indexed by a double-array trie.
+    ///
+    /// With the `double-array` feature, the trie is embedded in the library, as
+    /// written by `build-fst --prefix-index double-array`. Otherwise, it is built
+    /// from the FST, which takes a few seconds.
+    #[cfg(feature = "double-array")]
+    pub fn load_default_double_array() -> Result<PrefixDict, KuromojiError> {
+        PrefixDict::from_data(
+            PrefixIndexType::DoubleArray,
+            DictData::from_static(IPAD_DOUBLE_ARRAY),
+            DictData::from_static(IPAD_VALS),
+        )
+    }
+
+    /// Returns the dictionary embedded in the library, indexed by a double-array trie.
+    ///
+    /// With the `double-array` feature, the trie is embedded in the library, as
+    /// written by `build-fst --prefix-index double-array`. Otherwise, it is built
+    /// from the FST, which takes a few seconds.
+    #[cfg(not(feature = "double-array"))]
+    pub fn load_default_double_array() -> Result<PrefixDict, KuromojiError> {
+        let fst_index = FstIndex::new(IPAD_DATA)?;
+        let double_array_index = DoubleArrayIndex::build(&fst_index.key_values())?;
+        Ok(PrefixDict::new(
+            Box::new(double_array_index),
+            DictData::from_static(IPAD_VALS),
+        ))
+    }
+
+    /// Loads a dictionary from an index of the given type, as written by `build-fst`,
+    /// and its values.
+    pub fn from_data(
+        index_type: PrefixIndexType,
+        index_data: DictData,
+        vals_data: DictData,
+    ) -> Result<PrefixDict, KuromojiError> {
+        let index: Box<dyn PrefixIndex> = match index_type {
+            PrefixIndexType::Fst => Box::new(FstIndex::new(index_data)?),
+            PrefixIndexType::DoubleArray => Box::new(DoubleArrayIndex::new(index_data)?),
+        };
+        Ok(PrefixDict::new(index, vals_data))
     }
 }
 
 impl<D: Deref<Target = [u8]>> PrefixDict<D> {
-    pub fn prefix<'a>(&'a self, s: &'a str) -> impl Iterator<Item = (usize, WordEntry)> + 'a {
-        s.as_bytes()
-            .iter()
-            .scan(
-                (0, self.fst.root(), Output::zero()),
-                move |(prefix_len, node, output), &byte| {
-                    if let Some(b_index) = node.find_input(byte) {
-                        let transition = node.transition(b_index);
-                        *prefix_len += 1;
-                        *output = output.cat(transition.out);
-                        *node = self.fst.node(transition.addr);
-                        return Some((node.is_final(), *prefix_len, output.value()));
+    pub fn new(index: Box<dyn PrefixIndex>, vals_data: D) -> PrefixDict<D> {
+        PrefixDict { index, vals_data }
+    }
+
+    /// Calls `callback(prefix_len, word_entry)` for each word of the dictionary
+    /// that is a prefix of `s`.
+    pub fn for_each_prefix(&self, s: &str, mut callback: impl FnMut(usize, WordEntry)) {
+        let vals_data: &[u8] = &self.vals_data;
+        self.index
+            .common_prefix_search(s.as_bytes(), &mut |prefix_len, offset| {
+                for word_entry in word_entries(vals_data, offset) {
+                    callback(prefix_len, word_entry);
+                }
+            });
+    }
+
+    /// Returns the words of the dictionary whose surface form is `surface`.
+    pub fn get(&self, surface: &str) -> Vec<WordEntry> {
+        match self.index.get(surface.as_bytes()) {
+            Some(offset) => word_entries(&self.vals_data, offset).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Calls `callback(surface, word_entry)` for each word of the dictionary whose
+    /// surface form starts with `prefix`, in the lexicographic order of the surface forms.
+    pub fn for_each_word_with_prefix(
+        &self,
+        prefix: &str,
+        mut callback: impl FnMut(&str, WordEntry),
+    ) {
+        let vals_data: &[u8] = &self.vals_data;
+        self.index
+            .for_each_key_with_prefix(prefix.as_bytes(), &mut |key, offset| {
+                if let Ok(surface) = std::str::from_utf8(key) {
+                    for word_entry in word_entries(vals_data, offset) {
+                        callback(surface, word_entry);
                     }
-                    None
-                },
-            )
-            .filter_map(|(is_final, prefix_len, offset_len)| {
-                if is_final {
-                    Some((prefix_len, offset_len))
-                } else {
-                    None
                 }
-            })
-            .flat_map(move |(prefix_len, offset_len)| {
-                let len = offset_len & ((1u64 << 5) - 1u64);
-                let offset = offset_len >> 5u64;
-                let offset_bytes = (offset as usize) * WordEntry::SERIALIZED_LEN;
-                let data: &[u8] = &self.vals_data[offset_bytes..];
-                (0..len as usize).map(move |i| {
-                    (
-                        prefix_len,
-                        WordEntry::deserialize(&data[WordEntry::SERIALIZED_LEN * i..]),
-                    )
-                })
-            })
+            });
+    }
+
+    /// Calls `callback(surface, word_entry)` for each word of the dictionary whose
+    /// surface form is matched by `automaton`, in the lexicographic order of the
+    /// surface forms.
+    pub fn for_each_word_matching(
+        &self,
+        automaton: &KeyAutomaton,
+        mut callback: impl FnMut(&str, WordEntry),
+    ) {
+        let vals_data: &[u8] = &self.vals_data;
+        self.index.search(automaton, &mut |key, offset| {
+            if let Ok(surface) = std::str::from_utf8(key) {
+                for word_entry in word_entries(vals_data, offset) {
+                    callback(surface, word_entry);
+                }
+            }
+        });
+    }
+
+    /// Calls `callback` for each word entry of the dictionary, in the order of
+    /// the values data.
+    pub fn for_each_entry(&self, mut callback: impl FnMut(WordEntry)) {
+        let vals_data: &[u8] = &self.vals_data;
+        let mut offset = 0usize;
+        while offset + 4 <= vals_data.len() {
+            let len = LittleEndian::read_u32(&vals_data[offset..]) as usize;
+            for word_entry in word_entries(vals_data, offset as u64) {
+                callback(word_entry);
+            }
+            offset = offset
+                .saturating_add(4)
+                .saturating_add(len.saturating_mul(WordEntry::SERIALIZED_LEN));
+        }
+    }
+
+    /// Returns the words of the dictionary that are a prefix of `s`.
+    pub fn prefix(&self, s: &str) -> Vec<(usize, WordEntry)> {
+        let mut prefixes = Vec::new();
+        self.for_each_prefix(s, |prefix_len, word_entry| {
+            prefixes.push((prefix_len, word_entry))
+        });
+        prefixes
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::PrefixDict;
+    use super::{serialize_word_entries, PrefixDict};
+    use crate::prefix_index::FstIndex;
+    use crate::{WordEntry, WordId};
+    use tantivy_fst::MapBuilder;
 
     #[test]
     fn test_fst_prefix_2() {
-        let prefix_dict = PrefixDict::default();
-        let count_prefix = prefix_dict.prefix("—でも").count();
+        let prefix_dict = PrefixDict::load_default().unwrap();
+        let count_prefix = prefix_dict.prefix("—でも").len();
         assert_eq!(count_prefix, 1);
     }
 
     #[test]
     fn test_fst_prefix_tilde() {
-        let prefix_dict = PrefixDict::default();
-        let count_prefix = prefix_dict.prefix("〜").count();
+        let prefix_dict = PrefixDict::load_default().unwrap();
+        let count_prefix = prefix_dict.prefix("〜").len();
         assert_eq!(count_prefix, 2);
     }
 
     #[test]
     fn test_fst_ikkagetsu() {
-        let prefix_dict = PrefixDict::default();
-        let count_prefix = prefix_dict.prefix("ー").count();
+        let prefix_dict = PrefixDict::load_default().unwrap();
+        let count_prefix = prefix_dict.prefix("ー").len();
         assert_eq!(count_prefix, 0);
 
-        let count_prefix = prefix_dict.prefix("ヶ月").count();
+        let count_prefix = prefix_dict.prefix("ヶ月").len();
         assert_eq!(count_prefix, 1);
     }
 
+    #[test]
+    fn test_double_array_same_as_fst() {
+        let fst_prefix_dict = PrefixDict::load_default().unwrap();
+        let double_array_prefix_dict = PrefixDict::load_default_double_array().unwrap();
+        for text in &[
+            "—でも",
+            "〜",
+            "ー",
+            "ヶ月",
+            "すもももももももものうち",
+            "関西国際空港",
+        ] {
+            assert_eq!(
+                fst_prefix_dict.prefix(text),
+                double_array_prefix_dict.prefix(text)
+            );
+        }
+    }
+
+    #[test]
+    fn test_many_homographs() {
+        let mut vals_data = Vec::new();
+        let word_entries: Vec<WordEntry> = (0..1_000u32)
+            .map(|word_id| WordEntry {
+                word_id: WordId(word_id),
+                word_cost: word_id as i16,
+                cost_id: 1,
+            })
+            .collect();
+        let value_a = serialize_word_entries(&word_entries[..1], &mut vals_data);
+        let value_aa = serialize_word_entries(&word_entries, &mut vals_data);
+        let mut fst_builder = MapBuilder::memory();
+        fst_builder.insert("a", value_a).unwrap();
+        fst_builder.insert("aa", value_aa).unwrap();
+        let fst_index = FstIndex::new(fst_builder.into_inner().unwrap()).unwrap();
+        let prefix_dict = PrefixDict::new(Box::new(fst_index), vals_data);
+        let prefixes = prefix_dict.prefix("aab");
+        assert_eq!(prefixes.len(), 1_001);
+        assert_eq!(prefixes[0], (1, word_entries[0]));
+        assert!(prefixes[1..]
+            .iter()
+            .map(|&(_, word_entry)| word_entry)
+            .eq(word_entries.iter().cloned()));
+    }
+
+    #[test]
+    fn test_get_and_words_with_prefix() {
+        let fst_prefix_dict = PrefixDict::load_default().unwrap();
+        let double_array_prefix_dict = PrefixDict::load_default_double_array().unwrap();
+        for prefix_dict in [fst_prefix_dict, double_array_prefix_dict].iter() {
+            assert_eq!(prefix_dict.get("すもも").len(), 1);
+            assert!(prefix_dict.get("すもももも").is_empty());
+            let mut words = Vec::new();
+            prefix_dict.for_each_word_with_prefix("関西国際", |surface, word_entry| {
+                words.push((surface.to_string(), word_entry))
+            });
+            assert_eq!(words.len(), 2);
+            assert_eq!(words[0].0, "関西国際空港");
+            assert_eq!(words[0].1, prefix_dict.get("関西国際空港")[0]);
+            assert_eq!(words[1].0, "関西国際空港会社連絡橋");
+        }
+    }
 
     #[test]
     fn test_fst_prefix_asterisk_symbol() {
-        let prefix_dict = PrefixDict::default();
-        let count_prefix = prefix_dict.prefix("※").count();
+        let prefix_dict = PrefixDict::load_default().unwrap();
+        let count_prefix = prefix_dict.prefix("※").len();
         assert_eq!(count_prefix, 1);
     }
 }
@@ -0,0 +1,717 @@
+use crate::typo_tolerance::{TypoAutomaton, TypoState};
+use crate::KuromojiError;
+use byteorder::{ByteOrder, LittleEndian};
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, SINK_STATE};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tantivy_fst::automaton::AlwaysMatch;
+use tantivy_fst::raw::{Fst, Output};
+use tantivy_fst::Automaton;
+use yada::builder::DoubleArrayBuilder;
+use yada::unit::Unit;
+
+/// Index used to find the dictionary words that start at a given position of a text.
+///
+/// Keys are the surface forms of the words, and values are opaque `u64`s,
+/// interpreted by `PrefixDict`.
+pub trait PrefixIndex: Send + Sync {
+    /// Calls `callback(prefix_len, value)` for each key of the index that is a prefix
+    /// of `text`, by increasing length.
+    fn common_prefix_search(&self, text: &[u8], callback: &mut dyn FnMut(usize, u64));
+
+    /// Returns the value of `key`, if it is in the index.
+    fn get(&self, key: &[u8]) -> Option<u64>;
+
+    /// Calls `callback(key, value)` for each key of the index starting with `prefix`,
+    /// in lexicographic order.
+    fn for_each_key_with_prefix(&self, prefix: &[u8], callback: &mut dyn FnMut(&[u8], u64));
+
+    /// Calls `callback(key, value)` for each key of the index matched by `automaton`,
+    /// in lexicographic order.
+    fn search(&self, automaton: &KeyAutomaton, callback: &mut dyn FnMut(&[u8], u64));
+}
+
+enum KeyAutomatonKind {
+    Levenshtein(levenshtein_automata::DFA),
+    Regex(tantivy_fst::Regex),
+    Typos(TypoAutomaton),
+}
+
+/// Automaton matching the keys of a `PrefixIndex`, for fuzzy and regular expression
+/// lookups.
+pub struct KeyAutomaton {
+    kind: KeyAutomatonKind,
+}
+
+impl KeyAutomaton {
+    /// Largest edit distance supported by `KeyAutomaton::levenshtein`.
+    pub const MAX_DISTANCE: u8 = 3;
+
+    /// Matches the keys within `max_distance` edits of `query`.
+    ///
+    /// Edits are insertions, deletions and substitutions of a character, and
+    /// transpositions of two adjacent characters.
+    pub fn levenshtein(query: &str, max_distance: u8) -> Result<KeyAutomaton, KuromojiError> {
+        if max_distance > KeyAutomaton::MAX_DISTANCE {
+            return Err(KuromojiError::Format(format!(
+                "Edit distance {} is too large. At most {} is supported.",
+                max_distance,
+                KeyAutomaton::MAX_DISTANCE
+            )));
+        }
+        // building the automaton builder is expensive, so they are shared.
+        static BUILDERS: [OnceLock<LevenshteinAutomatonBuilder>;
+            KeyAutomaton::MAX_DISTANCE as usize + 1] = [
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+        ];
+        let builder = BUILDERS[max_distance as usize]
+            .get_or_init(|| LevenshteinAutomatonBuilder::new(max_distance, true));
+        Ok(KeyAutomaton {
+            kind: KeyAutomatonKind::Levenshtein(builder.build_dfa(query)),
+        })
+    }
+
+    /// Matches the keys matched by the regular expression `pattern`, from their first
+    /// to their last byte.
+    ///
+    /// See `tantivy_fst::Regex` for the supported syntax.
+    pub fn regex(pattern: &str) -> Result<KeyAutomaton, KuromojiError> {
+        let regex = tantivy_fst::Regex::new(pattern).map_err(|err| {
+            KuromojiError::Format(format!("Invalid regex {:?}: {}", pattern, err))
+        })?;
+        Ok(KeyAutomaton {
+            kind: KeyAutomatonKind::Regex(regex),
+        })
+    }
+
+    /// Returns the edit distance between `key` and the query of a Levenshtein automaton.
+    pub(crate) fn distance(&self, key: &[u8]) -> Option<u8> {
+        match &self.kind {
+            KeyAutomatonKind::Levenshtein(dfa) => match dfa.eval(key) {
+                Distance::Exact(distance) => Some(distance),
+                Distance::AtLeast(_) => None,
+            },
+            KeyAutomatonKind::Regex(_) | KeyAutomatonKind::Typos(_) => None,
+        }
+    }
+
+    /// Matches the keys within one edit of a prefix of the text of `automaton`.
+    pub(crate) fn typos(automaton: TypoAutomaton) -> KeyAutomaton {
+        KeyAutomaton {
+            kind: KeyAutomatonKind::Typos(automaton),
+        }
+    }
+
+    /// Returns the automaton of a typo automaton, so that it can be reused.
+    pub(crate) fn into_typo_automaton(self) -> Option<TypoAutomaton> {
+        match self.kind {
+            KeyAutomatonKind::Typos(automaton) => Some(automaton),
+            KeyAutomatonKind::Levenshtein(_) | KeyAutomatonKind::Regex(_) => None,
+        }
+    }
+
+    /// Returns the numbers of characters of the prefixes of the text of a typo
+    /// automaton at exactly one edit from `key`.
+    pub(crate) fn typo_spans(&self, key: &[u8]) -> Vec<usize> {
+        match &self.kind {
+            KeyAutomatonKind::Typos(automaton) => automaton.typo_spans(key),
+            KeyAutomatonKind::Levenshtein(_) | KeyAutomatonKind::Regex(_) => Vec::new(),
+        }
+    }
+}
+
+/// State of a `KeyAutomaton`.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyAutomatonState(KeyStateKind);
+
+#[derive(Clone, Copy, Debug)]
+enum KeyStateKind {
+    Dfa(usize),
+    Typos(TypoState),
+}
+
+impl KeyAutomatonState {
+    fn dfa(self) -> Option<usize> {
+        match self.0 {
+            KeyStateKind::Dfa(state) => Some(state),
+            KeyStateKind::Typos(_) => None,
+        }
+    }
+
+    fn typos(self) -> Option<TypoState> {
+        match self.0 {
+            KeyStateKind::Typos(state) => Some(state),
+            KeyStateKind::Dfa(_) => None,
+        }
+    }
+}
+
+impl Automaton for KeyAutomaton {
+    // `None` is the state from which no key can be matched.
+    type State = Option<KeyAutomatonState>;
+
+    fn start(&self) -> Option<KeyAutomatonState> {
+        let state = match &self.kind {
+            KeyAutomatonKind::Levenshtein(dfa) => KeyStateKind::Dfa(dfa.initial_state() as usize),
+            KeyAutomatonKind::Regex(regex) => KeyStateKind::Dfa(regex.start()?),
+            KeyAutomatonKind::Typos(automaton) => KeyStateKind::Typos(automaton.start()),
+        };
+        Some(KeyAutomatonState(state))
+    }
+
+    fn is_match(&self, state: &Option<KeyAutomatonState>) -> bool {
+        match &self.kind {
+            KeyAutomatonKind::Levenshtein(dfa) => state
+                .and_then(KeyAutomatonState::dfa)
+                .map(|state| dfa.is_match(&(state as u32)))
+                .unwrap_or(false),
+            KeyAutomatonKind::Regex(regex) => {
+                regex.is_match(&state.and_then(KeyAutomatonState::dfa))
+            }
+            KeyAutomatonKind::Typos(automaton) => state
+                .and_then(KeyAutomatonState::typos)
+                .map(|state| automaton.is_match(&state))
+                .unwrap_or(false),
+        }
+    }
+
+    fn can_match(&self, state: &Option<KeyAutomatonState>) -> bool {
+        state.is_some()
+    }
+
+    fn accept(&self, state: &Option<KeyAutomatonState>, byte: u8) -> Option<KeyAutomatonState> {
+        let state = (*state)?;
+        let state = match &self.kind {
+            KeyAutomatonKind::Levenshtein(dfa) => {
+                let state = dfa.transition(state.dfa()? as u32, byte);
+                if state == SINK_STATE {
+                    return None;
+                }
+                KeyStateKind::Dfa(state as usize)
+            }
+            KeyAutomatonKind::Regex(regex) => KeyStateKind::Dfa(regex.accept(&state.dfa(), byte)?),
+            KeyAutomatonKind::Typos(automaton) => {
+                KeyStateKind::Typos(automaton.accept(&state.typos()?, byte)?)
+            }
+        };
+        Some(KeyAutomatonState(state))
+    }
+}
+
+/// Implementations of `PrefixIndex` shipped with the library.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PrefixIndexType {
+    Fst,
+    DoubleArray,
+}
+
+impl PrefixIndexType {
+    /// Name of the dictionary file holding an index of this type.
+    pub fn filename(self) -> &'static str {
+        match self {
+            PrefixIndexType::Fst => "dict.fst",
+            PrefixIndexType::DoubleArray => "dict.da",
+        }
+    }
+}
+
+impl FromStr for PrefixIndexType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PrefixIndexType, String> {
+        match s {
+            "fst" => Ok(PrefixIndexType::Fst),
+            "double-array" => Ok(PrefixIndexType::DoubleArray),
+            _ => Err(format!(
+                "Unknown prefix index type {:?}. Expected `fst` or `double-array`.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PrefixIndexType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrefixIndexType::Fst => write!(f, "fst"),
+            PrefixIndexType::DoubleArray => write!(f, "double-array"),
+        }
+    }
+}
+
+/// `PrefixIndex` backed by a finite state transducer.
+pub struct FstIndex<Data> {
+    fst: Fst<Data>,
+}
+
+impl<Data: Deref<Target = [u8]>> FstIndex<Data> {
+    pub fn new(fst_data: Data) -> tantivy_fst::Result<FstIndex<Data>> {
+        let fst = Fst::new(fst_data)?;
+        Ok(FstIndex { fst })
+    }
+
+    /// Returns all of the `(key, value)` pairs of the index, sorted by key.
+    pub fn key_values(&self) -> Vec<(Vec<u8>, u64)> {
+        use tantivy_fst::Streamer;
+        let mut stream = self.fst.stream();
+        let mut key_values = Vec::new();
+        while let Some((key, output)) = stream.next() {
+            key_values.push((key.to_vec(), output.value()));
+        }
+        key_values
+    }
+}
+
+impl<Data: Deref<Target = [u8]> + Send + Sync> PrefixIndex for FstIndex<Data> {
+    fn common_prefix_search(&self, text: &[u8], callback: &mut dyn FnMut(usize, u64)) {
+        let mut node = self.fst.root();
+        let mut output = Output::zero();
+        for (prefix_len, &byte) in (1..).zip(text.iter()) {
+            if let Some(b_index) = node.find_input(byte) {
+                let transition = node.transition(b_index);
+                output = output.cat(transition.out);
+                node = self.fst.node(transition.addr);
+                if node.is_final() {
+                    callback(prefix_len, output.cat(node.final_output()).value());
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        self.fst.get(key).map(Output::value)
+    }
+
+    fn for_each_key_with_prefix(&self, prefix: &[u8], callback: &mut dyn FnMut(&[u8], u64)) {
+        use tantivy_fst::{IntoStreamer, Streamer};
+        let mut stream = self.fst.range().ge(prefix).into_stream();
+        while let Some((key, output)) = stream.next() {
+            if !key.starts_with(prefix) {
+                return;
+            }
+            callback(key, output.value());
+        }
+    }
+
+    fn search(&self, automaton: &KeyAutomaton, callback: &mut dyn FnMut(&[u8], u64)) {
+        use tantivy_fst::{IntoStreamer, Streamer};
+        let mut stream = self.fst.search(automaton).into_stream();
+        while let Some((key, output)) = stream.next() {
+            callback(key, output.value());
+        }
+    }
+}
+
+/// `PrefixIndex` backed by a double-array trie.
+///
+/// Common-prefix search is faster than with an `FstIndex`, at the expense of
+/// a larger index. Values are limited to 31 bits.
+///
+/// The trie is searched with bounds-checked accesses, so that corrupt data
+/// cannot make a search panic.
+///
+/// The data holds the number of units of the trie, as a `u32`, followed by its
+/// 4-byte units, the first one being the root. The units are followed by the child
+/// labels of each unit, so that the keys can be enumerated without probing all
+/// of the labels: the label of its first child, and the label of its next sibling,
+/// 0 if there is none. Siblings are in increasing order of label.
+pub struct DoubleArrayIndex<Data: Deref<Target = [u8]>> {
+    data: Data,
+    num_units: usize,
+}
+
+impl<Data: Deref<Target = [u8]>> DoubleArrayIndex<Data> {
+    /// Loads a double-array trie, as written by `build-fst`.
+    ///
+    /// Fails if the data does not hold a non-empty array of units and their child
+    /// labels.
+    pub fn new(data: Data) -> Result<DoubleArrayIndex<Data>, KuromojiError> {
+        let num_units = data.get(..4).map(LittleEndian::read_u32).unwrap_or(0) as usize;
+        if num_units == 0 || data.len() != 4 + num_units * 6 {
+            return Err(KuromojiError::Format(format!(
+                "Double-array trie of {} bytes, expected a non-empty array of units",
+                data.len()
+            )));
+        }
+        Ok(DoubleArrayIndex { data, num_units })
+    }
+
+    fn unit(&self, unit_id: usize) -> Option<Unit> {
+        if unit_id >= self.num_units {
+            return None;
+        }
+        let unit_data = &self.data[4 + unit_id * 4..];
+        Some(Unit::from_u32(LittleEndian::read_u32(unit_data)))
+    }
+
+    /// Returns the label of the first child of `unit_id`, and the label of its
+    /// next sibling, 0 if there is none.
+    fn child_labels(&self, unit_id: usize) -> (u8, u8) {
+        if unit_id >= self.num_units {
+            return (0, 0);
+        }
+        let offset = 4 + self.num_units * 4 + unit_id * 2;
+        (self.data[offset], self.data[offset + 1])
+    }
+
+    /// Returns the value of the key ending at the node `unit_id`, if any.
+    fn value(&self, unit_id: usize, unit: Unit) -> Option<u64> {
+        if !unit.has_leaf() {
+            return None;
+        }
+        // keys are terminated by a `\0` label, leading to a leaf holding the value.
+        let leaf_id = (unit.offset() ^ unit_id as u32) as usize;
+        let leaf = self.unit(leaf_id).filter(Unit::is_leaf)?;
+        Some(leaf.value() as u64)
+    }
+
+    /// Returns the id of the child of the node `unit_id` with the given label.
+    fn child(&self, unit_id: usize, unit: Unit, label: u8) -> Option<(usize, Unit)> {
+        let child_id = (unit.offset() ^ unit_id as u32 ^ label as u32) as usize;
+        let child = self.unit(child_id)?;
+        if child.label() != label as u32 {
+            return None;
+        }
+        Some((child_id, child))
+    }
+
+    /// Visits the keys of the subtree of the node `unit_id` matched by `automaton`,
+    /// `key` being the key of the node, and `state` the state of `automaton` after `key`.
+    fn visit_keys<A: Automaton>(
+        &self,
+        unit_id: usize,
+        unit: Unit,
+        key: &mut Vec<u8>,
+        automaton: &A,
+        state: A::State,
+        callback: &mut dyn FnMut(&[u8], u64),
+    ) {
+        if !automaton.can_match(&state) {
+            return;
+        }
+        if automaton.is_match(&state) {
+            if let Some(value) = self.value(unit_id, unit) {
+                callback(key, value);
+            }
+        }
+        let (mut label, _) = self.child_labels(unit_id);
+        while label != 0 {
+            let (child_id, child) = match self.child(unit_id, unit, label) {
+                Some(child) => child,
+                None => return,
+            };
+            let child_state = automaton.accept(&state, label);
+            key.push(label);
+            self.visit_keys(child_id, child, key, automaton, child_state, callback);
+            key.pop();
+            // labels increase along the siblings, so that corrupt data cannot make
+            // this loop forever.
+            let (_, next_label) = self.child_labels(child_id);
+            if next_label <= label {
+                return;
+            }
+            label = next_label;
+        }
+    }
+}
+
+impl DoubleArrayIndex<Vec<u8>> {
+    /// Builds a double-array trie.
+    ///
+    /// Keys must be sorted and must not contain any `\0` byte.
+    pub fn build<K: AsRef<[u8]>>(
+        key_values: &[(K, u64)],
+    ) -> Result<DoubleArrayIndex<Vec<u8>>, KuromojiError> {
+        let mut keyset: Vec<(&[u8], u32)> = Vec::with_capacity(key_values.len());
+        for (key, value) in key_values {
+            let key = key.as_ref();
+            if key.contains(&0u8) {
+                return Err(KuromojiError::Format(format!(
+                    "Key {:?} contains a \\0 byte",
+                    key
+                )));
+            }
+            if *value >= (1u64 << 31) {
+                return Err(KuromojiError::Format(format!(
+                    "Value {} of key {:?} does not fit in a double-array trie (31 bits)",
+                    value,
+                    String::from_utf8_lossy(key)
+                )));
+            }
+            keyset.push((key, *value as u32));
+        }
+        let units_data = DoubleArrayBuilder::build(&keyset).ok_or_else(|| {
+            KuromojiError::Format("Failed to build the double-array trie".to_string())
+        })?;
+        let num_units = units_data.len() / 4;
+        let mut data = Vec::with_capacity(4 + num_units * 6);
+        data.extend_from_slice(&(num_units as u32).to_le_bytes());
+        data.extend_from_slice(&units_data);
+        data.resize(4 + num_units * 6, 0);
+        let index = DoubleArrayIndex::new(data)?;
+
+        // the keys being sorted, the children of each node are found in increasing
+        // order of label.
+        let mut child_labels = vec![(0u8, 0u8); num_units];
+        let mut last_child: Vec<Option<(u8, usize)>> = vec![None; num_units];
+        for (key, _) in &keyset {
+            let mut unit_id = 0;
+            let mut unit = index.unit(unit_id).unwrap();
+            for &label in key.iter() {
+                let (child_id, child) = index.child(unit_id, unit, label).ok_or_else(|| {
+                    KuromojiError::Format("Inconsistent double-array trie".to_string())
+                })?;
+                let is_new_child =
+                    last_child[unit_id].is_none_or(|(last_label, _)| last_label < label);
+                if is_new_child {
+                    match last_child[unit_id] {
+                        Some((_, last_child_id)) => child_labels[last_child_id].1 = label,
+                        None => child_labels[unit_id].0 = label,
+                    }
+                    last_child[unit_id] = Some((label, child_id));
+                }
+                unit_id = child_id;
+                unit = child;
+            }
+        }
+        let mut data = index.data;
+        for (unit_id, (first_child, next_sibling)) in child_labels.into_iter().enumerate() {
+            data[4 + num_units * 4 + unit_id * 2] = first_child;
+            data[4 + num_units * 4 + unit_id * 2 + 1] = next_sibling;
+        }
+        DoubleArrayIndex::new(data)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..]
+    }
+}
+
+impl<Data: Deref<Target = [u8]> + Send + Sync> PrefixIndex for DoubleArrayIndex<Data> {
+    fn common_prefix_search(&self, text: &[u8], callback: &mut dyn FnMut(usize, u64)) {
+        let mut unit_id = 0;
+        let mut unit = match self.unit(unit_id) {
+            Some(unit) => unit,
+            None => return,
+        };
+        for (prefix_len, &label) in (1..).zip(text) {
+            if label == 0 {
+                return;
+            }
+            match self.child(unit_id, unit, label) {
+                Some((child_id, child)) => {
+                    unit_id = child_id;
+                    unit = child;
+                }
+                None => return,
+            }
+            if let Some(value) = self.value(unit_id, unit) {
+                callback(prefix_len, value);
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        let mut unit_id = 0;
+        let mut unit = self.unit(unit_id)?;
+        for &label in key {
+            if label == 0 {
+                return None;
+            }
+            let (child_id, child) = self.child(unit_id, unit, label)?;
+            unit_id = child_id;
+            unit = child;
+        }
+        self.value(unit_id, unit)
+    }
+
+    fn for_each_key_with_prefix(&self, prefix: &[u8], callback: &mut dyn FnMut(&[u8], u64)) {
+        let mut unit_id = 0;
+        let mut unit = match self.unit(unit_id) {
+            Some(unit) => unit,
+            None => return,
+        };
+        for &label in prefix {
+            if label == 0 {
+                return;
+            }
+            match self.child(unit_id, unit, label) {
+                Some((child_id, child)) => {
+                    unit_id = child_id;
+                    unit = child;
+                }
+                None => return,
+            }
+        }
+        self.visit_keys(
+            unit_id,
+            unit,
+            &mut prefix.to_vec(),
+            &AlwaysMatch,
+            (),
+            callback,
+        );
+    }
+
+    fn search(&self, automaton: &KeyAutomaton, callback: &mut dyn FnMut(&[u8], u64)) {
+        if let Some(unit) = self.unit(0) {
+            let state = automaton.start();
+            self.visit_keys(0, unit, &mut Vec::new(), automaton, state, callback);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DoubleArrayIndex, FstIndex, KeyAutomaton, PrefixIndex, PrefixIndexType};
+    use tantivy_fst::MapBuilder;
+
+    fn prefixes(index: &dyn PrefixIndex, text: &str) -> Vec<(usize, u64)> {
+        let mut prefixes = Vec::new();
+        index.common_prefix_search(text.as_bytes(), &mut |prefix_len, value| {
+            prefixes.push((prefix_len, value))
+        });
+        prefixes
+    }
+
+    #[test]
+    fn test_fst_and_double_array_agree() {
+        let key_values: Vec<(&str, u64)> = vec![
+            ("す", 3),
+            ("すもも", 1),
+            ("すももも", 7),
+            ("も", 2),
+            ("もも", 4),
+        ];
+        let mut fst_builder = MapBuilder::memory();
+        for &(key, value) in &key_values {
+            fst_builder.insert(key, value).unwrap();
+        }
+        let fst_index = FstIndex::new(fst_builder.into_inner().unwrap()).unwrap();
+        let double_array_index = DoubleArrayIndex::build(&key_values).unwrap();
+        for text in &["すもももももも", "もも", "も", "すす", "", "あ"] {
+            assert_eq!(
+                prefixes(&fst_index, text),
+                prefixes(&double_array_index, text)
+            );
+        }
+        assert_eq!(
+            prefixes(&double_array_index, "すももものうち"),
+            vec![(3, 3), (9, 1), (12, 7)]
+        );
+    }
+
+    #[test]
+    fn test_keys_with_prefix() {
+        let key_values: Vec<(&str, u64)> = vec![
+            ("す", 3),
+            ("すもも", 1),
+            ("すももも", 7),
+            ("も", 2),
+            ("もも", 4),
+        ];
+        let mut fst_builder = MapBuilder::memory();
+        for &(key, value) in &key_values {
+            fst_builder.insert(key, value).unwrap();
+        }
+        let fst_index = FstIndex::new(fst_builder.into_inner().unwrap()).unwrap();
+        let double_array_index = DoubleArrayIndex::build(&key_values).unwrap();
+        for index in [&fst_index as &dyn PrefixIndex, &double_array_index].iter() {
+            let keys_with_prefix = |prefix: &str| {
+                let mut keys = Vec::new();
+                index.for_each_key_with_prefix(prefix.as_bytes(), &mut |key, value| {
+                    keys.push((String::from_utf8(key.to_vec()).unwrap(), value))
+                });
+                keys
+            };
+            assert_eq!(
+                keys_with_prefix("すも"),
+                vec![("すもも".to_string(), 1), ("すももも".to_string(), 7)]
+            );
+            assert_eq!(keys_with_prefix("").len(), key_values.len());
+            assert!(keys_with_prefix("あ").is_empty());
+            assert_eq!(index.get("もも".as_bytes()), Some(4));
+            assert_eq!(index.get("すもも".as_bytes()), Some(1));
+            assert_eq!(index.get("すもｍ".as_bytes()), None);
+            assert_eq!(index.get(b""), None);
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let key_values: Vec<(&str, u64)> = vec![
+            ("すもも", 1),
+            ("すももも", 7),
+            ("もも", 2),
+            ("もものうち", 4),
+            ("李", 3),
+        ];
+        let mut fst_builder = MapBuilder::memory();
+        for &(key, value) in &key_values {
+            fst_builder.insert(key, value).unwrap();
+        }
+        let fst_index = FstIndex::new(fst_builder.into_inner().unwrap()).unwrap();
+        let double_array_index = DoubleArrayIndex::build(&key_values).unwrap();
+        for index in [&fst_index as &dyn PrefixIndex, &double_array_index].iter() {
+            let search = |automaton: &KeyAutomaton| {
+                let mut values = Vec::new();
+                index.search(automaton, &mut |_key, value| values.push(value));
+                values
+            };
+            let automaton = KeyAutomaton::levenshtein("すもま", 1).unwrap();
+            assert_eq!(search(&automaton), vec![1]);
+            assert_eq!(automaton.distance("すもも".as_bytes()), Some(1));
+            assert_eq!(automaton.distance("すもま".as_bytes()), Some(0));
+            assert_eq!(automaton.distance("もも".as_bytes()), None);
+            assert_eq!(
+                search(&KeyAutomaton::levenshtein("すもも", 2).unwrap()),
+                vec![1, 7, 2]
+            );
+            assert_eq!(
+                search(&KeyAutomaton::levenshtein("もす", 1).unwrap()),
+                vec![2]
+            );
+            assert_eq!(
+                search(&KeyAutomaton::regex("(す|も)も+").unwrap()),
+                vec![1, 7, 2]
+            );
+            assert!(search(&KeyAutomaton::regex("も").unwrap()).is_empty());
+        }
+        assert!(KeyAutomaton::levenshtein("すもも", 4).is_err());
+        assert!(KeyAutomaton::regex("(も").is_err());
+    }
+
+    #[test]
+    fn test_double_array_value_too_large() {
+        assert!(DoubleArrayIndex::build(&[("a", 1u64 << 31)]).is_err());
+    }
+
+    #[test]
+    fn test_double_array_invalid_data() {
+        assert!(DoubleArrayIndex::new(&[][..]).is_err());
+        assert!(DoubleArrayIndex::new(&[0u8; 10][..]).is_err());
+        // a single unit, without its child labels.
+        assert!(DoubleArrayIndex::new(&[1, 0, 0, 0, 0xff, 0xff, 0, 0][..]).is_err());
+        // a root whose children lie past the end of the units.
+        let index = DoubleArrayIndex::new(&[1, 0, 0, 0, 0xff, 0xff, 0, 0, 0xe3, 0][..]).unwrap();
+        assert_eq!(prefixes(&index, "すもも"), vec![]);
+        assert_eq!(index.get("すもも".as_bytes()), None);
+        let mut keys = 0;
+        index.for_each_key_with_prefix(b"", &mut |_, _| keys += 1);
+        assert_eq!(keys, 0);
+    }
+
+    #[test]
+    fn test_prefix_index_type_from_str() {
+        assert_eq!("fst".parse(), Ok(PrefixIndexType::Fst));
+        assert_eq!("double-array".parse(), Ok(PrefixIndexType::DoubleArray));
+        assert!("trie".parse::<PrefixIndexType>().is_err());
+    }
+}
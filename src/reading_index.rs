@@ -0,0 +1,164 @@
+//! Index of the words of a dictionary by reading.
+//!
+//! The index maps the readings, in katakana, to the words having this reading.
+//! It is made of an FST, associating each reading to an offset in the values
+//! data, and of the values data, holding the word entries and surface forms of
+//! the words.
+use crate::prefix_index::{FstIndex, PrefixIndex};
+use crate::{DictData, KuromojiError, WordEntry};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use tantivy_fst::MapBuilder;
+
+/// Name of the dictionary file holding the FST of the reading index.
+pub const READING_INDEX_FST: &str = "dict.readings.fst";
+/// Name of the dictionary file holding the values of the reading index.
+pub const READING_INDEX_VALS: &str = "dict.readings.vals";
+
+/// Builds a reading index from `(reading, surface, word_entry)` triples.
+///
+/// Returns the data of the FST and of the values, to be loaded with
+/// `ReadingIndex::from_data`.
+pub fn build_reading_index<'a>(
+    words: impl IntoIterator<Item = (&'a str, &'a str, WordEntry)>,
+) -> Result<(Vec<u8>, Vec<u8>), KuromojiError> {
+    let mut words_by_reading: BTreeMap<&str, Vec<(&str, WordEntry)>> = BTreeMap::new();
+    for (reading, surface, word_entry) in words {
+        words_by_reading
+            .entry(reading)
+            .or_default()
+            .push((surface, word_entry));
+    }
+    let mut vals_data = Vec::new();
+    let mut fst_builder = MapBuilder::memory();
+    for (reading, words) in &words_by_reading {
+        let offset = vals_data.len() as u64;
+        // writing to a `Vec` cannot fail.
+        vals_data
+            .write_u32::<LittleEndian>(words.len() as u32)
+            .unwrap();
+        for (surface, word_entry) in words {
+            word_entry.serialize(&mut vals_data).unwrap();
+            vals_data
+                .write_u32::<LittleEndian>(surface.len() as u32)
+                .unwrap();
+            vals_data.extend_from_slice(surface.as_bytes());
+        }
+        fst_builder.insert(reading, offset)?;
+    }
+    Ok((fst_builder.into_inner()?, vals_data))
+}
+
+/// Returns the words serialized at `offset` by `build_reading_index`.
+///
+/// Corrupt groups are truncated.
+fn words(vals_data: &[u8], offset: u64) -> Vec<(&str, WordEntry)> {
+    let mut words = Vec::new();
+    let mut data = match vals_data
+        .get(offset as usize..)
+        .filter(|data| data.len() >= 4)
+    {
+        Some(data) => data,
+        None => return words,
+    };
+    let num_words = LittleEndian::read_u32(data);
+    data = &data[4..];
+    for _ in 0..num_words {
+        if data.len() < WordEntry::SERIALIZED_LEN + 4 {
+            break;
+        }
+        let word_entry = WordEntry::deserialize(data);
+        let surface_len = LittleEndian::read_u32(&data[WordEntry::SERIALIZED_LEN..]) as usize;
+        data = &data[WordEntry::SERIALIZED_LEN + 4..];
+        let surface = match data.get(..surface_len).map(std::str::from_utf8) {
+            Some(Ok(surface)) => surface,
+            _ => break,
+        };
+        words.push((surface, word_entry));
+        data = &data[surface_len..];
+    }
+    words
+}
+
+/// Index of the words of a dictionary by reading.
+pub struct ReadingIndex<Data = DictData> {
+    index: FstIndex<Data>,
+    vals_data: Data,
+}
+
+impl<Data: Deref<Target = [u8]> + Send + Sync> ReadingIndex<Data> {
+    /// Loads a reading index, as built by `build_reading_index`.
+    pub fn from_data(fst_data: Data, vals_data: Data) -> Result<ReadingIndex<Data>, KuromojiError> {
+        Ok(ReadingIndex {
+            index: FstIndex::new(fst_data)?,
+            vals_data,
+        })
+    }
+
+    /// Returns the surface forms and entries of the words whose reading is `reading`.
+    pub fn get(&self, reading: &str) -> Vec<(&str, WordEntry)> {
+        match self.index.get(reading.as_bytes()) {
+            Some(offset) => words(&self.vals_data, offset),
+            None => Vec::new(),
+        }
+    }
+
+    /// Calls `callback(surface, word_entry)` for each word whose reading starts with
+    /// `prefix`, in the lexicographic order of the readings.
+    pub fn for_each_word_with_prefix(
+        &self,
+        prefix: &str,
+        mut callback: impl FnMut(&str, WordEntry),
+    ) {
+        let vals_data: &[u8] = &self.vals_data;
+        self.index
+            .for_each_key_with_prefix(prefix.as_bytes(), &mut |_reading, offset| {
+                for (surface, word_entry) in words(vals_data, offset) {
+                    callback(surface, word_entry);
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_reading_index, ReadingIndex};
+    use crate::{WordEntry, WordId};
+
+    fn word_entry(word_id: u32) -> WordEntry {
+        WordEntry {
+            word_id: WordId(word_id),
+            word_cost: 10,
+            cost_id: 3,
+        }
+    }
+
+    #[test]
+    fn test_reading_index() {
+        let words = vec![
+            ("トウキョウ", "東京", word_entry(0)),
+            ("スモモ", "すもも", word_entry(1)),
+            ("スモモ", "李", word_entry(2)),
+            ("トウキョウト", "東京都", word_entry(3)),
+        ];
+        let (fst_data, vals_data) = build_reading_index(words).unwrap();
+        let reading_index = ReadingIndex::from_data(fst_data, vals_data).unwrap();
+        assert_eq!(
+            reading_index.get("スモモ"),
+            vec![("すもも", word_entry(1)), ("李", word_entry(2))]
+        );
+        assert!(reading_index.get("スモ").is_empty());
+        let mut words = Vec::new();
+        reading_index.for_each_word_with_prefix("トウキョウ", |surface, word_entry| {
+            words.push((surface.to_string(), word_entry.word_id))
+        });
+        assert_eq!(
+            words,
+            vec![
+                ("東京".to_string(), WordId(0)),
+                ("東京都".to_string(), WordId(3))
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{Tokenizer, WordId};
+use std::io::{self, BufRead};
+use std::mem;
+use std::str;
+
+/// Default maximum length, in bytes, of the text buffered by a `TokenStream`
+/// before it is forced to tokenize it.
+pub const DEFAULT_MAX_CHUNK_LEN: usize = 1 << 16;
+
+/// Token emitted by a `TokenStream`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamToken {
+    pub text: String,
+    /// Offset of the token in the stream, in bytes.
+    pub offset: usize,
+    pub word_id: WordId,
+}
+
+/// Iterator over the tokens of a `BufRead`.
+///
+/// The stream is read incrementally and only buffered until a safe split point
+/// (`。` or `、`, as in `Tokenizer::tokenize`) is found, so the output is the same
+/// as tokenizing the whole text at once.
+///
+/// If the buffer grows over `max_chunk_len` without encountering any such split
+/// point, the buffer is split after its last whitespace, or at an arbitrary
+/// character boundary if it has none. Tokens around these forced splits may
+/// differ from whole-text tokenization.
+///
+/// See `Tokenizer::tokenize_reader`.
+pub struct TokenStream<'t, R> {
+    tokenizer: &'t mut Tokenizer,
+    reader: R,
+    max_chunk_len: usize,
+    // text read but not tokenized yet.
+    buffer: String,
+    // bytes of an incomplete UTF-8 sequence at the end of the input read so far.
+    incomplete_utf8: Vec<u8>,
+    // offset of `buffer` in the stream.
+    buffer_offset: usize,
+    tokens: Vec<StreamToken>,
+    eof: bool,
+}
+
+impl<'t, R: BufRead> TokenStream<'t, R> {
+    pub(crate) fn new(tokenizer: &'t mut Tokenizer, reader: R) -> TokenStream<'t, R> {
+        TokenStream {
+            tokenizer,
+            reader,
+            max_chunk_len: DEFAULT_MAX_CHUNK_LEN,
+            buffer: String::new(),
+            incomplete_utf8: Vec::new(),
+            buffer_offset: 0,
+            tokens: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Sets the length, in bytes, above which the buffered text is tokenized
+    /// even if no safe split point was found.
+    pub fn with_max_chunk_len(mut self, max_chunk_len: usize) -> Self {
+        assert!(max_chunk_len > 0);
+        self.max_chunk_len = max_chunk_len;
+        self
+    }
+
+    /// Reads from the underlying reader, and appends the result to the buffer.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        let num_bytes = {
+            let data = self.reader.fill_buf()?;
+            if data.is_empty() {
+                self.eof = true;
+                if !self.incomplete_utf8.is_empty() {
+                    return Err(invalid_utf8());
+                }
+                return Ok(());
+            }
+            self.incomplete_utf8.extend_from_slice(data);
+            data.len()
+        };
+        self.reader.consume(num_bytes);
+        let valid_len = match str::from_utf8(&self.incomplete_utf8) {
+            Ok(_) => self.incomplete_utf8.len(),
+            Err(utf8_error) => {
+                if utf8_error.error_len().is_some() {
+                    return Err(invalid_utf8());
+                }
+                utf8_error.valid_up_to()
+            }
+        };
+        // `valid_len` bytes were just checked to be valid UTF-8.
+        self.buffer
+            .push_str(str::from_utf8(&self.incomplete_utf8[..valid_len]).unwrap());
+        self.incomplete_utf8.drain(..valid_len);
+        Ok(())
+    }
+
+    /// Returns the length of the prefix of the buffer that can be tokenized,
+    /// or `None` if more data should be read first.
+    fn chunk_len(&self) -> Option<usize> {
+        if let Some(split_idx) = self.buffer.rfind(['。', '、']) {
+            return Some(split_idx + 3);
+        }
+        if self.eof {
+            return Some(self.buffer.len());
+        }
+        if self.buffer.len() < self.max_chunk_len {
+            return None;
+        }
+        if let Some((idx, c)) = self
+            .buffer
+            .char_indices()
+            .rev()
+            .find(|&(idx, c)| c.is_whitespace() && idx > 0)
+        {
+            return Some(idx + c.len_utf8());
+        }
+        let mut split_idx = self.max_chunk_len;
+        while !self.buffer.is_char_boundary(split_idx) {
+            split_idx -= 1;
+        }
+        Some(split_idx)
+    }
+
+    fn tokenize_chunk(&mut self, chunk_len: usize) {
+        let chunk: String = self.buffer.drain(..chunk_len).collect();
+        let mut offset = self.buffer_offset;
+        self.tokens
+            .extend(self.tokenizer.tokenize_iter(&chunk).map(|token| {
+                let stream_token = StreamToken {
+                    text: token.text.to_string(),
+                    offset,
+                    word_id: token.word_id,
+                };
+                offset += token.text.len();
+                stream_token
+            }));
+        // tokens are popped from the end.
+        self.tokens.reverse();
+        self.buffer_offset += chunk_len;
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream is not valid UTF-8")
+}
+
+impl<'t, R: BufRead> Iterator for TokenStream<'t, R> {
+    type Item = io::Result<StreamToken>;
+
+    fn next(&mut self) -> Option<io::Result<StreamToken>> {
+        loop {
+            if let Some(token) = self.tokens.pop() {
+                return Some(Ok(token));
+            }
+            if self.eof && self.buffer.is_empty() {
+                return None;
+            }
+            match self.chunk_len() {
+                Some(chunk_len) => {
+                    self.tokenize_chunk(chunk_len);
+                }
+                None => {
+                    if let Err(io_err) = self.fill_buffer() {
+                        // the stream cannot be resumed after an error.
+                        self.eof = true;
+                        mem::take(&mut self.buffer);
+                        return Some(Err(io_err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamToken;
+    use crate::Tokenizer;
+    use std::io::BufReader;
+
+    const TEXT: &str = "本項で解説する地方病とは、山梨県における日本住血吸虫症の呼称であり、\
+                        長い間その原因が明らかにならず住民を苦しめた感染症である。\n\
+                        僕は ここでは";
+
+    #[test]
+    fn test_stream_same_as_tokenize() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let expected: Vec<String> = tokenizer
+            .tokenize_str(TEXT)
+            .into_iter()
+            .map(|token| token.to_string())
+            .collect();
+        // a capacity of 1 byte forces multibyte characters to be split across reads.
+        let reader = BufReader::with_capacity(1, TEXT.as_bytes());
+        let tokens: Vec<StreamToken> = tokenizer
+            .tokenize_reader(reader)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let texts: Vec<String> = tokens.iter().map(|token| token.text.clone()).collect();
+        assert_eq!(texts, expected);
+        for token in &tokens {
+            assert_eq!(&TEXT[token.offset..][..token.text.len()], &token.text[..]);
+        }
+    }
+
+    #[test]
+    fn test_stream_max_chunk_len() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let text = "僕は ここでは 僕は";
+        let tokens: Vec<String> = tokenizer
+            .tokenize_reader(BufReader::with_capacity(1, text.as_bytes()))
+            .with_max_chunk_len(13)
+            .map(|token| token.unwrap().text)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec!["僕", "は", " ", "ここ", "で", "は", " ", "僕", "は"]
+        );
+    }
+
+    #[test]
+    fn test_stream_invalid_utf8() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let data: &[u8] = &[b'a', 0xe3, 0x81];
+        let results: Vec<_> = tokenizer.tokenize_reader(data).collect();
+        assert!(results.iter().any(|result| result.is_err()));
+    }
+}
@@ -0,0 +1,284 @@
+//! Estimation of the context ids and cost of words missing from the dictionary,
+//! like `mecab-dict-index -m`.
+use super::{char_type, DictionaryRow, Model, Rewriter};
+use crate::{CharacterDefinitions, KuromojiError};
+use std::collections::HashMap;
+
+/// Builds the IPADIC features of a word, given its part-of-speech and reading.
+///
+/// `pos` holds the comma-separated part-of-speech levels, optionally followed by the
+/// conjugation type and form. Missing fields are `*`. The surface form is used as the
+/// base form, and the reading as the pronunciation.
+///
+/// Fails if `pos` has more than these 6 fields.
+pub fn ipadic_features(
+    surface: &str,
+    pos: &str,
+    reading: &str,
+) -> Result<Vec<String>, KuromojiError> {
+    let mut features: Vec<String> = pos.split(',').map(str::to_string).collect();
+    if features.len() > NUM_POS_FEATURES {
+        return Err(KuromojiError::Format(format!(
+            "Part-of-speech {:?} has {} fields, expected at most {}",
+            pos,
+            features.len(),
+            NUM_POS_FEATURES
+        )));
+    }
+    features.resize(NUM_POS_FEATURES, "*".to_string());
+    features.push(surface.to_string());
+    features.push(reading.to_string());
+    features.push(reading.to_string());
+    Ok(features)
+}
+
+fn parse_id_def(content: &str) -> Result<Vec<(u32, String)>, KuromojiError> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(2, ' ');
+            let id = fields
+                .next()
+                .unwrap_or("")
+                .parse::<u32>()
+                .map_err(KuromojiError::from_error)?;
+            let attribute = fields.next().ok_or_else(|| {
+                KuromojiError::Format(format!("Expected `id attribute`, got {:?}", line))
+            })?;
+            Ok((id, attribute.to_string()))
+        })
+        .collect()
+}
+
+/// Context ids of `left-id.def` and `right-id.def`.
+pub struct ContextIds {
+    left_ids: HashMap<String, u32>,
+    right_ids: HashMap<String, u32>,
+    // Ids whose left and right attributes are both defined, and
+    // can be used as both the left and the right id of a word.
+    shared_ids: HashMap<(String, String), u32>,
+}
+
+impl ContextIds {
+    pub fn parse(left_id_def: &str, right_id_def: &str) -> Result<ContextIds, KuromojiError> {
+        let left_attributes = parse_id_def(left_id_def)?;
+        let right_attributes = parse_id_def(right_id_def)?;
+        let mut left_ids = HashMap::new();
+        for (id, attribute) in &left_attributes {
+            left_ids.entry(attribute.clone()).or_insert(*id);
+        }
+        let mut right_ids = HashMap::new();
+        let mut right_attribute_of_id = HashMap::new();
+        for (id, attribute) in &right_attributes {
+            right_ids.entry(attribute.clone()).or_insert(*id);
+            right_attribute_of_id.insert(*id, attribute);
+        }
+        let mut shared_ids = HashMap::new();
+        for (id, left_attribute) in left_attributes {
+            if let Some(&right_attribute) = right_attribute_of_id.get(&id) {
+                shared_ids
+                    .entry((left_attribute, right_attribute.clone()))
+                    .or_insert(id);
+            }
+        }
+        Ok(ContextIds {
+            left_ids,
+            right_ids,
+            shared_ids,
+        })
+    }
+
+    /// Returns the context id of a word, used as both its left and right id, as
+    /// the dictionary builder requires it.
+    ///
+    /// Fails if no id has both the left and the right attribute of the word.
+    pub fn lookup(&self, rewriter: &Rewriter, features: &[&str]) -> Result<u32, KuromojiError> {
+        let left_attribute = rewriter.left_rules.rewrite(features);
+        let right_attribute = rewriter.right_rules.rewrite(features);
+        if let Some(&id) = self
+            .shared_ids
+            .get(&(left_attribute.clone(), right_attribute.clone()))
+        {
+            return Ok(id);
+        }
+        let reason = if !self.left_ids.contains_key(&left_attribute) {
+            "no left id"
+        } else if !self.right_ids.contains_key(&right_attribute) {
+            "no right id"
+        } else {
+            "different left and right ids"
+        };
+        Err(KuromojiError::Format(format!(
+            "No context id for {:?}: {} with the attributes {:?} and {:?}",
+            features.join(","),
+            reason,
+            left_attribute,
+            right_attribute
+        )))
+    }
+}
+
+/// Estimates the cost of a word, given its features.
+pub trait CostEstimator {
+    fn estimate_cost(&self, surface: &str, features: &[&str]) -> Option<i32>;
+}
+
+/// Costs computed from the weights of a trained model.
+pub struct ModelCostEstimator<'a> {
+    pub model: &'a Model,
+    pub char_definitions: &'a CharacterDefinitions,
+}
+
+impl<'a> CostEstimator for ModelCostEstimator<'a> {
+    fn estimate_cost(&self, surface: &str, features: &[&str]) -> Option<i32> {
+        let char_type = char_type(self.char_definitions, surface);
+        Some(self.model.word_cost(features, char_type) as i32)
+    }
+}
+
+/// Number of leading features used to group the words of the same part-of-speech:
+/// the four part-of-speech levels, and the conjugation type and form.
+const NUM_POS_FEATURES: usize = 6;
+
+/// Costs and context ids of the existing words, grouped by part-of-speech.
+///
+/// Words are estimated from the words sharing their longest part-of-speech prefix.
+#[derive(Default)]
+pub struct PosStatistics {
+    costs: HashMap<String, Vec<i32>>,
+    context_ids: HashMap<String, HashMap<(u32, u32), usize>>,
+}
+
+fn pos_prefixes<'a>(features: &'a [&'a str]) -> impl Iterator<Item = String> + 'a {
+    let max_len = features.len().min(NUM_POS_FEATURES);
+    (1..=max_len)
+        .rev()
+        .map(move |len| features[..len].join(","))
+}
+
+impl PosStatistics {
+    pub fn new<'a>(rows: impl IntoIterator<Item = &'a DictionaryRow>) -> PosStatistics {
+        let mut pos_statistics = PosStatistics::default();
+        for row in rows {
+            let features = row.feature_refs();
+            for pos in pos_prefixes(&features) {
+                pos_statistics
+                    .costs
+                    .entry(pos.clone())
+                    .or_default()
+                    .push(row.word_cost);
+                *pos_statistics
+                    .context_ids
+                    .entry(pos)
+                    .or_default()
+                    .entry((row.left_id, row.right_id))
+                    .or_default() += 1;
+            }
+        }
+        for costs in pos_statistics.costs.values_mut() {
+            costs.sort_unstable();
+        }
+        pos_statistics
+    }
+
+    /// Most frequent `(left_id, right_id)` of the words of the same part-of-speech.
+    ///
+    /// This is a fallback for dictionaries that come without `left-id.def` and
+    /// `right-id.def`.
+    pub fn context_ids(&self, features: &[&str]) -> Option<(u32, u32)> {
+        pos_prefixes(features).find_map(|pos| {
+            let counts = self.context_ids.get(&pos)?;
+            counts
+                .iter()
+                .max_by_key(|(&ids, &count)| (count, std::cmp::Reverse(ids)))
+                .map(|(&ids, _)| ids)
+        })
+    }
+}
+
+impl CostEstimator for PosStatistics {
+    /// Median cost of the words of the same part-of-speech.
+    fn estimate_cost(&self, _surface: &str, features: &[&str]) -> Option<i32> {
+        pos_prefixes(features).find_map(|pos| {
+            let costs = self.costs.get(&pos)?;
+            Some(costs[costs.len() / 2])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(line: &str) -> DictionaryRow {
+        DictionaryRow::parse(line).unwrap()
+    }
+
+    #[test]
+    fn test_ipadic_features() {
+        assert_eq!(
+            ipadic_features("くるま", "名詞,一般", "クルマ").unwrap(),
+            vec![
+                "名詞",
+                "一般",
+                "*",
+                "*",
+                "*",
+                "*",
+                "くるま",
+                "クルマ",
+                "クルマ"
+            ]
+        );
+        assert!(ipadic_features("くるま", "名詞,一般,*,*,*,*,*", "クルマ").is_err());
+    }
+
+    #[test]
+    fn test_context_ids() {
+        let context_ids = ContextIds::parse(
+            "0 BOS/EOS,*,*,*,*,*,*\n1 名詞,一般,*,*,*,*,*\n2 名詞,一般,*,*,*,*,*\n\
+             3 名詞,固有名詞,*,*,*,*,*\n",
+            "0 BOS/EOS,*,*,*,*,*,*\n1 名詞,固有名詞,*,*,*,*,*\n2 名詞,一般,*,*,*,*,*\n",
+        )
+        .unwrap();
+        let rewriter = Rewriter::load_default().unwrap();
+        let lookup = |surface, pos, reading| {
+            let features = ipadic_features(surface, pos, reading).unwrap();
+            let features: Vec<&str> = features.iter().map(String::as_str).collect();
+            context_ids.lookup(&rewriter, &features)
+        };
+        assert_eq!(lookup("くるま", "名詞,一般", "クルマ").unwrap(), 2);
+        assert!(lookup("走る", "動詞,自立", "ハシル").is_err());
+        // the left id 3 and the right id 1 differ.
+        assert!(lookup("東京", "名詞,固有名詞", "トウキョウ").is_err());
+    }
+
+    #[test]
+    fn test_pos_statistics() {
+        let rows = vec![
+            row("山,1285,1285,5000,名詞,一般,*,*,*,*,山,ヤマ,ヤマ"),
+            row("川,1285,1285,6000,名詞,一般,*,*,*,*,川,カワ,カワ"),
+            row("海,1285,1285,7000,名詞,一般,*,*,*,*,海,ウミ,ウミ"),
+            row("東京,1293,1293,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー"),
+        ];
+        let pos_statistics = PosStatistics::new(&rows);
+        let features = ["名詞", "一般", "*", "*", "*", "*", "谷", "タニ", "タニ"];
+        assert_eq!(pos_statistics.estimate_cost("谷", &features), Some(6000));
+        assert_eq!(pos_statistics.context_ids(&features), Some((1285, 1285)));
+        // Falls back to the words sharing the longest part-of-speech prefix.
+        let features = [
+            "名詞",
+            "固有名詞",
+            "人名",
+            "*",
+            "*",
+            "*",
+            "太郎",
+            "タロウ",
+            "タロー",
+        ];
+        assert_eq!(pos_statistics.estimate_cost("太郎", &features), Some(3000));
+        assert_eq!(pos_statistics.estimate_cost("走る", &["動詞"]), None);
+    }
+}
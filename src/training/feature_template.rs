@@ -0,0 +1,275 @@
+//! Feature templates, as defined in MeCab's `feature.def`.
+//!
+//! Unigram templates describe a word, using the `%F[n]` macros (n-th feature of the word)
+//! and the `%t` macro (character type of the first character of the word).
+//! Bigram templates describe the connection of two words, using the `%L[n]` macros
+//! (n-th right context feature of the left word) and the `%R[n]` macros (n-th left
+//! context feature of the right word).
+//!
+//! The `?` variants (`%F?[n]`...) disable the template when the feature is `*`.
+use crate::KuromojiError;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Side {
+    Unigram,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Field {
+        side: Side,
+        index: usize,
+        optional: bool,
+    },
+    CharType,
+}
+
+/// A single `UNIGRAM` or `BIGRAM` template.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeatureTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl FeatureTemplate {
+    fn parse(template: &str, allowed_sides: &[Side]) -> Result<FeatureTemplate, KuromojiError> {
+        let invalid =
+            |msg: &str| KuromojiError::Format(format!("Invalid template {:?}: {}", template, msg));
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let macro_char = chars.next().ok_or_else(|| invalid("trailing %"))?;
+            let side = match macro_char {
+                '%' => {
+                    literal.push('%');
+                    continue;
+                }
+                't' => None,
+                'F' => Some(Side::Unigram),
+                'L' => Some(Side::Left),
+                'R' => Some(Side::Right),
+                _ => return Err(invalid(&format!("unknown macro %{}", macro_char))),
+            };
+            if !literal.is_empty() {
+                parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+            }
+            let side = if let Some(side) = side {
+                side
+            } else {
+                if !allowed_sides.contains(&Side::Unigram) {
+                    return Err(invalid("%t is only allowed in unigram templates"));
+                }
+                parts.push(TemplatePart::CharType);
+                continue;
+            };
+            if !allowed_sides.contains(&side) {
+                return Err(invalid(&format!("%{} is not allowed here", macro_char)));
+            }
+            let optional = chars.peek() == Some(&'?');
+            if optional {
+                chars.next();
+            }
+            if chars.next() != Some('[') {
+                return Err(invalid("expected ["));
+            }
+            let mut index_str = String::new();
+            for c in chars.by_ref() {
+                if c == ']' {
+                    break;
+                }
+                index_str.push(c);
+            }
+            let index = index_str
+                .parse::<usize>()
+                .map_err(|_| invalid(&format!("invalid index {:?}", index_str)))?;
+            parts.push(TemplatePart::Field {
+                side,
+                index,
+                optional,
+            });
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(FeatureTemplate { parts })
+    }
+
+    /// Returns the values of the macros of `side`, in order, or `None` if the template
+    /// does not apply.
+    fn eval_side<'a>(
+        &self,
+        side: Side,
+        features: &[&'a str],
+        char_type: &'a str,
+    ) -> Option<Vec<&'a str>> {
+        let mut values = Vec::new();
+        for part in &self.parts {
+            match *part {
+                TemplatePart::Field {
+                    side: part_side,
+                    index,
+                    optional,
+                } if part_side == side => {
+                    let value = *features.get(index)?;
+                    if optional && value == "*" {
+                        return None;
+                    }
+                    values.push(value);
+                }
+                TemplatePart::CharType if side == Side::Unigram => values.push(char_type),
+                _ => {}
+            }
+        }
+        Some(values)
+    }
+
+    /// Renders the template, given the values of its left (or unigram) macros and of
+    /// its right macros, as returned by `eval_side`.
+    fn render(&self, left_values: &[&str], right_values: &[&str]) -> String {
+        let mut left_it = left_values.iter();
+        let mut right_it = right_values.iter();
+        let mut feature = String::new();
+        for part in &self.parts {
+            let value = match part {
+                TemplatePart::Literal(literal) => literal.as_str(),
+                TemplatePart::Field {
+                    side: Side::Right, ..
+                } => right_it.next().unwrap(),
+                TemplatePart::Field { .. } | TemplatePart::CharType => left_it.next().unwrap(),
+            };
+            feature.push_str(value);
+        }
+        feature
+    }
+
+    /// Returns the values of the `%L` macros of a bigram template, given the right
+    /// context features of the left word, or `None` if the template does not apply.
+    ///
+    /// Evaluating each side separately makes it possible to compute the values of
+    /// a word once, and to combine them with many other words via `render_bigram`.
+    pub fn eval_left<'a>(&self, left_features: &[&'a str]) -> Option<Vec<&'a str>> {
+        self.eval_side(Side::Left, left_features, "")
+    }
+
+    /// Returns the values of the `%R` macros of a bigram template, given the left
+    /// context features of the right word, or `None` if the template does not apply.
+    pub fn eval_right<'a>(&self, right_features: &[&'a str]) -> Option<Vec<&'a str>> {
+        self.eval_side(Side::Right, right_features, "")
+    }
+
+    pub fn render_bigram(&self, left_values: &[&str], right_values: &[&str]) -> String {
+        self.render(left_values, right_values)
+    }
+}
+
+/// All of the templates of a `feature.def` file.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureTemplates {
+    pub unigram_templates: Vec<FeatureTemplate>,
+    pub bigram_templates: Vec<FeatureTemplate>,
+}
+
+impl FeatureTemplates {
+    pub fn parse(content: &str) -> Result<FeatureTemplates, KuromojiError> {
+        let mut feature_templates = FeatureTemplates::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let kind = fields.next().unwrap_or("");
+            let template = fields
+                .next()
+                .ok_or_else(|| KuromojiError::Format(format!("Missing template in {:?}", line)))?;
+            match kind {
+                "UNIGRAM" => feature_templates
+                    .unigram_templates
+                    .push(FeatureTemplate::parse(template, &[Side::Unigram])?),
+                "BIGRAM" => feature_templates
+                    .bigram_templates
+                    .push(FeatureTemplate::parse(
+                        template,
+                        &[Side::Left, Side::Right],
+                    )?),
+                _ => {
+                    return Err(KuromojiError::Format(format!(
+                        "Expected UNIGRAM or BIGRAM, got {:?}",
+                        kind
+                    )))
+                }
+            }
+        }
+        Ok(feature_templates)
+    }
+
+    /// Appends the unigram features of a word to `output`.
+    pub fn unigram_features(&self, features: &[&str], char_type: &str, output: &mut Vec<String>) {
+        for template in &self.unigram_templates {
+            if let Some(values) = template.eval_side(Side::Unigram, features, char_type) {
+                output.push(template.render(&values, &[]));
+            }
+        }
+    }
+
+    /// Appends the bigram features of the connection of two words to `output`.
+    ///
+    /// `left_features` are the right context features of the left word, and
+    /// `right_features` the left context features of the right word.
+    pub fn bigram_features(
+        &self,
+        left_features: &[&str],
+        right_features: &[&str],
+        output: &mut Vec<String>,
+    ) {
+        for template in &self.bigram_templates {
+            if let (Some(left_values), Some(right_values)) = (
+                template.eval_left(left_features),
+                template.eval_right(right_features),
+            ) {
+                output.push(template.render(&left_values, &right_values));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeatureTemplates;
+
+    #[test]
+    fn test_feature_templates() {
+        let feature_templates = FeatureTemplates::parse(
+            "# comment\n\
+             UNIGRAM U2:%F[0],%F?[1]\n\
+             UNIGRAM T1:%F[0]/%t\n\
+             UNIGRAM W:%F[9]\n\
+             BIGRAM B01:%L[0],%L?[1]/%R[0]\n",
+        )
+        .unwrap();
+        let mut features = Vec::new();
+        feature_templates.unigram_features(&["名詞", "一般", "*"], "KANJI", &mut features);
+        assert_eq!(features, vec!["U2:名詞,一般", "T1:名詞/KANJI"]);
+        features.clear();
+        feature_templates.unigram_features(&["記号", "*"], "SYMBOL", &mut features);
+        assert_eq!(features, vec!["T1:記号/SYMBOL"]);
+        features.clear();
+        feature_templates.bigram_features(&["助詞", "係助詞"], &["名詞"], &mut features);
+        assert_eq!(features, vec!["B01:助詞,係助詞/名詞"]);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(FeatureTemplates::parse("UNIGRAM U:%L[0]").is_err());
+        assert!(FeatureTemplates::parse("BIGRAM B:%t").is_err());
+        assert!(FeatureTemplates::parse("BIGRAM B:%L[x]").is_err());
+        assert!(FeatureTemplates::parse("TRIGRAM T:%F[0]").is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! Lattices of the training sentences, and the feature index they share.
+use super::{char_type, FeatureExtractor, TrainingDictionary};
+use crate::character_definition::CategoryId;
+use crate::corpus::{GoldToken, Sentence};
+use crate::prefix_index::{DoubleArrayIndex, PrefixIndex};
+use crate::KuromojiError;
+use std::collections::HashMap;
+
+/// Features of the beginning and end of sentence nodes.
+pub(crate) const BOS_FEATURES: &str = "BOS/EOS,*,*,*,*,*,*,*,*";
+
+const BOS_FEATURE_SET: u32 = 0;
+const BOS_ATTRIBUTE: u32 = 0;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum NodeSource {
+    Known(u32),
+    Unknown(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct NodeInfo {
+    feature_set: u32,
+    left_attribute: u32,
+    right_attribute: u32,
+}
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<String, u32>,
+    values: Vec<String>,
+}
+
+impl Interner {
+    fn get_or_insert(&mut self, value: String) -> u32 {
+        let values = &mut self.values;
+        *self.ids.entry(value).or_insert_with_key(|value| {
+            values.push(value.clone());
+            (values.len() - 1) as u32
+        })
+    }
+}
+
+/// Assigns ids to the features, and to the sets of features of nodes and edges.
+///
+/// Nodes with the same unigram features share the same feature set, and edges between
+/// the same context attributes share the same pair. Scores are computed once per set
+/// and once per pair.
+pub(crate) struct FeatureIndex<'a> {
+    extractor: &'a FeatureExtractor,
+    dictionary: &'a TrainingDictionary,
+    known_index: DoubleArrayIndex<Vec<u8>>,
+    known_groups: Vec<Vec<u32>>,
+    unknown_groups: Vec<Vec<u32>>,
+    features: Interner,
+    pub(crate) feature_sets: Vec<Vec<u32>>,
+    node_infos: HashMap<NodeSource, NodeInfo>,
+    left_attributes: Interner,
+    right_attributes: Interner,
+    pair_ids: HashMap<(u32, u32), u32>,
+    pub(crate) pair_features: Vec<Vec<u32>>,
+}
+
+impl<'a> FeatureIndex<'a> {
+    pub(crate) fn new(
+        extractor: &'a FeatureExtractor,
+        dictionary: &'a TrainingDictionary,
+    ) -> Result<FeatureIndex<'a>, KuromojiError> {
+        let mut surface_groups: HashMap<&str, Vec<u32>> = HashMap::new();
+        for (row_id, row) in dictionary.rows().enumerate() {
+            surface_groups
+                .entry(row.surface.as_str())
+                .or_default()
+                .push(row_id as u32);
+        }
+        let mut surfaces: Vec<&str> = surface_groups.keys().cloned().collect();
+        surfaces.sort_unstable();
+        let key_values: Vec<(&str, u64)> = surfaces
+            .iter()
+            .enumerate()
+            .map(|(group_id, &surface)| (surface, group_id as u64))
+            .collect();
+        let known_index = DoubleArrayIndex::build(&key_values)?;
+        let known_groups = surfaces
+            .iter()
+            .map(|surface| surface_groups.remove(surface).unwrap_or_default())
+            .collect();
+        let unknown_groups = dictionary
+            .char_definitions
+            .categories()
+            .iter()
+            .map(|category| {
+                dictionary
+                    .unknown_rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, row)| &row.surface == category)
+                    .map(|(row_id, _)| row_id as u32)
+                    .collect()
+            })
+            .collect();
+        let mut feature_index = FeatureIndex {
+            extractor,
+            dictionary,
+            known_index,
+            known_groups,
+            unknown_groups,
+            features: Interner::default(),
+            feature_sets: vec![Vec::new()],
+            node_infos: HashMap::new(),
+            left_attributes: Interner::default(),
+            right_attributes: Interner::default(),
+            pair_ids: HashMap::new(),
+            pair_features: Vec::new(),
+        };
+        let bos_features: Vec<&str> = BOS_FEATURES.split(',').collect();
+        let bos_left = extractor.left_attribute(&bos_features);
+        let bos_right = extractor.right_attribute(&bos_features);
+        feature_index.left_attributes.get_or_insert(bos_left);
+        feature_index.right_attributes.get_or_insert(bos_right);
+        Ok(feature_index)
+    }
+
+    pub(crate) fn num_features(&self) -> usize {
+        self.features.values.len()
+    }
+
+    pub(crate) fn feature_names(&self) -> &[String] {
+        &self.features.values
+    }
+
+    fn node_info(&mut self, features: &[&str], char_type: &str) -> NodeInfo {
+        let mut feature_set = Vec::new();
+        for feature in self.extractor.unigram_features(features, char_type) {
+            feature_set.push(self.features.get_or_insert(feature));
+        }
+        self.feature_sets.push(feature_set);
+        NodeInfo {
+            feature_set: (self.feature_sets.len() - 1) as u32,
+            left_attribute: self
+                .left_attributes
+                .get_or_insert(self.extractor.left_attribute(features)),
+            right_attribute: self
+                .right_attributes
+                .get_or_insert(self.extractor.right_attribute(features)),
+        }
+    }
+
+    fn cached_node_info(&mut self, source: NodeSource) -> NodeInfo {
+        if let Some(&node_info) = self.node_infos.get(&source) {
+            return node_info;
+        }
+        let dictionary = self.dictionary;
+        let node_info = match source {
+            NodeSource::Known(row_id) => {
+                let row = dictionary.row(row_id as usize);
+                let char_type = char_type(&dictionary.char_definitions, &row.surface);
+                self.node_info(&row.feature_refs(), char_type)
+            }
+            NodeSource::Unknown(row_id) => {
+                let row = &dictionary.unknown_rows[row_id as usize];
+                self.node_info(&row.feature_refs(), &row.surface)
+            }
+        };
+        self.node_infos.insert(source, node_info);
+        node_info
+    }
+
+    fn pair_id(&mut self, right_attribute: u32, left_attribute: u32) -> u32 {
+        if let Some(&pair_id) = self.pair_ids.get(&(right_attribute, left_attribute)) {
+            return pair_id;
+        }
+        let left_features: Vec<&str> = self.right_attributes.values[right_attribute as usize]
+            .split(',')
+            .collect();
+        let right_features: Vec<&str> = self.left_attributes.values[left_attribute as usize]
+            .split(',')
+            .collect();
+        let mut bigram_features = Vec::new();
+        self.extractor.feature_templates.bigram_features(
+            &left_features,
+            &right_features,
+            &mut bigram_features,
+        );
+        let pair_features = bigram_features
+            .into_iter()
+            .map(|feature| self.features.get_or_insert(feature))
+            .collect();
+        self.pair_features.push(pair_features);
+        let pair_id = (self.pair_features.len() - 1) as u32;
+        self.pair_ids
+            .insert((right_attribute, left_attribute), pair_id);
+        pair_id
+    }
+}
+
+struct Node {
+    stop: usize,
+    source: Option<NodeSource>,
+    info: NodeInfo,
+    // (left node, pair)
+    left_edges: Vec<(u32, u32)>,
+}
+
+/// Lattice of a training sentence, with its gold path.
+pub(crate) struct TrainingLattice {
+    nodes: Vec<Node>,
+    // Nodes of the gold path, including the BOS and EOS nodes.
+    gold_path: Vec<u32>,
+    gold_pairs: Vec<u32>,
+}
+
+fn matches_gold(node_features: &[&str], gold_token: &GoldToken, exact: bool) -> bool {
+    if exact {
+        return node_features.len() == gold_token.features.len()
+            && node_features
+                .iter()
+                .zip(&gold_token.features)
+                .all(|(node_feature, gold_feature)| node_feature == gold_feature);
+    }
+    node_features
+        .iter()
+        .zip(&gold_token.features)
+        .all(|(node_feature, gold_feature)| *node_feature == "*" || node_feature == gold_feature)
+}
+
+impl TrainingLattice {
+    pub(crate) fn build(sentence: &Sentence, feature_index: &mut FeatureIndex) -> TrainingLattice {
+        let text = sentence.text();
+        let len = text.len();
+        let bos_info = NodeInfo {
+            feature_set: BOS_FEATURE_SET,
+            left_attribute: BOS_ATTRIBUTE,
+            right_attribute: BOS_ATTRIBUTE,
+        };
+        let mut nodes = vec![Node {
+            stop: 0,
+            source: None,
+            info: bos_info,
+            left_edges: Vec::new(),
+        }];
+        let mut ends_at: Vec<Vec<u32>> = vec![Vec::new(); len + 1];
+        ends_at[0].push(0);
+        let mut gold_tokens: HashMap<usize, (usize, &GoldToken)> = HashMap::new();
+        let mut gold_offset = 0;
+        for gold_token in &sentence.tokens {
+            let stop = gold_offset + gold_token.surface.len();
+            gold_tokens.insert(gold_offset, (stop, gold_token));
+            gold_offset = stop;
+        }
+        let mut gold_path = vec![0u32];
+        let dictionary = feature_index.dictionary;
+        let char_definitions = &dictionary.char_definitions;
+        let mut unknown_word_end: Option<usize> = None;
+        for start in 0..len {
+            if ends_at[start].is_empty() {
+                continue;
+            }
+            let first_node = nodes.len();
+            let suffix = &text[start..];
+            let mut candidates: Vec<(usize, NodeSource)> = Vec::new();
+            {
+                let known_groups = &feature_index.known_groups;
+                feature_index.known_index.common_prefix_search(
+                    suffix.as_bytes(),
+                    &mut |prefix_len, group_id| {
+                        for &row_id in &known_groups[group_id as usize] {
+                            candidates.push((start + prefix_len, NodeSource::Known(row_id)));
+                        }
+                    },
+                );
+            }
+            let found = !candidates.is_empty();
+            if unknown_word_end.map(|index| index <= start).unwrap_or(true) {
+                let first_char = suffix.chars().next().unwrap();
+                let categories = char_definitions.lookup_categories(first_char);
+                for (category_ord, &category) in categories.iter().enumerate() {
+                    if let Some(stop) = unknown_word_stop(
+                        feature_index,
+                        category,
+                        category_ord,
+                        start,
+                        suffix,
+                        found,
+                    ) {
+                        for &row_id in &feature_index.unknown_groups[category.0] {
+                            candidates.push((stop, NodeSource::Unknown(row_id)));
+                        }
+                        unknown_word_end = Some(stop);
+                    }
+                }
+            }
+            for (stop, source) in candidates {
+                let info = feature_index.cached_node_info(source);
+                nodes.push(Node {
+                    stop,
+                    source: Some(source),
+                    info,
+                    left_edges: Vec::new(),
+                });
+            }
+            if let Some(&(gold_stop, gold_token)) = gold_tokens.get(&start) {
+                gold_path.push(find_or_add_gold_node(
+                    &mut nodes,
+                    first_node,
+                    gold_stop,
+                    gold_token,
+                    feature_index,
+                ));
+            }
+            for node_id in first_node..nodes.len() {
+                let left_attribute = nodes[node_id].info.left_attribute;
+                let left_edges: Vec<(u32, u32)> = ends_at[start]
+                    .iter()
+                    .map(|&left_node_id| {
+                        let right_attribute = nodes[left_node_id as usize].info.right_attribute;
+                        (
+                            left_node_id,
+                            feature_index.pair_id(right_attribute, left_attribute),
+                        )
+                    })
+                    .collect();
+                nodes[node_id].left_edges = left_edges;
+                ends_at[nodes[node_id].stop].push(node_id as u32);
+            }
+        }
+        let left_edges = ends_at[len]
+            .iter()
+            .map(|&left_node_id| {
+                let right_attribute = nodes[left_node_id as usize].info.right_attribute;
+                (
+                    left_node_id,
+                    feature_index.pair_id(right_attribute, BOS_ATTRIBUTE),
+                )
+            })
+            .collect();
+        nodes.push(Node {
+            stop: len,
+            source: None,
+            info: bos_info,
+            left_edges,
+        });
+        gold_path.push((nodes.len() - 1) as u32);
+        let gold_pairs = gold_path
+            .windows(2)
+            .map(|window| {
+                let left = &nodes[window[0] as usize];
+                let right = &nodes[window[1] as usize];
+                feature_index.pair_id(left.info.right_attribute, right.info.left_attribute)
+            })
+            .collect();
+        TrainingLattice {
+            nodes,
+            gold_path,
+            gold_pairs,
+        }
+    }
+
+    /// Adds the expected counts of the feature sets and pairs, minus their counts
+    /// on the gold path, to the expectations.
+    ///
+    /// Returns the negative log-likelihood of the gold path.
+    pub(crate) fn accumulate_expectations(
+        &self,
+        set_scores: &[f64],
+        pair_scores: &[f64],
+        set_expectations: &mut [f64],
+        pair_expectations: &mut [f64],
+    ) -> f64 {
+        let num_nodes = self.nodes.len();
+        let node_score = |node: &Node| set_scores[node.info.feature_set as usize];
+        let mut alpha = vec![f64::NEG_INFINITY; num_nodes];
+        alpha[0] = node_score(&self.nodes[0]);
+        for (node_id, node) in self.nodes.iter().enumerate().skip(1) {
+            let mut log_sum = f64::NEG_INFINITY;
+            for &(left_node_id, pair_id) in &node.left_edges {
+                log_sum = log_add(
+                    log_sum,
+                    alpha[left_node_id as usize] + pair_scores[pair_id as usize],
+                );
+            }
+            alpha[node_id] = log_sum + node_score(node);
+        }
+        let mut beta = vec![f64::NEG_INFINITY; num_nodes];
+        beta[num_nodes - 1] = 0f64;
+        for (node_id, node) in self.nodes.iter().enumerate().rev() {
+            let incoming = beta[node_id] + node_score(node);
+            for &(left_node_id, pair_id) in &node.left_edges {
+                let left_node_id = left_node_id as usize;
+                beta[left_node_id] =
+                    log_add(beta[left_node_id], incoming + pair_scores[pair_id as usize]);
+            }
+        }
+        let log_z = alpha[num_nodes - 1];
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            let node_marginal = (alpha[node_id] + beta[node_id] - log_z).exp();
+            set_expectations[node.info.feature_set as usize] += node_marginal;
+            let incoming = beta[node_id] + node_score(node) - log_z;
+            for &(left_node_id, pair_id) in &node.left_edges {
+                let edge_marginal =
+                    (alpha[left_node_id as usize] + pair_scores[pair_id as usize] + incoming).exp();
+                pair_expectations[pair_id as usize] += edge_marginal;
+            }
+        }
+        let mut gold_score = 0f64;
+        for &node_id in &self.gold_path {
+            let feature_set = self.nodes[node_id as usize].info.feature_set as usize;
+            gold_score += set_scores[feature_set];
+            set_expectations[feature_set] -= 1f64;
+        }
+        for &pair_id in &self.gold_pairs {
+            gold_score += pair_scores[pair_id as usize];
+            pair_expectations[pair_id as usize] -= 1f64;
+        }
+        log_z - gold_score
+    }
+}
+
+fn unknown_word_stop(
+    feature_index: &FeatureIndex,
+    category: CategoryId,
+    category_ord: usize,
+    start: usize,
+    suffix: &str,
+    found: bool,
+) -> Option<usize> {
+    let char_definitions = &feature_index.dictionary.char_definitions;
+    let category_data = char_definitions.lookup_definition(category);
+    if !category_data.invoke && found {
+        return None;
+    }
+    let mut num_chars = 1;
+    if category_data.group {
+        for c in suffix.chars().skip(1) {
+            let categories = char_definitions.lookup_categories(c);
+            if categories.len() > category_ord && categories[category_ord] == category {
+                num_chars += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    let len = suffix
+        .char_indices()
+        .nth(num_chars)
+        .map(|(byte_offset, _)| byte_offset)
+        .unwrap_or(suffix.len());
+    Some(start + len)
+}
+
+/// Returns the node matching the gold token, adding it if the dictionary
+/// does not contain it.
+fn find_or_add_gold_node(
+    nodes: &mut Vec<Node>,
+    first_node: usize,
+    stop: usize,
+    gold_token: &GoldToken,
+    feature_index: &mut FeatureIndex,
+) -> u32 {
+    let dictionary = feature_index.dictionary;
+    let candidate_features = |source: NodeSource| match source {
+        NodeSource::Known(row_id) => dictionary.row(row_id as usize).feature_refs(),
+        NodeSource::Unknown(row_id) => dictionary.unknown_rows[row_id as usize].feature_refs(),
+    };
+    for &exact in &[true, false] {
+        for (node_id, node) in nodes.iter().enumerate().skip(first_node) {
+            let source = node.source.unwrap();
+            if exact == matches!(source, NodeSource::Unknown(_)) {
+                // Dictionary words must match exactly, unknown words are templates.
+                continue;
+            }
+            if node.stop == stop && matches_gold(&candidate_features(source), gold_token, exact) {
+                return node_id as u32;
+            }
+        }
+    }
+    let features: Vec<&str> = gold_token.features.iter().map(String::as_str).collect();
+    let char_type = char_type(&dictionary.char_definitions, &gold_token.surface);
+    let info = feature_index.node_info(&features, char_type);
+    nodes.push(Node {
+        stop,
+        source: None,
+        info,
+        left_edges: Vec::new(),
+    });
+    (nodes.len() - 1) as u32
+}
+
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    let (max, min) = if a > b { (a, b) } else { (b, a) };
+    max + (min - max).exp().ln_1p()
+}
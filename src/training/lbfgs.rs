@@ -0,0 +1,146 @@
+//! Limited-memory BFGS minimizer, with a backtracking line search.
+use std::collections::VecDeque;
+
+const HISTORY_SIZE: usize = 5;
+const ARMIJO: f64 = 1e-4;
+const MAX_LINE_SEARCH_STEPS: usize = 40;
+/// Number of consecutive iterations with a relative decrease below `eta`
+/// before stopping.
+const NUM_CONVERGED_ITERATIONS: usize = 3;
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Minimizes `objective`, starting from `x`.
+///
+/// `objective(x, gradient)` returns the value of the function at `x`, and writes its
+/// gradient. `progress(iteration, value)` is called after each iteration.
+pub(crate) fn minimize(
+    x: &mut Vec<f64>,
+    max_iterations: usize,
+    eta: f64,
+    mut objective: impl FnMut(&[f64], &mut [f64]) -> f64,
+    mut progress: impl FnMut(usize, f64),
+) -> f64 {
+    let n = x.len();
+    let mut gradient = vec![0f64; n];
+    let mut value = objective(x, &mut gradient);
+    // (s, y, 1 / y.s) of the last iterations.
+    let mut history: VecDeque<(Vec<f64>, Vec<f64>, f64)> = VecDeque::new();
+    let mut num_converged = 0;
+    let mut new_x = vec![0f64; n];
+    let mut new_gradient = vec![0f64; n];
+    for iteration in 0..max_iterations {
+        let gradient_norm = dot(&gradient, &gradient).sqrt();
+        if gradient_norm == 0f64 {
+            break;
+        }
+        // Two-loop recursion: direction = -H.gradient
+        let mut direction = gradient.clone();
+        let mut alphas = Vec::with_capacity(history.len());
+        for (s, y, rho) in history.iter().rev() {
+            let alpha = rho * dot(s, &direction);
+            for (d, y_i) in direction.iter_mut().zip(y) {
+                *d -= alpha * y_i;
+            }
+            alphas.push(alpha);
+        }
+        let scale = if let Some((s, y, _)) = history.back() {
+            dot(s, y) / dot(y, y)
+        } else {
+            1f64 / gradient_norm
+        };
+        for d in direction.iter_mut() {
+            *d *= scale;
+        }
+        for ((s, y, rho), alpha) in history.iter().zip(alphas.iter().rev()) {
+            let beta = rho * dot(y, &direction);
+            for (d, s_i) in direction.iter_mut().zip(s) {
+                *d += (alpha - beta) * s_i;
+            }
+        }
+        for d in direction.iter_mut() {
+            *d = -*d;
+        }
+        let mut slope = dot(&gradient, &direction);
+        if slope >= 0f64 {
+            // Not a descent direction: restart from steepest descent.
+            history.clear();
+            for (d, g) in direction.iter_mut().zip(&gradient) {
+                *d = -g / gradient_norm;
+            }
+            slope = -gradient_norm;
+        }
+
+        let mut step = 1f64;
+        let mut new_value = f64::INFINITY;
+        for _ in 0..MAX_LINE_SEARCH_STEPS {
+            for ((new_x_i, x_i), d) in new_x.iter_mut().zip(x.iter()).zip(&direction) {
+                *new_x_i = x_i + step * d;
+            }
+            new_value = objective(&new_x, &mut new_gradient);
+            if new_value <= value + ARMIJO * step * slope {
+                break;
+            }
+            step *= 0.5;
+        }
+        if new_value > value {
+            // The line search failed: we are as close to the minimum as we can get.
+            break;
+        }
+
+        let s: Vec<f64> = new_x.iter().zip(x.iter()).map(|(a, b)| a - b).collect();
+        let y: Vec<f64> = new_gradient
+            .iter()
+            .zip(&gradient)
+            .map(|(a, b)| a - b)
+            .collect();
+        let ys = dot(&y, &s);
+        if ys > 1e-10 {
+            if history.len() == HISTORY_SIZE {
+                history.pop_front();
+            }
+            history.push_back((s, y, 1f64 / ys));
+        }
+        let relative_decrease = (value - new_value) / new_value.abs().max(1f64);
+        std::mem::swap(x, &mut new_x);
+        std::mem::swap(&mut gradient, &mut new_gradient);
+        value = new_value;
+        progress(iteration, value);
+        if relative_decrease < eta {
+            num_converged += 1;
+            if num_converged >= NUM_CONVERGED_ITERATIONS {
+                break;
+            }
+        } else {
+            num_converged = 0;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minimize;
+
+    #[test]
+    fn test_minimize_rosenbrock() {
+        let mut x = vec![-1.2f64, 1f64];
+        let value = minimize(
+            &mut x,
+            1000,
+            1e-12,
+            |x, gradient| {
+                let (a, b) = (x[0], x[1]);
+                gradient[0] = -2f64 * (1f64 - a) - 400f64 * a * (b - a * a);
+                gradient[1] = 200f64 * (b - a * a);
+                (1f64 - a).powi(2) + 100f64 * (b - a * a).powi(2)
+            },
+            |_, _| {},
+        );
+        assert!(value < 1e-6, "value={}", value);
+        assert!((x[0] - 1f64).abs() < 1e-2);
+        assert!((x[1] - 1f64).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,867 @@
+//! Re-estimation of the dictionary costs from an annotated corpus.
+//!
+//! This mirrors `mecab-cost-train` and `mecab-dict-gen`: a CRF is trained on
+//! the gold segmentations of a corpus, using the features of `feature.def`,
+//! and its weights are then turned into new word costs and a new connection
+//! cost matrix.
+pub mod estimate;
+mod feature_template;
+mod lattice;
+mod lbfgs;
+mod rewrite;
+
+pub use self::feature_template::{FeatureTemplate, FeatureTemplates};
+pub use self::rewrite::{RewriteRules, Rewriter, DEFAULT_REWRITE_RULES};
+
+use self::lattice::{FeatureIndex, TrainingLattice, BOS_FEATURES};
+use crate::character_definition::CharacterDefinitionsBuilder;
+use crate::corpus::Sentence;
+use crate::CharacterDefinitions;
+use crate::KuromojiError;
+use encoding::{DecoderTrap, EncoderTrap, EncodingRef};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Reads a dictionary source file, such as a CSV file or `char.def`.
+pub fn read_dictionary_file(path: &Path, encoding: EncodingRef) -> Result<String, KuromojiError> {
+    let buffer = fs::read(path)?;
+    encoding
+        .decode(&buffer, DecoderTrap::Strict)
+        .map_err(|_| KuromojiError::Encoding)
+}
+
+/// Writes a dictionary source file, encoded in `encoding`.
+pub fn write_dictionary_file(
+    path: &Path,
+    encoding: EncodingRef,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<(), KuromojiError> {
+    let mut content = Vec::new();
+    write(&mut content)?;
+    let content = String::from_utf8(content).map_err(KuromojiError::from_error)?;
+    let encoded = encoding
+        .encode(&content, EncoderTrap::Strict)
+        .map_err(|_| KuromojiError::Encoding)?;
+    fs::write(path, encoded)?;
+    Ok(())
+}
+
+fn write_rows(
+    path: &Path,
+    encoding: EncodingRef,
+    rows: &[DictionaryRow],
+) -> Result<(), KuromojiError> {
+    write_dictionary_file(path, encoding, |wtr| {
+        for row in rows {
+            writeln!(wtr, "{}", row.to_csv_line())?;
+        }
+        Ok(())
+    })
+}
+
+fn parse_rows(content: &str) -> Result<Vec<DictionaryRow>, KuromojiError> {
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(DictionaryRow::parse)
+        .collect()
+}
+
+/// Row of a MeCab dictionary CSV file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DictionaryRow {
+    pub surface: String,
+    pub left_id: u32,
+    pub right_id: u32,
+    pub word_cost: i32,
+    pub features: Vec<String>,
+}
+
+impl DictionaryRow {
+    pub fn parse(line: &str) -> Result<DictionaryRow, KuromojiError> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 5 {
+            return Err(KuromojiError::Format(format!(
+                "Expected at least 5 fields, got {:?}",
+                line
+            )));
+        }
+        Ok(DictionaryRow {
+            surface: fields[0].to_string(),
+            left_id: u32::from_str(fields[1]).map_err(KuromojiError::from_error)?,
+            right_id: u32::from_str(fields[2]).map_err(KuromojiError::from_error)?,
+            word_cost: i32::from_str(fields[3]).map_err(KuromojiError::from_error)?,
+            features: fields[4..].iter().map(|field| field.to_string()).collect(),
+        })
+    }
+
+    pub fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.surface,
+            self.left_id,
+            self.right_id,
+            self.word_cost,
+            self.features.join(",")
+        )
+    }
+
+    pub fn feature_refs(&self) -> Vec<&str> {
+        self.features.iter().map(String::as_str).collect()
+    }
+}
+
+/// Seed dictionary: the words whose costs get re-estimated.
+pub struct TrainingDictionary {
+    /// Rows of each CSV file, with the name of the file.
+    pub files: Vec<(String, Vec<DictionaryRow>)>,
+    /// Rows of `unk.def`. Their surface is the name of a character category.
+    pub unknown_rows: Vec<DictionaryRow>,
+    pub char_definitions: CharacterDefinitions,
+}
+
+impl TrainingDictionary {
+    /// Loads the CSV files, `unk.def` and `char.def` of a MeCab dictionary directory.
+    pub fn load(dicdir: &Path, encoding: EncodingRef) -> Result<TrainingDictionary, KuromojiError> {
+        let mut csv_paths: Vec<PathBuf> = fs::read_dir(dicdir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<_>>()?;
+        csv_paths.retain(|path| path.extension().map(|ext| ext == "csv").unwrap_or(false));
+        csv_paths.sort();
+        let mut files = Vec::new();
+        for csv_path in csv_paths {
+            let filename = csv_path.file_name().unwrap().to_string_lossy().to_string();
+            let rows = parse_rows(&read_dictionary_file(&csv_path, encoding)?)?;
+            files.push((filename, rows));
+        }
+        let unknown_rows = parse_rows(&read_dictionary_file(&dicdir.join("unk.def"), encoding)?)?;
+        let mut char_definitions_builder = CharacterDefinitionsBuilder::default();
+        char_definitions_builder
+            .parse(&read_dictionary_file(&dicdir.join("char.def"), encoding)?)?;
+        Ok(TrainingDictionary {
+            files,
+            unknown_rows,
+            char_definitions: char_definitions_builder.build()?,
+        })
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &DictionaryRow> {
+        self.files.iter().flat_map(|(_, rows)| rows.iter())
+    }
+
+    fn row(&self, mut row_id: usize) -> &DictionaryRow {
+        for (_, rows) in &self.files {
+            if row_id < rows.len() {
+                return &rows[row_id];
+            }
+            row_id -= rows.len();
+        }
+        panic!("Row id out of bounds");
+    }
+}
+
+/// Character type of a word, as used by the `%t` template macro.
+pub fn char_type<'a>(char_definitions: &'a CharacterDefinitions, surface: &str) -> &'a str {
+    surface
+        .chars()
+        .next()
+        .and_then(|c| char_definitions.lookup_categories(c).first())
+        .map(|&category_id| char_definitions.category_name(category_id))
+        .unwrap_or("DEFAULT")
+}
+
+/// Turns the features of a word into CRF features and context attributes.
+#[derive(Clone, Debug)]
+pub struct FeatureExtractor {
+    pub feature_templates: FeatureTemplates,
+    pub rewriter: Rewriter,
+}
+
+impl FeatureExtractor {
+    /// Loads `feature.def` and `rewrite.def` from a dictionary directory.
+    ///
+    /// The default rewrite rules are used if the directory has no `rewrite.def`.
+    pub fn load(dicdir: &Path, encoding: EncodingRef) -> Result<FeatureExtractor, KuromojiError> {
+        let feature_templates = FeatureTemplates::parse(&read_dictionary_file(
+            &dicdir.join("feature.def"),
+            encoding,
+        )?)?;
+        Ok(FeatureExtractor {
+            feature_templates,
+            rewriter: Rewriter::load(dicdir, encoding)?,
+        })
+    }
+
+    pub fn unigram_features(&self, features: &[&str], char_type: &str) -> Vec<String> {
+        let rewritten = self.rewriter.unigram_rules.rewrite(features);
+        let rewritten_features: Vec<&str> = rewritten.split(',').collect();
+        let mut unigram_features = Vec::new();
+        self.feature_templates.unigram_features(
+            &rewritten_features,
+            char_type,
+            &mut unigram_features,
+        );
+        unigram_features
+    }
+
+    pub fn left_attribute(&self, features: &[&str]) -> String {
+        self.rewriter.left_rules.rewrite(features)
+    }
+
+    pub fn right_attribute(&self, features: &[&str]) -> String {
+        self.rewriter.right_rules.rewrite(features)
+    }
+}
+
+/// Hyperparameters of the training.
+#[derive(Clone, Debug)]
+pub struct TrainingConfig {
+    /// Scale between the CRF weights and the integer costs.
+    pub cost_factor: f64,
+    /// Inverse of the strength of the L2 regularization.
+    pub c: f64,
+    pub max_iterations: usize,
+    /// Stop once the relative decrease of the objective is below `eta`.
+    pub eta: f64,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            cost_factor: 700f64,
+            c: 1f64,
+            max_iterations: 1000,
+            eta: 1e-4,
+        }
+    }
+}
+
+/// Progress of the training, reported after each iteration.
+#[derive(Clone, Copy, Debug)]
+pub struct TrainingProgress {
+    pub iteration: usize,
+    pub objective: f64,
+}
+
+/// Trains the CRF weights, using the dictionary as the set of candidate words.
+///
+/// Gold tokens that are neither in the dictionary nor matched by an unknown word
+/// template are still added to the lattice, so that no sentence is discarded.
+pub fn train(
+    dictionary: &TrainingDictionary,
+    extractor: &FeatureExtractor,
+    corpus: &[Sentence],
+    config: &TrainingConfig,
+    mut progress: impl FnMut(TrainingProgress),
+) -> Result<Model, KuromojiError> {
+    let mut feature_index = FeatureIndex::new(extractor, dictionary)?;
+    let lattices: Vec<TrainingLattice> = corpus
+        .iter()
+        .filter(|sentence| !sentence.tokens.is_empty())
+        .map(|sentence| TrainingLattice::build(sentence, &mut feature_index))
+        .collect();
+    let num_features = feature_index.num_features();
+    let mut set_scores = vec![0f64; feature_index.feature_sets.len()];
+    let mut pair_scores = vec![0f64; feature_index.pair_features.len()];
+    let mut set_expectations = vec![0f64; set_scores.len()];
+    let mut pair_expectations = vec![0f64; pair_scores.len()];
+    let objective = |weights: &[f64], gradient: &mut [f64]| {
+        score_sets(&feature_index.feature_sets, weights, &mut set_scores);
+        score_sets(&feature_index.pair_features, weights, &mut pair_scores);
+        set_expectations.iter_mut().for_each(|e| *e = 0f64);
+        pair_expectations.iter_mut().for_each(|e| *e = 0f64);
+        let mut value = 0f64;
+        for lattice in &lattices {
+            value += lattice.accumulate_expectations(
+                &set_scores,
+                &pair_scores,
+                &mut set_expectations,
+                &mut pair_expectations,
+            );
+        }
+        for (gradient_i, weight) in gradient.iter_mut().zip(weights) {
+            *gradient_i = weight / config.c;
+            value += weight * weight / (2f64 * config.c);
+        }
+        for (feature_ids, &expectation) in feature_index
+            .feature_sets
+            .iter()
+            .zip(&set_expectations)
+            .chain(feature_index.pair_features.iter().zip(&pair_expectations))
+        {
+            for &feature_id in feature_ids {
+                gradient[feature_id as usize] += expectation;
+            }
+        }
+        value
+    };
+    let mut weights = vec![0f64; num_features];
+    lbfgs::minimize(
+        &mut weights,
+        config.max_iterations,
+        config.eta,
+        objective,
+        |iteration, objective| {
+            progress(TrainingProgress {
+                iteration,
+                objective,
+            })
+        },
+    );
+    let weights = feature_index
+        .feature_names()
+        .iter()
+        .zip(weights)
+        .filter(|(_, weight)| weight.abs() > 1e-12)
+        .map(|(feature, weight)| (feature.clone(), weight))
+        .collect();
+    Ok(Model {
+        extractor: extractor.clone(),
+        weights,
+        cost_factor: config.cost_factor,
+    })
+}
+
+fn score_sets(sets: &[Vec<u32>], weights: &[f64], scores: &mut [f64]) {
+    for (score, feature_ids) in scores.iter_mut().zip(sets) {
+        *score = feature_ids
+            .iter()
+            .map(|&feature_id| weights[feature_id as usize])
+            .sum();
+    }
+}
+
+/// Trained CRF weights.
+pub struct Model {
+    pub extractor: FeatureExtractor,
+    weights: HashMap<String, f64>,
+    cost_factor: f64,
+}
+
+impl Model {
+    /// Parses a model written by `Model::write`.
+    pub fn parse(extractor: FeatureExtractor, content: &str) -> Result<Model, KuromojiError> {
+        let mut lines = content.lines();
+        let header = lines.next().unwrap_or("");
+        let cost_factor = header
+            .strip_prefix("cost-factor\t")
+            .ok_or_else(|| KuromojiError::Format(format!("Invalid header {:?}", header)))?
+            .parse::<f64>()
+            .map_err(KuromojiError::from_error)?;
+        let mut weights = HashMap::new();
+        for line in lines {
+            let mut fields = line.splitn(2, '\t');
+            let weight = fields
+                .next()
+                .unwrap_or("")
+                .parse::<f64>()
+                .map_err(KuromojiError::from_error)?;
+            let feature = fields.next().ok_or_else(|| {
+                KuromojiError::Format(format!("Expected `weight<TAB>feature`, got {:?}", line))
+            })?;
+            weights.insert(feature.to_string(), weight);
+        }
+        Ok(Model {
+            extractor,
+            weights,
+            cost_factor,
+        })
+    }
+
+    /// Writes the cost factor and the non-zero weights, sorted by feature.
+    pub fn write(&self, wtr: &mut dyn Write) -> io::Result<()> {
+        writeln!(wtr, "cost-factor\t{}", self.cost_factor)?;
+        let mut weights: Vec<(&String, &f64)> = self.weights.iter().collect();
+        weights.sort_by_key(|(feature, _)| *feature);
+        for (feature, weight) in weights {
+            writeln!(wtr, "{}\t{}", weight, feature)?;
+        }
+        Ok(())
+    }
+
+    pub fn num_features(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn weight(&self, feature: &str) -> f64 {
+        self.weights.get(feature).copied().unwrap_or(0f64)
+    }
+
+    fn to_cost(&self, score: f64) -> i16 {
+        (-self.cost_factor * score)
+            .round()
+            .max(i16::MIN as f64)
+            .min(i16::MAX as f64) as i16
+    }
+
+    /// Cost of a word, given its features.
+    pub fn word_cost(&self, features: &[&str], char_type: &str) -> i16 {
+        let score = self
+            .extractor
+            .unigram_features(features, char_type)
+            .iter()
+            .map(|feature| self.weight(feature))
+            .sum();
+        self.to_cost(score)
+    }
+
+    /// Costs of the connections between words, indexed by the right attribute
+    /// of the left word and the left attribute of the right word.
+    fn connection_costs(&self, right_attributes: &[&str], left_attributes: &[&str]) -> Vec<i16> {
+        let left_words: Vec<Vec<&str>> = right_attributes
+            .iter()
+            .map(|attribute| attribute.split(',').collect())
+            .collect();
+        let right_words: Vec<Vec<&str>> = left_attributes
+            .iter()
+            .map(|attribute| attribute.split(',').collect())
+            .collect();
+        let num_columns = right_words.len();
+        let mut scores = vec![0f64; left_words.len() * num_columns];
+        // Templates only depend on a few features of each side: weights are looked up
+        // once per distinct pair of values, instead of once per pair of attributes.
+        for template in &self.extractor.feature_templates.bigram_templates {
+            let (left_value_ids, left_values) = value_ids(
+                left_words
+                    .iter()
+                    .map(|features| template.eval_left(features)),
+            );
+            let (right_value_ids, right_values) = value_ids(
+                right_words
+                    .iter()
+                    .map(|features| template.eval_right(features)),
+            );
+            let mut template_scores = vec![0f64; left_values.len() * right_values.len()];
+            let mut has_weights = false;
+            for (i, left_value) in left_values.iter().enumerate() {
+                for (j, right_value) in right_values.iter().enumerate() {
+                    let weight = self.weight(&template.render_bigram(left_value, right_value));
+                    template_scores[i * right_values.len() + j] = weight;
+                    has_weights |= weight != 0f64;
+                }
+            }
+            if !has_weights {
+                continue;
+            }
+            for (row, left_value_id) in left_value_ids.iter().enumerate() {
+                let left_value_id = if let Some(left_value_id) = left_value_id {
+                    *left_value_id
+                } else {
+                    continue;
+                };
+                for (column, right_value_id) in right_value_ids.iter().enumerate() {
+                    if let Some(right_value_id) = right_value_id {
+                        scores[row * num_columns + column] +=
+                            template_scores[left_value_id * right_values.len() + right_value_id];
+                    }
+                }
+            }
+        }
+        scores
+            .into_iter()
+            .map(|score| self.to_cost(score))
+            .collect()
+    }
+
+    /// Generates the dictionary with the re-estimated costs.
+    ///
+    /// Each distinct pair of left and right context attributes gets its own context id,
+    /// used as both the left and the right id of the words.
+    pub fn generate_dictionary(&self, dictionary: &TrainingDictionary) -> GeneratedDictionary {
+        let mut context_ids: HashMap<(String, String), u32> = HashMap::new();
+        let mut contexts: Vec<(String, String)> = Vec::new();
+        let mut context_id = |features: &[&str]| -> u32 {
+            let context = (
+                self.extractor.left_attribute(features),
+                self.extractor.right_attribute(features),
+            );
+            *context_ids.entry(context).or_insert_with_key(|context| {
+                contexts.push(context.clone());
+                (contexts.len() - 1) as u32
+            })
+        };
+        let bos_features: Vec<&str> = BOS_FEATURES.split(',').collect();
+        context_id(&bos_features);
+        let mut rewrite_row = |row: &DictionaryRow, char_type: &str| {
+            let features = row.feature_refs();
+            let id = context_id(&features);
+            DictionaryRow {
+                surface: row.surface.clone(),
+                left_id: id,
+                right_id: id,
+                word_cost: self.word_cost(&features, char_type) as i32,
+                features: row.features.clone(),
+            }
+        };
+        let files = dictionary
+            .files
+            .iter()
+            .map(|(filename, rows)| {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        rewrite_row(row, char_type(&dictionary.char_definitions, &row.surface))
+                    })
+                    .collect();
+                (filename.clone(), rows)
+            })
+            .collect();
+        let unknown_rows = dictionary
+            .unknown_rows
+            .iter()
+            .map(|row| rewrite_row(row, &row.surface))
+            .collect();
+
+        let (right_attribute_ids, right_attributes) =
+            value_ids(contexts.iter().map(|(_, right)| Some(right.as_str())));
+        let (left_attribute_ids, left_attributes) =
+            value_ids(contexts.iter().map(|(left, _)| Some(left.as_str())));
+        let attribute_costs = self.connection_costs(&right_attributes, &left_attributes);
+        let mut matrix = Vec::with_capacity(contexts.len() * contexts.len());
+        for right_attribute_id in &right_attribute_ids {
+            for left_attribute_id in &left_attribute_ids {
+                matrix.push(
+                    attribute_costs[right_attribute_id.unwrap() * left_attributes.len()
+                        + left_attribute_id.unwrap()],
+                );
+            }
+        }
+        GeneratedDictionary {
+            files,
+            unknown_rows,
+            contexts,
+            matrix,
+        }
+    }
+}
+
+/// Assigns an id to each distinct value. `None` values get no id.
+fn value_ids<T: Eq + std::hash::Hash + Clone>(
+    values: impl Iterator<Item = Option<T>>,
+) -> (Vec<Option<usize>>, Vec<T>) {
+    let mut ids: HashMap<T, usize> = HashMap::new();
+    let mut distinct_values = Vec::new();
+    let value_ids = values
+        .map(|value| {
+            value.map(|value| {
+                *ids.entry(value).or_insert_with_key(|value| {
+                    distinct_values.push(value.clone());
+                    distinct_values.len() - 1
+                })
+            })
+        })
+        .collect();
+    (value_ids, distinct_values)
+}
+
+/// Dictionary with re-estimated costs, in MeCab's source format.
+pub struct GeneratedDictionary {
+    pub files: Vec<(String, Vec<DictionaryRow>)>,
+    pub unknown_rows: Vec<DictionaryRow>,
+    /// Left and right attributes of each context id.
+    pub contexts: Vec<(String, String)>,
+    /// Connection costs, indexed by `right_id * num_contexts + left_id`.
+    pub matrix: Vec<i16>,
+}
+
+impl GeneratedDictionary {
+    pub fn num_contexts(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn connection_cost(&self, right_id: u32, left_id: u32) -> i16 {
+        self.matrix[right_id as usize * self.num_contexts() + left_id as usize]
+    }
+
+    /// Writes `matrix.def`: a `num_right_ids num_left_ids` header, followed by
+    /// `right_id left_id cost` lines.
+    pub fn write_matrix_def(&self, wtr: &mut dyn Write) -> io::Result<()> {
+        let num_contexts = self.num_contexts();
+        writeln!(wtr, "{} {}", num_contexts, num_contexts)?;
+        for right_id in 0..num_contexts {
+            for left_id in 0..num_contexts {
+                writeln!(
+                    wtr,
+                    "{} {} {}",
+                    right_id,
+                    left_id,
+                    self.connection_cost(right_id as u32, left_id as u32)
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `left-id.def`: the left attribute of each context id.
+    pub fn write_left_id_def(&self, wtr: &mut dyn Write) -> io::Result<()> {
+        for (context_id, (left_attribute, _)) in self.contexts.iter().enumerate() {
+            writeln!(wtr, "{} {}", context_id, left_attribute)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `right-id.def`: the right attribute of each context id.
+    pub fn write_right_id_def(&self, wtr: &mut dyn Write) -> io::Result<()> {
+        for (context_id, (_, right_attribute)) in self.contexts.iter().enumerate() {
+            writeln!(wtr, "{} {}", context_id, right_attribute)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the dictionary CSVs, `unk.def`, `matrix.def`, `left-id.def` and
+    /// `right-id.def` to `output`, encoded in `encoding`.
+    ///
+    /// With the EUC-JP encoding, `output` is a source directory for `build-fst --dicdir`,
+    /// once `char.def` is copied into it.
+    pub fn write_dir(&self, output: &Path, encoding: EncodingRef) -> Result<(), KuromojiError> {
+        fs::create_dir_all(output)?;
+        for (filename, rows) in &self.files {
+            write_rows(&output.join(filename), encoding, rows)?;
+        }
+        write_rows(&output.join("unk.def"), encoding, &self.unknown_rows)?;
+        write_dictionary_file(&output.join("matrix.def"), encoding, |wtr| {
+            self.write_matrix_def(wtr)
+        })?;
+        write_dictionary_file(&output.join("left-id.def"), encoding, |wtr| {
+            self.write_left_id_def(wtr)
+        })?;
+        write_dictionary_file(&output.join("right-id.def"), encoding, |wtr| {
+            self.write_right_id_def(wtr)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::character_definition::CharacterDefinitionsBuilder;
+    use crate::corpus::parse_mecab_corpus;
+
+    const CHAR_DEF: &str = "DEFAULT 0 1 0\n\
+                            HIRAGANA 0 1 2\n\
+                            KANJI 0 0 2\n\
+                            0x3041..0x309F HIRAGANA\n\
+                            0x4E00..0x9FA5 KANJI\n";
+
+    const FEATURE_DEF: &str = "UNIGRAM U0:%F[0]\n\
+                               UNIGRAM W0:%F[0]/%F[6]\n\
+                               UNIGRAM T0:%F[0]/%t\n\
+                               BIGRAM B00:%L[0]/%R[0]\n\
+                               BIGRAM B01:%L[0],%L?[6]/%R[0]\n\
+                               BIGRAM B02:%L[0],%L?[6]/%R[0],%R?[6]\n";
+
+    fn rows(lines: &[&str]) -> Vec<DictionaryRow> {
+        lines
+            .iter()
+            .map(|line| DictionaryRow::parse(line).unwrap())
+            .collect()
+    }
+
+    fn toy_dictionary() -> TrainingDictionary {
+        let mut char_definitions_builder = CharacterDefinitionsBuilder::default();
+        char_definitions_builder.parse(CHAR_DEF).unwrap();
+        TrainingDictionary {
+            files: vec![(
+                "Toy.csv".to_string(),
+                rows(&[
+                    "くる,0,0,0,名詞,一般,*,*,*,*,くる,クル,クル",
+                    "まで,0,0,0,助詞,副助詞,*,*,*,*,まで,マデ,マデ",
+                    "くるま,0,0,0,名詞,一般,*,*,*,*,くるま,クルマ,クルマ",
+                    "で,0,0,0,助詞,格助詞,*,*,*,*,で,デ,デ",
+                    "待つ,0,0,0,動詞,自立,*,*,五段・タ行,基本形,待つ,マツ,マツ",
+                ]),
+            )],
+            unknown_rows: rows(&[
+                "DEFAULT,0,0,0,記号,一般,*,*,*,*,*",
+                "HIRAGANA,0,0,0,名詞,一般,*,*,*,*,*",
+                "KANJI,0,0,0,名詞,一般,*,*,*,*,*",
+            ]),
+            char_definitions: char_definitions_builder.build().unwrap(),
+        }
+    }
+
+    fn toy_extractor() -> FeatureExtractor {
+        FeatureExtractor {
+            feature_templates: FeatureTemplates::parse(FEATURE_DEF).unwrap(),
+            rewriter: Rewriter::load_default().unwrap(),
+        }
+    }
+
+    fn best_segmentation(generated: &GeneratedDictionary, text: &str) -> Vec<String> {
+        // Exhaustive search of the cheapest path through the generated dictionary.
+        let rows: Vec<&DictionaryRow> = generated.files[0].1.iter().collect();
+        fn search(
+            generated: &GeneratedDictionary,
+            rows: &[&DictionaryRow],
+            text: &str,
+            right_id: u32,
+        ) -> Option<(i32, Vec<String>)> {
+            if text.is_empty() {
+                return Some((generated.connection_cost(right_id, 0) as i32, Vec::new()));
+            }
+            rows.iter()
+                .filter(|row| text.starts_with(row.surface.as_str()))
+                .filter_map(|row| {
+                    let (cost, mut words) =
+                        search(generated, rows, &text[row.surface.len()..], row.right_id)?;
+                    words.insert(0, row.surface.clone());
+                    let cost = cost
+                        + row.word_cost
+                        + generated.connection_cost(right_id, row.left_id) as i32;
+                    Some((cost, words))
+                })
+                .min()
+        }
+        search(generated, &rows, text, 0).unwrap().1
+    }
+
+    #[test]
+    fn test_train_toy_corpus() {
+        let corpus = parse_mecab_corpus(
+            "くる\t名詞,一般,*,*,*,*,くる,クル,クル\n\
+             まで\t助詞,副助詞,*,*,*,*,まで,マデ,マデ\n\
+             待つ\t動詞,自立,*,*,五段・タ行,基本形,待つ,マツ,マツ\n\
+             EOS\n\
+             くるま\t名詞,一般,*,*,*,*,くるま,クルマ,クルマ\n\
+             で\t助詞,格助詞,*,*,*,*,で,デ,デ\n\
+             EOS\n",
+        )
+        .unwrap();
+        let dictionary = toy_dictionary();
+        let mut iterations = 0;
+        let model = train(
+            &dictionary,
+            &toy_extractor(),
+            &corpus,
+            &TrainingConfig::default(),
+            |_| iterations += 1,
+        )
+        .unwrap();
+        assert!(iterations > 0);
+        assert!(model.num_features() > 0);
+        let generated = model.generate_dictionary(&dictionary);
+        assert_eq!(generated.unknown_rows.len(), 3);
+        assert_eq!(
+            generated.matrix.len(),
+            generated.num_contexts() * generated.num_contexts()
+        );
+        for row in &generated.files[0].1 {
+            assert_eq!(row.left_id, row.right_id);
+        }
+        assert_eq!(
+            best_segmentation(&generated, "くるまで"),
+            vec!["くるま", "で"]
+        );
+        assert_eq!(
+            best_segmentation(&generated, "くるまで待つ"),
+            vec!["くる", "まで", "待つ"]
+        );
+
+        let mut model_data = Vec::new();
+        model.write(&mut model_data).unwrap();
+        let reloaded =
+            Model::parse(toy_extractor(), std::str::from_utf8(&model_data).unwrap()).unwrap();
+        assert_eq!(reloaded.num_features(), model.num_features());
+        assert_eq!(
+            reloaded.word_cost(&["助詞", "格助詞", "*", "*", "*", "*", "で"], "HIRAGANA"),
+            model.word_cost(&["助詞", "格助詞", "*", "*", "*", "*", "で"], "HIRAGANA")
+        );
+    }
+
+    #[test]
+    fn test_train_and_write_dictionary_dir() {
+        let dir = std::env::temp_dir().join(format!("kuromoji-train-{}", std::process::id()));
+        let (source, output) = (dir.join("source"), dir.join("output"));
+        fs::create_dir_all(&source).unwrap();
+        let encoding = encoding::all::EUC_JP;
+        let toy = toy_dictionary();
+        write_rows(&source.join("Toy.csv"), encoding, &toy.files[0].1).unwrap();
+        write_rows(&source.join("unk.def"), encoding, &toy.unknown_rows).unwrap();
+        for (filename, content) in &[("char.def", CHAR_DEF), ("feature.def", FEATURE_DEF)] {
+            write_dictionary_file(&source.join(filename), encoding, |wtr| {
+                wtr.write_all(content.as_bytes())
+            })
+            .unwrap();
+        }
+        let corpus = parse_mecab_corpus(
+            "くる\t名詞,一般,*,*,*,*,くる,クル,クル\n\
+             まで\t助詞,副助詞,*,*,*,*,まで,マデ,マデ\n\
+             待つ\t動詞,自立,*,*,五段・タ行,基本形,待つ,マツ,マツ\n\
+             EOS\n\
+             くるま\t名詞,一般,*,*,*,*,くるま,クルマ,クルマ\n\
+             で\t助詞,格助詞,*,*,*,*,で,デ,デ\n\
+             EOS\n",
+        )
+        .unwrap();
+        let dictionary = TrainingDictionary::load(&source, encoding).unwrap();
+        let extractor = FeatureExtractor::load(&source, encoding).unwrap();
+        let model = train(
+            &dictionary,
+            &extractor,
+            &corpus,
+            &TrainingConfig::default(),
+            |_| {},
+        )
+        .unwrap();
+        let generated = model.generate_dictionary(&dictionary);
+        generated.write_dir(&output, encoding).unwrap();
+        fs::copy(source.join("char.def"), output.join("char.def")).unwrap();
+
+        let written = TrainingDictionary::load(&output, encoding);
+        let matrix_def = read_dictionary_file(&output.join("matrix.def"), encoding);
+        fs::remove_dir_all(&dir).unwrap();
+        let written = written.unwrap();
+        let matrix_def = matrix_def.unwrap();
+        assert_eq!(written.files, generated.files);
+        assert_eq!(written.unknown_rows, generated.unknown_rows);
+        for row in written.rows() {
+            let char_type = char_type(&written.char_definitions, &row.surface);
+            assert_eq!(
+                row.word_cost,
+                model.word_cost(&row.feature_refs(), char_type) as i32
+            );
+        }
+        let mut lines = matrix_def.lines();
+        let num_contexts = generated.num_contexts();
+        assert_eq!(
+            lines.next(),
+            Some(format!("{} {}", num_contexts, num_contexts).as_str())
+        );
+        let matrix: Vec<i16> = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                assert_eq!(fields.len(), 3);
+                fields[2].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(matrix, generated.matrix);
+        assert!(matrix.iter().any(|&cost| cost != 0));
+        let written = GeneratedDictionary {
+            files: written.files,
+            unknown_rows: written.unknown_rows,
+            contexts: generated.contexts.clone(),
+            matrix,
+        };
+        assert_eq!(
+            best_segmentation(&written, "くるまで"),
+            vec!["くるま", "で"]
+        );
+        assert_eq!(
+            best_segmentation(&written, "くるまで待つ"),
+            vec!["くる", "まで", "待つ"]
+        );
+    }
+
+    #[test]
+    fn test_dictionary_row() {
+        let line = "僕,1285,1285,5000,名詞,代名詞,一般,*,*,*,僕,ボク,ボク";
+        let row = DictionaryRow::parse(line).unwrap();
+        assert_eq!(row.surface, "僕");
+        assert_eq!(row.features.len(), 9);
+        assert_eq!(row.to_csv_line(), line);
+        assert!(DictionaryRow::parse("僕,a,1,1,名詞").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Feature rewriting rules, as defined in MeCab's `rewrite.def`.
+//!
+//! The features of a word are rewritten three times: once for the unigram
+//! features, once to get its left context attribute, and once to get its right
+//! context attribute. Words sharing the same context attribute share the same
+//! context id.
+use super::read_dictionary_file;
+use crate::KuromojiError;
+use encoding::EncodingRef;
+use std::path::Path;
+
+/// Rules used when the dictionary does not come with a `rewrite.def`.
+///
+/// Only closed-class words are lexicalized in the context attributes,
+/// which keeps the number of context ids reasonable.
+pub const DEFAULT_REWRITE_RULES: &str = "\
+[unigram rewrite]
+*,*,*,*,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7,$8,$9
+*,*,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7,*,*
+*,*,*,*,*,* $1,$2,$3,$4,$5,$6,*,*,*
+
+[left rewrite]
+(助詞|助動詞|記号|フィラー|感動詞|接続詞|連体詞|接頭詞),*,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7
+(名詞|動詞|形容詞),非自立,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7
+*,*,*,*,*,*,* $1,$2,$3,$4,$5,$6,*
+*,*,*,*,*,* $1,$2,$3,$4,$5,$6,*
+
+[right rewrite]
+(助詞|助動詞|記号|フィラー|感動詞|接続詞|連体詞|接頭詞),*,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7
+(名詞|動詞|形容詞),非自立,*,*,*,*,* $1,$2,$3,$4,$5,$6,$7
+*,*,*,*,*,*,* $1,$2,$3,$4,$5,$6,*
+*,*,*,*,*,* $1,$2,$3,$4,$5,$6,*
+";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Pattern {
+    Any,
+    OneOf(Vec<String>),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        if pattern == "*" {
+            Pattern::Any
+        } else if pattern.starts_with('(') && pattern.ends_with(')') && pattern.len() >= 2 {
+            Pattern::OneOf(
+                pattern[1..pattern.len() - 1]
+                    .split('|')
+                    .map(str::to_string)
+                    .collect(),
+            )
+        } else {
+            Pattern::OneOf(vec![pattern.to_string()])
+        }
+    }
+
+    fn matches(&self, feature: &str) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::OneOf(alternatives) => alternatives.iter().any(|alt| alt == feature),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum OutputPart {
+    Literal(String),
+    /// `$n`, with `n` starting at 1.
+    Feature(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct RewriteRule {
+    patterns: Vec<Pattern>,
+    output: Vec<OutputPart>,
+}
+
+impl RewriteRule {
+    fn parse(line: &str) -> Result<RewriteRule, KuromojiError> {
+        let mut fields = line.split_whitespace();
+        let (pattern, output) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(pattern), Some(output), None) => (pattern, output),
+            _ => {
+                return Err(KuromojiError::Format(format!(
+                    "Expected `pattern output`, got {:?}",
+                    line
+                )))
+            }
+        };
+        let patterns = pattern.split(',').map(Pattern::parse).collect();
+        let mut output_parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = output.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' || !chars.peek().map(char::is_ascii_digit).unwrap_or(false) {
+                literal.push(c);
+                continue;
+            }
+            let mut index = 0usize;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                index = index * 10 + digit as usize;
+                chars.next();
+            }
+            if index == 0 {
+                return Err(KuromojiError::Format(format!(
+                    "Invalid reference $0 in {:?}",
+                    line
+                )));
+            }
+            if !literal.is_empty() {
+                output_parts.push(OutputPart::Literal(std::mem::take(&mut literal)));
+            }
+            output_parts.push(OutputPart::Feature(index));
+        }
+        if !literal.is_empty() {
+            output_parts.push(OutputPart::Literal(literal));
+        }
+        Ok(RewriteRule {
+            patterns,
+            output: output_parts,
+        })
+    }
+
+    fn rewrite(&self, features: &[&str]) -> Option<String> {
+        if self.patterns.len() > features.len() {
+            return None;
+        }
+        if !self
+            .patterns
+            .iter()
+            .zip(features)
+            .all(|(pattern, feature)| pattern.matches(feature))
+        {
+            return None;
+        }
+        let mut rewritten = String::new();
+        for part in &self.output {
+            match part {
+                OutputPart::Literal(literal) => rewritten.push_str(literal),
+                OutputPart::Feature(index) => {
+                    rewritten.push_str(features.get(index - 1).copied().unwrap_or("*"))
+                }
+            }
+        }
+        Some(rewritten)
+    }
+}
+
+/// Ordered list of rules. The first matching rule wins.
+#[derive(Clone, Debug, Default)]
+pub struct RewriteRules {
+    rules: Vec<RewriteRule>,
+}
+
+impl RewriteRules {
+    /// Rewrites the features. Features that match no rule are kept as is.
+    pub fn rewrite(&self, features: &[&str]) -> String {
+        self.rules
+            .iter()
+            .find_map(|rule| rule.rewrite(features))
+            .unwrap_or_else(|| features.join(","))
+    }
+}
+
+/// The three sets of rules of a `rewrite.def` file.
+#[derive(Clone, Debug, Default)]
+pub struct Rewriter {
+    pub unigram_rules: RewriteRules,
+    pub left_rules: RewriteRules,
+    pub right_rules: RewriteRules,
+}
+
+impl Rewriter {
+    pub fn parse(content: &str) -> Result<Rewriter, KuromojiError> {
+        let mut rewriter = Rewriter::default();
+        let mut current_rules: Option<&mut RewriteRules> = None;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line {
+                "[unigram rewrite]" => current_rules = Some(&mut rewriter.unigram_rules),
+                "[left rewrite]" => current_rules = Some(&mut rewriter.left_rules),
+                "[right rewrite]" => current_rules = Some(&mut rewriter.right_rules),
+                _ => {
+                    let rules = current_rules.as_mut().ok_or_else(|| {
+                        KuromojiError::Format(format!("Rule {:?} appears before any section", line))
+                    })?;
+                    rules.rules.push(RewriteRule::parse(line)?);
+                }
+            }
+        }
+        Ok(rewriter)
+    }
+
+    /// Returns the rewriter of `DEFAULT_REWRITE_RULES`.
+    pub fn load_default() -> Result<Rewriter, KuromojiError> {
+        Rewriter::parse(DEFAULT_REWRITE_RULES)
+    }
+
+    /// Loads the `rewrite.def` of a dictionary directory, or the default rules
+    /// if there is none.
+    pub fn load(dicdir: &Path, encoding: EncodingRef) -> Result<Rewriter, KuromojiError> {
+        let rewrite_path = dicdir.join("rewrite.def");
+        if rewrite_path.exists() {
+            Rewriter::parse(&read_dictionary_file(&rewrite_path, encoding)?)
+        } else {
+            Rewriter::load_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewriter;
+
+    #[test]
+    fn test_rewrite() {
+        let rewriter = Rewriter::parse(
+            "[left rewrite]\n\
+             (助詞|助動詞),*,* $1,$2,$3\n\
+             *,* $1,*,x$2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rewriter.left_rules.rewrite(&["助詞", "係助詞", "は"]),
+            "助詞,係助詞,は"
+        );
+        assert_eq!(
+            rewriter.left_rules.rewrite(&["名詞", "一般", "僕"]),
+            "名詞,*,x一般"
+        );
+        // No matching rule: the features are kept as is.
+        assert_eq!(rewriter.left_rules.rewrite(&["記号"]), "記号");
+        assert_eq!(rewriter.right_rules.rewrite(&["名詞", "一般"]), "名詞,一般");
+    }
+
+    #[test]
+    fn test_default_rewrite_rules() {
+        let rewriter = Rewriter::load_default().unwrap();
+        let features = ["名詞", "一般", "*", "*", "*", "*", "僕", "ボク", "ボク"];
+        assert_eq!(
+            rewriter.right_rules.rewrite(&features),
+            "名詞,一般,*,*,*,*,*"
+        );
+        let features = ["助詞", "係助詞", "*", "*", "*", "*", "は", "ハ", "ワ"];
+        assert_eq!(
+            rewriter.left_rules.rewrite(&features),
+            "助詞,係助詞,*,*,*,*,は"
+        );
+        assert_eq!(
+            rewriter.unigram_rules.rewrite(&features),
+            "助詞,係助詞,*,*,*,*,は,ハ,ワ"
+        );
+    }
+
+    #[test]
+    fn test_rule_before_section() {
+        assert!(Rewriter::parse("*,* $1,$2").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Tolerance of typos at tokenization time.
+//!
+//! When enabled, the lattice also holds, for each span of the text within one
+//! edit of a dictionary word, an edge for this word. Its cost is the cost of the
+//! word plus a penalty, so that it only wins when the text cannot be segmented
+//! well otherwise. The tokens of such edges report the corrected word, see
+//! `Token::corrected_text`.
+
+/// Settings of the typo tolerant tokenization.
+///
+/// Only the words whose first character is spelled correctly are found: a typo
+/// in the first character of a word is never corrected. Allowing it would make each
+/// position of the text match the first character of every word of the dictionary.
+///
+/// See `Tokenizer::with_typo_tolerance`.
+#[derive(Clone, Debug)]
+pub struct TypoTolerance {
+    /// Cost added to the words matched with a typo.
+    pub penalty: i32,
+    /// Minimum number of characters of the words matched with a typo.
+    ///
+    /// Short words are within one edit of too many spans to be useful.
+    pub min_word_len: usize,
+}
+
+impl Default for TypoTolerance {
+    fn default() -> TypoTolerance {
+        TypoTolerance {
+            penalty: 8000,
+            min_word_len: 3,
+        }
+    }
+}
+
+/// Largest number of characters of the text considered by a `TypoAutomaton`.
+const MAX_TEXT_CHARS: usize = 63;
+
+/// Automaton matching the words within one edit of a prefix of a text.
+///
+/// Edits are insertions, deletions and substitutions of a character, except
+/// for the first one. Allowing an edit there would make the automaton accept
+/// the first character of every word of the dictionary.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TypoAutomaton {
+    chars: Vec<char>,
+}
+
+/// State of a `TypoAutomaton`, after some bytes of a word.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TypoState {
+    // bytes of the current character of the word, if it is incomplete.
+    pending: [u8; 4],
+    pending_len: u8,
+    // number of characters of the text equal to the word, if the word is a prefix
+    // of the text.
+    exact: Option<u8>,
+    // bitset of the numbers of characters of the text prefixes within one edit
+    // of the word.
+    fuzzy: u64,
+}
+
+/// Returns the number of bytes of the UTF-8 character starting with `first_byte`.
+fn utf8_len(first_byte: u8) -> Option<usize> {
+    match first_byte {
+        0x00..=0x7F => Some(1),
+        0xC0..=0xDF => Some(2),
+        0xE0..=0xEF => Some(3),
+        0xF0..=0xF7 => Some(4),
+        _ => None,
+    }
+}
+
+impl TypoAutomaton {
+    /// Makes the automaton match the prefixes of `text`, up to `MAX_TEXT_CHARS`
+    /// characters, reusing its buffer.
+    pub fn reset(&mut self, text: &str) {
+        self.chars.clear();
+        self.chars.extend(text.chars().take(MAX_TEXT_CHARS));
+    }
+
+    pub fn start(&self) -> TypoState {
+        TypoState {
+            pending: [0; 4],
+            pending_len: 0,
+            exact: Some(0),
+            fuzzy: 0,
+        }
+    }
+
+    /// Deleting the next character of the text is one edit away from an exact match.
+    fn close(&self, mut state: TypoState) -> TypoState {
+        if let Some(exact) = state.exact.filter(|&exact| exact > 0) {
+            if (exact as usize) < self.chars.len() {
+                state.fuzzy |= 1 << (exact + 1);
+            }
+        }
+        state
+    }
+
+    pub fn is_match(&self, state: &TypoState) -> bool {
+        state.pending_len == 0 && (state.exact.unwrap_or(0) > 0 || state.fuzzy != 0)
+    }
+
+    pub fn accept(&self, state: &TypoState, byte: u8) -> Option<TypoState> {
+        let mut state = *state;
+        state.pending[state.pending_len as usize] = byte;
+        state.pending_len += 1;
+        let char_len = utf8_len(state.pending[0])?;
+        if (state.pending_len as usize) < char_len {
+            return self.can_continue(&state).then_some(state);
+        }
+        let c = std::str::from_utf8(&state.pending[..char_len])
+            .ok()?
+            .chars()
+            .next()?;
+        state.pending_len = 0;
+        self.accept_char(&state, c)
+    }
+
+    /// Returns false if the pending bytes of `state` cannot start a character
+    /// leading to a match.
+    ///
+    /// Without this check, all of the characters following a mismatch would have
+    /// to be decoded before the state could be pruned.
+    fn can_continue(&self, state: &TypoState) -> bool {
+        let mut positions = state.fuzzy;
+        match state.exact {
+            Some(0) => positions |= 1,
+            // any character can be inserted, or substitute the next one of the text.
+            Some(_) => return true,
+            None => {}
+        }
+        let pending = &state.pending[..state.pending_len as usize];
+        let mut buffer = [0u8; 4];
+        bit_positions(positions).any(|pos| {
+            self.chars
+                .get(pos)
+                .map(|c| c.encode_utf8(&mut buffer).as_bytes().starts_with(pending))
+                .unwrap_or(false)
+        })
+    }
+
+    fn accept_char(&self, state: &TypoState, c: char) -> Option<TypoState> {
+        let matches = |pos: usize| self.chars.get(pos) == Some(&c);
+        let mut fuzzy = 0u64;
+        for pos in bit_positions(state.fuzzy) {
+            if matches(pos) {
+                fuzzy |= 1 << (pos + 1);
+            }
+        }
+        let mut exact = None;
+        if let Some(pos) = state.exact.map(usize::from) {
+            if matches(pos) {
+                exact = Some(pos as u8 + 1);
+            }
+            // the first character of the word must be correct.
+            if pos > 0 {
+                // `c` is inserted.
+                fuzzy |= 1 << pos;
+                if exact.is_none() && pos < self.chars.len() {
+                    // `c` substitutes the next character of the text.
+                    fuzzy |= 1 << (pos + 1);
+                }
+            }
+        }
+        if exact.is_none() && fuzzy == 0 {
+            return None;
+        }
+        Some(self.close(TypoState {
+            exact,
+            fuzzy,
+            ..*state
+        }))
+    }
+
+    /// Returns the numbers of characters of the prefixes of the text at exactly
+    /// one edit from `word`.
+    pub fn typo_spans(&self, word: &[u8]) -> Vec<usize> {
+        let state = word
+            .iter()
+            .try_fold(self.start(), |state, &byte| self.accept(&state, byte));
+        let state = match state.filter(|state| state.pending_len == 0) {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
+        bit_positions(state.fuzzy)
+            .filter(|&num_chars| state.exact != Some(num_chars as u8))
+            .collect()
+    }
+}
+
+/// Iterates over the positions of the bits set in `bits`, in increasing order.
+fn bit_positions(mut bits: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bits == 0 {
+            return None;
+        }
+        let pos = bits.trailing_zeros() as usize;
+        bits &= bits - 1;
+        Some(pos)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TypoAutomaton, TypoTolerance};
+    use crate::Tokenizer;
+
+    #[test]
+    fn test_typo_spans() {
+        let mut automaton = TypoAutomaton::default();
+        automaton.reset("東京都に");
+        // insertion and substitution
+        assert_eq!(automaton.typo_spans("東京湾".as_bytes()), vec![2, 3]);
+        // deletion and insertion
+        assert_eq!(automaton.typo_spans("東京".as_bytes()), vec![1, 3]);
+        // exact matches are not typos.
+        assert_eq!(automaton.typo_spans("東京都".as_bytes()), vec![2, 4]);
+        assert!(automaton.typo_spans("京都府".as_bytes()).is_empty());
+        assert!(automaton.typo_spans("大阪".as_bytes()).is_empty());
+        // the first character must be correct.
+        assert!(automaton.typo_spans("西京都".as_bytes()).is_empty());
+
+        automaton.reset("大坂");
+        assert_eq!(automaton.typo_spans("大阪".as_bytes()), vec![1, 2]);
+        assert!(automaton.typo_spans("東京湾".as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_tokenizer_with_typo_tolerance() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let tokens = tokenizer.tokenize("関西国債空港に行く");
+        assert!(tokens.iter().all(|token| token.corrected_text().is_none()));
+        assert_ne!(tokens[0].text, "関西国債空港");
+
+        let mut tokenizer = tokenizer.with_typo_tolerance(TypoTolerance::default());
+        let tokens = tokenizer.tokenize("関西国債空港に行く");
+        assert_eq!(tokens[0].text, "関西国債空港");
+        assert_eq!(tokens[0].corrected_text(), Some("関西国際空港"));
+        assert_eq!(
+            tokenizer.detail(&tokens[0]).reading,
+            "カンサイコクサイクウコウ"
+        );
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, vec!["関西国債空港", "に", "行く"]);
+
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert!(tokens.iter().all(|token| token.corrected_text().is_none()));
+    }
+}
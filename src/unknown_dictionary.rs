@@ -1,8 +1,8 @@
 use crate::character_definition::CategoryId;
-use crate::WordEntry;
+use crate::{KuromojiError, WordEntry};
 use serde::{Deserialize, Serialize};
 
-const CHAR_DEFINITION_DATA: &'static [u8] = include_bytes!("../dict/unk.bin");
+const CHAR_DEFINITION_DATA: &[u8] = include_bytes!("../dict/unk.bin");
 
 //TODO optimize
 #[derive(Serialize, Deserialize)]
@@ -11,14 +11,6 @@ pub struct UnknownDictionary {
     pub costs: Vec<WordEntry>,
 }
 
-#[derive(Debug)]
-pub struct DictionaryEntry {
-    surface: String,
-    left_id: u32,
-    right_id: u32,
-    word_cost: i32,
-}
-
 impl UnknownDictionary {
     pub fn word_entry(&self, word_id: u32) -> WordEntry {
         self.costs[word_id as usize]
@@ -28,8 +20,22 @@ impl UnknownDictionary {
         &self.category_references[category_id.0][..]
     }
 
-    pub fn load() -> UnknownDictionary {
-        bincode::deserialize(CHAR_DEFINITION_DATA).unwrap()
+    pub fn load() -> Result<UnknownDictionary, KuromojiError> {
+        UnknownDictionary::from_bytes(CHAR_DEFINITION_DATA)
+    }
+
+    /// Loads an unknown dictionary in the format of `unk.bin`.
+    pub fn from_bytes(data: &[u8]) -> Result<UnknownDictionary, KuromojiError> {
+        let unknown_dictionary: UnknownDictionary = bincode::deserialize(data)?;
+        let num_entries = unknown_dictionary.costs.len();
+        let mut word_ids = unknown_dictionary.category_references.iter().flatten();
+        if let Some(word_id) = word_ids.find(|&&word_id| word_id as usize >= num_entries) {
+            return Err(KuromojiError::Format(format!(
+                "Unknown word id {} out of range",
+                word_id
+            )));
+        }
+        Ok(unknown_dictionary)
     }
 }
 
@@ -39,7 +45,6 @@ mod tests {
 
     #[test]
     fn test_parse_unknown_dictionary() {
-        let _unknown_dict = UnknownDictionary::load();
+        let _unknown_dict = UnknownDictionary::load().unwrap();
     }
-
 }
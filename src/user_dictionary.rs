@@ -0,0 +1,523 @@
+//! Words added to the system dictionary at runtime.
+//!
+//! User dictionaries are saved and loaded as text, one word per line:
+//!
+//! ```text
+//! # surface,reading,cost,pos_level1[,pos_level2[,pos_level3[,pos_level4]]]
+//! 東京スカイツリー,トウキョウスカイツリー,-2000,名詞,固有名詞,一般
+//! ```
+//!
+//! Empty lines and lines starting with `#` are ignored.
+use crate::{Dictionary, KuromojiError, WordDetail, WordEntry, WordId};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+use std::iter;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+// Word ids of user words have their highest bit set, so that they never collide
+// with the word ids of the system dictionary.
+const USER_WORD_BIT: u32 = 1 << 31;
+
+/// Word of a `UserDictionary`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UserWord {
+    pub word_id: WordId,
+    pub surface: String,
+    pub reading: String,
+    /// Up to four levels of part-of-speech.
+    pub pos: Vec<String>,
+    pub cost: i16,
+    /// Context id of the word, taken from the system words with the same part-of-speech.
+    pub context_id: u16,
+}
+
+impl UserWord {
+    pub fn word_entry(&self) -> WordEntry {
+        WordEntry {
+            word_id: self.word_id,
+            word_cost: self.cost,
+            cost_id: self.context_id,
+        }
+    }
+
+    pub fn detail(&self) -> WordDetail<'_> {
+        let pos_level = |level: usize| self.pos.get(level).map(String::as_str).unwrap_or("*");
+        WordDetail {
+            pos_level1: pos_level(0),
+            pos_level2: pos_level(1),
+            pos_level3: pos_level(2),
+            pos_level4: pos_level(3),
+            conjugation_type: "*",
+            conjugate_form: "*",
+            base_form: &self.surface,
+            reading: &self.reading,
+            pronunciation: &self.reading,
+        }
+    }
+}
+
+/// Snapshot of the words of a `UserDictionary`.
+#[derive(Clone, Default)]
+pub(crate) struct UserWords {
+    // keyed by the word id, without its user bit. Removed words are dropped from
+    // the map, and the ids of the other words do not change.
+    words: BTreeMap<u32, Arc<UserWord>>,
+    by_surface: HashMap<String, Vec<u32>>,
+    max_surface_len: usize,
+    next_word_ord: u32,
+}
+
+impl UserWords {
+    pub fn word(&self, word_id: WordId) -> Option<&Arc<UserWord>> {
+        if !word_id.is_user() {
+            return None;
+        }
+        self.words.get(&(word_id.0 & !USER_WORD_BIT))
+    }
+
+    /// Calls `callback(prefix_len, word_entry)` for each user word that is a prefix of `s`.
+    pub fn for_each_prefix(&self, s: &str, mut callback: impl FnMut(usize, WordEntry)) {
+        if self.by_surface.is_empty() {
+            return;
+        }
+        let prefix_lens = s
+            .char_indices()
+            .map(|(offset, c)| offset + c.len_utf8())
+            .take_while(|&prefix_len| prefix_len <= self.max_surface_len);
+        for prefix_len in prefix_lens {
+            if let Some(word_ords) = self.by_surface.get(&s[..prefix_len]) {
+                for word_ord in word_ords {
+                    callback(prefix_len, self.words[word_ord].word_entry());
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, mut word: UserWord) -> WordId {
+        let word_ord = self.next_word_ord;
+        self.next_word_ord += 1;
+        word.word_id = WordId(word_ord | USER_WORD_BIT);
+        self.max_surface_len = self.max_surface_len.max(word.surface.len());
+        self.by_surface
+            .entry(word.surface.clone())
+            .or_default()
+            .push(word_ord);
+        let word_id = word.word_id;
+        self.words.insert(word_ord, Arc::new(word));
+        word_id
+    }
+
+    fn remove(&mut self, surface: &str) -> bool {
+        match self.by_surface.remove(surface) {
+            Some(word_ords) => {
+                for word_ord in word_ords {
+                    self.words.remove(&word_ord);
+                }
+                if surface.len() == self.max_surface_len {
+                    self.max_surface_len =
+                        self.by_surface.keys().map(String::len).max().unwrap_or(0);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+enum Change {
+    Add(UserWord),
+    Remove(String),
+}
+
+/// Changes to a `UserDictionary`, applied at once by `commit`.
+///
+/// Tokenizers share the words of the dictionary, so each change copies them:
+/// batching changes copies them only once. Until the batch is committed, its changes
+/// are not visible to tokenizers.
+pub struct UserDictionaryBatch<'a> {
+    user_dictionary: &'a UserDictionary,
+    changes: Vec<Change>,
+}
+
+impl<'a> UserDictionaryBatch<'a> {
+    /// Adds a word when the batch is committed, see `UserDictionary::add_word`.
+    ///
+    /// Fails right away if the word is invalid.
+    pub fn add_word(
+        &mut self,
+        surface: &str,
+        reading: &str,
+        pos: &[&str],
+        cost: i16,
+    ) -> Result<(), KuromojiError> {
+        let word = self.user_dictionary.new_word(surface, reading, pos, cost)?;
+        self.changes.push(Change::Add(word));
+        Ok(())
+    }
+
+    /// Removes all of the words with the given surface form when the batch is committed,
+    /// including the ones added earlier in the batch.
+    pub fn remove_word(&mut self, surface: &str) {
+        self.changes.push(Change::Remove(surface.to_string()));
+    }
+
+    /// Number of changes of the batch.
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the changes of the batch, in order, and returns the word ids of
+    /// the added words.
+    pub fn commit(self) -> Vec<WordId> {
+        self.commit_changes().0
+    }
+
+    /// Returns the word ids of the added words, and whether any word was removed.
+    fn commit_changes(self) -> (Vec<WordId>, bool) {
+        let mut word_ids = Vec::new();
+        let mut removed = false;
+        if self.changes.is_empty() {
+            return (word_ids, removed);
+        }
+        let mut words_lock = self
+            .user_dictionary
+            .words
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // the snapshots of tokenizers keep the current words alive, so the new
+        // words are a copy of them, swapped in at once.
+        let mut words = UserWords::clone(&words_lock);
+        for change in self.changes {
+            match change {
+                Change::Add(word) => word_ids.push(words.insert(word)),
+                Change::Remove(surface) => removed |= words.remove(&surface),
+            }
+        }
+        *words_lock = Arc::new(words);
+        (word_ids, removed)
+    }
+}
+
+/// In-memory dictionary of words overlaid on the system dictionary.
+///
+/// Words can be added and removed while tokenizers are using the dictionary.
+/// Clones share the same words, and each tokenization sees the words as they
+/// were when it started.
+///
+/// See `Tokenizer::with_user_dictionary`.
+#[derive(Clone)]
+pub struct UserDictionary {
+    dictionary: Arc<Dictionary>,
+    words: Arc<RwLock<Arc<UserWords>>>,
+}
+
+impl UserDictionary {
+    /// Creates an empty user dictionary, overlaid on `dictionary`.
+    pub fn new(dictionary: Arc<Dictionary>) -> UserDictionary {
+        UserDictionary {
+            dictionary,
+            words: Arc::default(),
+        }
+    }
+
+    pub fn dictionary(&self) -> &Arc<Dictionary> {
+        &self.dictionary
+    }
+
+    /// Returns the current words of the dictionary.
+    pub(crate) fn snapshot(&self) -> Arc<UserWords> {
+        self.words
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Returns an empty batch of changes to the dictionary.
+    pub fn batch(&self) -> UserDictionaryBatch<'_> {
+        UserDictionaryBatch {
+            user_dictionary: self,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Adds a word, and returns its word id.
+    ///
+    /// `pos` lists up to four levels of part-of-speech. At least one word of the
+    /// system dictionary must share these levels, as the context id of the word is
+    /// taken from these words. Fields must not contain any comma or line break.
+    ///
+    /// The words are copied at each change: use a `batch` to add many words.
+    pub fn add_word(
+        &self,
+        surface: &str,
+        reading: &str,
+        pos: &[&str],
+        cost: i16,
+    ) -> Result<WordId, KuromojiError> {
+        let mut batch = self.batch();
+        batch.add_word(surface, reading, pos, cost)?;
+        Ok(batch.commit()[0])
+    }
+
+    /// Checks a word, and returns it without a word id.
+    fn new_word(
+        &self,
+        surface: &str,
+        reading: &str,
+        pos: &[&str],
+        cost: i16,
+    ) -> Result<UserWord, KuromojiError> {
+        if surface.is_empty() {
+            return Err(KuromojiError::Format("Empty surface form".to_string()));
+        }
+        let mut fields = iter::once(surface)
+            .chain(Some(reading))
+            .chain(pos.iter().cloned());
+        if let Some(field) = fields.find(|field| field.contains([',', '\n', '\r'])) {
+            return Err(KuromojiError::Format(format!(
+                "Field {:?} contains a comma or a line break",
+                field
+            )));
+        }
+        let context_id = self.dictionary.context_id(pos).ok_or_else(|| {
+            KuromojiError::Format(format!("No system word with the part-of-speech {:?}", pos))
+        })?;
+        Ok(UserWord {
+            word_id: WordId::default(),
+            surface: surface.to_string(),
+            reading: reading.to_string(),
+            pos: pos.iter().map(|level| level.to_string()).collect(),
+            cost,
+            context_id,
+        })
+    }
+
+    /// Removes all of the words with the given surface form.
+    ///
+    /// Returns false if there were none.
+    pub fn remove_word(&self, surface: &str) -> bool {
+        if !self.snapshot().by_surface.contains_key(surface) {
+            return false;
+        }
+        let mut batch = self.batch();
+        batch.remove_word(surface);
+        batch.commit_changes().1
+    }
+
+    /// Returns the words of the dictionary, in the order they were added.
+    pub fn words(&self) -> Vec<Arc<UserWord>> {
+        self.snapshot().words.values().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshot().words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the words of a user dictionary file, and returns their number.
+    ///
+    /// The words are added at once: tokenizers never see part of the file, and
+    /// if any line is invalid, no word is added.
+    pub fn load<R: BufRead>(&self, reader: R) -> Result<usize, KuromojiError> {
+        let mut batch = self.batch();
+        for (line_ord, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() < 4 || fields.len() > 7 {
+                return Err(KuromojiError::Format(format!(
+                    "Line {}: expected 4 to 7 fields, got {}",
+                    line_ord + 1,
+                    fields.len()
+                )));
+            }
+            let cost = i16::from_str(fields[2]).map_err(|_| {
+                KuromojiError::Format(format!(
+                    "Line {}: invalid cost {:?}",
+                    line_ord + 1,
+                    fields[2]
+                ))
+            })?;
+            batch.add_word(fields[0], fields[1], &fields[3..], cost)?;
+        }
+        Ok(batch.commit().len())
+    }
+
+    /// Writes the words of the dictionary, in the format read by `load`.
+    pub fn save<W: Write>(&self, wtr: &mut W) -> Result<(), KuromojiError> {
+        for word in self.words() {
+            writeln!(
+                wtr,
+                "{},{},{},{}",
+                word.surface,
+                word.reading,
+                word.cost,
+                word.pos.join(",")
+            )?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserDictionary;
+    use crate::{Dictionary, Mode, Tokenizer, WordDetail};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_user_dictionary() {
+        let dictionary = Arc::new(Dictionary::load_default().unwrap());
+        let user_dictionary = UserDictionary::new(dictionary.clone());
+        let mut tokenizer = Tokenizer::with_dictionary(dictionary, Mode::Normal)
+            .with_user_dictionary(user_dictionary.clone());
+        assert_eq!(
+            tokenizer.tokenize_str("東京スカイツリーに行く"),
+            vec!["東京", "スカイ", "ツリー", "に", "行く"]
+        );
+
+        let word_id = user_dictionary
+            .add_word(
+                "東京スカイツリー",
+                "トウキョウスカイツリー",
+                &["名詞", "固有名詞"],
+                -2000,
+            )
+            .unwrap();
+        assert!(word_id.is_user());
+        let tokens = tokenizer.tokenize("東京スカイツリーに行く");
+        assert_eq!(tokens[0].text, "東京スカイツリー");
+        assert_eq!(tokens[0].word_id, word_id);
+        assert_eq!(
+            tokenizer.detail(&tokens[0]).reading,
+            "トウキョウスカイツリー"
+        );
+        assert_eq!(
+            tokenizer.detail(&tokens[0]).pos()[..2],
+            ["名詞", "固有名詞"]
+        );
+
+        assert!(user_dictionary.remove_word("東京スカイツリー"));
+        assert!(!user_dictionary.remove_word("東京スカイツリー"));
+        assert!(user_dictionary.is_empty());
+        // the tokenizer keeps the details of removed words until its next tokenization.
+        assert_eq!(
+            tokenizer.detail(&tokens[0]).reading,
+            "トウキョウスカイツリー"
+        );
+        assert_eq!(
+            tokenizer.tokenize_str("東京スカイツリーに行く"),
+            vec!["東京", "スカイ", "ツリー", "に", "行く"]
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_user_dictionary_tokenize_batch() {
+        let dictionary = Arc::new(Dictionary::load_default().unwrap());
+        let user_dictionary = UserDictionary::new(dictionary.clone());
+        let mut tokenizer = Tokenizer::with_dictionary(dictionary, Mode::Normal)
+            .with_user_dictionary(user_dictionary.clone());
+        user_dictionary
+            .add_word("ほげ", "ホゲ", &["名詞"], -2000)
+            .unwrap();
+        let batch = tokenizer.tokenize_batch(&["ほげに行く", "ほげ"]);
+        assert_eq!(batch[1][0].text, "ほげ");
+        assert_eq!(tokenizer.detail(&batch[0][0]).reading, "ホゲ");
+        assert_eq!(tokenizer.detail(&batch[1][0]).reading, "ホゲ");
+    }
+
+    #[test]
+    fn test_user_dictionary_invalid_word() {
+        let user_dictionary = UserDictionary::new(Arc::new(Dictionary::load_default().unwrap()));
+        assert!(user_dictionary.add_word("", "", &["名詞"], 0).is_err());
+        assert!(user_dictionary.add_word("a,b", "", &["名詞"], 0).is_err());
+        assert!(user_dictionary
+            .add_word("ほげ", "ホゲ", &["存在しない品詞"], 0)
+            .is_err());
+        assert!(user_dictionary.is_empty());
+    }
+
+    #[test]
+    fn test_user_dictionary_load_save() {
+        let user_dictionary = UserDictionary::new(Arc::new(Dictionary::load_default().unwrap()));
+        let data = "# surface,reading,cost,pos\n\
+                    \n\
+                    東京スカイツリー,トウキョウスカイツリー,-2000,名詞,固有名詞,一般\n\
+                    ほげ,ホゲ,100,名詞\n";
+        assert_eq!(user_dictionary.load(data.as_bytes()).unwrap(), 2);
+        let words = user_dictionary.words();
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[1].detail().pos(), ["名詞", "*", "*", "*"]);
+        assert_eq!(words[1].detail().base_form, "ほげ");
+
+        let mut saved = Vec::new();
+        user_dictionary.save(&mut saved).unwrap();
+        let reloaded = UserDictionary::new(user_dictionary.dictionary().clone());
+        assert_eq!(reloaded.load(&saved[..]).unwrap(), 2);
+        let details: Vec<WordDetail> = words.iter().map(|word| word.detail()).collect();
+        let reloaded_words = reloaded.words();
+        let reloaded_details: Vec<WordDetail> =
+            reloaded_words.iter().map(|word| word.detail()).collect();
+        assert_eq!(details, reloaded_details);
+
+        assert!(user_dictionary.load("ほげ,ホゲ,名詞".as_bytes()).is_err());
+        assert!(user_dictionary
+            .load("ほげ,ホゲ,abc,名詞".as_bytes())
+            .is_err());
+        // the valid first line of an invalid file is not added either.
+        assert!(user_dictionary
+            .load("ふが,フガ,0,名詞\nほげ,ホゲ,名詞\n".as_bytes())
+            .is_err());
+        assert_eq!(user_dictionary.len(), 2);
+    }
+
+    #[test]
+    fn test_user_dictionary_batch() {
+        let user_dictionary = UserDictionary::new(Arc::new(Dictionary::load_default().unwrap()));
+        let hoge_id = user_dictionary
+            .add_word("ほげ", "ホゲ", &["名詞"], 0)
+            .unwrap();
+        let snapshot = user_dictionary.snapshot();
+
+        let mut batch = user_dictionary.batch();
+        batch.add_word("ふが", "フガ", &["名詞"], 0).unwrap();
+        batch.add_word("ぴよ", "ピヨ", &["名詞"], 0).unwrap();
+        batch.remove_word("ふが");
+        assert!(batch.add_word("", "", &["名詞"], 0).is_err());
+        assert_eq!(batch.len(), 3);
+        assert_eq!(user_dictionary.len(), 1);
+        let word_ids = batch.commit();
+        assert_eq!(word_ids.len(), 2);
+
+        let surfaces: Vec<String> = user_dictionary
+            .words()
+            .iter()
+            .map(|word| word.surface.clone())
+            .collect();
+        assert_eq!(surfaces, vec!["ほげ", "ぴよ"]);
+        assert!(snapshot.word(word_ids[1]).is_none());
+        let snapshot = user_dictionary.snapshot();
+        assert!(snapshot.word(word_ids[0]).is_none());
+        assert_eq!(snapshot.word(word_ids[1]).unwrap().surface, "ぴよ");
+
+        // removed words leave no trace, and do not change the ids of the other words.
+        assert!(user_dictionary.remove_word("ほげ"));
+        let snapshot = user_dictionary.snapshot();
+        assert!(snapshot.word(hoge_id).is_none());
+        assert_eq!(snapshot.words.len(), 1);
+        assert_eq!(snapshot.word(word_ids[1]).unwrap().surface, "ぴよ");
+    }
+}
@@ -1,25 +1,25 @@
 use crate::character_definition::CategoryId;
 use crate::connection::ConnectionCostMatrix;
-use crate::prefix_dict::PrefixDict;
+use crate::cost_adjustment::ResolvedCostAdjustments;
+use crate::prefix_index::KeyAutomaton;
+use crate::typo_tolerance::{TypoAutomaton, TypoTolerance};
 use crate::unknown_dictionary::UnknownDictionary;
-use crate::word_entry::WordDictionary;
-use crate::{CharacterDefinitions, Mode, WordDetail, WordEntry, WordId};
-use std::u32;
+use crate::user_dictionary::UserWords;
+use crate::word_mask::WordMask;
+use crate::{CharacterDefinitions, Dictionary, KuromojiError, Mode, WordEntry, WordId};
+use std::ops::Range;
 
 const EOS_NODE: EdgeId = EdgeId(1u32);
 
-#[derive(Clone, Copy, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Default)]
 pub enum EdgeType {
+    #[default]
     KNOWN,
     UNKNOWN,
     USER,
     INSERTED,
-}
-
-impl Default for EdgeType {
-    fn default() -> Self {
-        EdgeType::KNOWN
-    }
+    TYPO,
 }
 
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
@@ -46,17 +46,130 @@ impl Edge {
     }
 }
 
-#[derive(Default)]
+/// Constraints on the segmentation of a text, as in MeCab's partial parsing.
+///
+/// Offsets are byte offsets in the text, and must fall on character boundaries.
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    boundaries: Vec<usize>,
+    tokens: Vec<TokenConstraint>,
+}
+
+#[derive(Clone, Debug)]
+struct TokenConstraint {
+    range: Range<usize>,
+    pos: Vec<String>,
+}
+
+impl TokenConstraint {
+    fn matches(&self, dictionary: &Dictionary, user_words: &UserWords, word_id: WordId) -> bool {
+        if self.pos.is_empty() {
+            return true;
+        }
+        let word_detail = match user_words.word(word_id) {
+            Some(user_word) => user_word.detail(),
+            None => match dictionary.word_detail(word_id) {
+                Ok(word_detail) => word_detail,
+                Err(_) => return false,
+            },
+        };
+        let word_pos = word_detail.pos();
+        self.pos
+            .iter()
+            .zip(word_pos.iter())
+            .all(|(pos, word_pos)| pos == "*" || pos == word_pos)
+    }
+}
+
+impl Constraints {
+    /// Forces a token boundary at `offset`.
+    pub fn add_boundary(&mut self, offset: usize) {
+        self.boundaries.push(offset);
+    }
+
+    /// Forces `range` to be a single token. Token ranges must not be empty, and must
+    /// not overlap.
+    ///
+    /// `pos` lists the first levels of the part-of-speech of the token, `*` matching
+    /// any level. Among the dictionary words spanning exactly `range`, only the ones
+    /// with a matching part-of-speech are kept. If there are none, the span is
+    /// inserted as an unknown word.
+    pub fn add_token(&mut self, range: Range<usize>, pos: &[&str]) {
+        self.tokens.push(TokenConstraint {
+            range,
+            pos: pos.iter().map(|level| level.to_string()).collect(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boundaries.is_empty() && self.tokens.is_empty()
+    }
+
+    /// Checks that the constraints can be applied to `text`: offsets must be character
+    /// boundaries of `text`, and token ranges must be non-empty and must not overlap.
+    pub fn validate(&self, text: &str) -> Result<(), KuromojiError> {
+        let offsets = self.boundaries.iter().cloned().chain(
+            self.tokens
+                .iter()
+                .flat_map(|token| vec![token.range.start, token.range.end]),
+        );
+        for offset in offsets {
+            if offset > text.len() {
+                return Err(KuromojiError::InvalidConstraints(format!(
+                    "Constraint offset {} is past the end of the text, of length {}",
+                    offset,
+                    text.len()
+                )));
+            }
+            if !text.is_char_boundary(offset) {
+                return Err(KuromojiError::InvalidConstraints(format!(
+                    "Constraint offset {} is not a character boundary",
+                    offset
+                )));
+            }
+        }
+        let mut ranges: Vec<&Range<usize>> = self.tokens.iter().map(|token| &token.range).collect();
+        if let Some(range) = ranges.iter().find(|range| range.start >= range.end) {
+            return Err(KuromojiError::InvalidConstraints(format!(
+                "Token constraint {:?} is empty",
+                range
+            )));
+        }
+        ranges.sort_by_key(|range| range.start);
+        if let Some(pair) = ranges.windows(2).find(|pair| pair[0].end > pair[1].start) {
+            return Err(KuromojiError::InvalidConstraints(format!(
+                "Token constraints {:?} and {:?} overlap",
+                pair[0], pair[1]
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Lattice {
     capacity: usize,
     edges: Vec<Edge>,
     starts_at: Vec<Vec<EdgeId>>,
     ends_at: Vec<Vec<EdgeId>>,
+    // Constraints of the current text, empty if the text is unconstrained.
+    // `next_boundary[i]` is the first fixed boundary after `i`, `inside_token[i]` tells
+    // whether `i` is strictly within a forced token, and `forced_token[i]` is the
+    // ordinal of the forced token starting at `i`.
+    next_boundary: Vec<usize>,
+    inside_token: Vec<bool>,
+    forced_token: Vec<Option<usize>>,
+    // corrected surface forms of the `EdgeType::TYPO` edges, by increasing edge id.
+    typo_surfaces: Vec<(EdgeId, String)>,
+    // requested part-of-speech of the `EdgeType::INSERTED` edges, by increasing edge id.
+    inserted_pos: Vec<(EdgeId, Vec<String>)>,
+    // automaton of `add_typo_edges`, kept to reuse its buffer at each position.
+    typo_automaton: TypoAutomaton,
 }
 
 fn is_kanji(c: char) -> bool {
     let c = c as u32;
-    (c >= 19968 && c <= 40879)
+    (19968..=40879).contains(&c)
 }
 
 fn is_kanji_only(s: &str) -> bool {
@@ -71,7 +184,12 @@ impl Lattice {
         for edge_vec in &mut self.ends_at {
             edge_vec.clear();
         }
-        self.edges.clear()
+        self.edges.clear();
+        self.next_boundary.clear();
+        self.inside_token.clear();
+        self.forced_token.clear();
+        self.typo_surfaces.clear();
+        self.inserted_pos.clear();
     }
 
     fn set_capacity(&mut self, text_len: usize) {
@@ -84,17 +202,78 @@ impl Lattice {
         }
     }
 
+    fn set_constraints(&mut self, constraints: &Constraints, len: usize) {
+        if constraints.is_empty() {
+            return;
+        }
+        let mut is_boundary = vec![false; len + 1];
+        for &boundary in &constraints.boundaries {
+            is_boundary[boundary] = true;
+        }
+        self.inside_token.resize(len + 1, false);
+        self.forced_token.resize(len + 1, None);
+        for (token_ord, token) in constraints.tokens.iter().enumerate() {
+            is_boundary[token.range.start] = true;
+            is_boundary[token.range.end] = true;
+            self.forced_token[token.range.start] = Some(token_ord);
+            for inside in &mut self.inside_token[token.range.start + 1..token.range.end] {
+                *inside = true;
+            }
+        }
+        self.next_boundary.resize(len + 1, len);
+        for offset in (0..len).rev() {
+            self.next_boundary[offset] = if is_boundary[offset + 1] {
+                offset + 1
+            } else {
+                self.next_boundary[offset + 1]
+            };
+        }
+    }
+
+    /// Returns the end of the longest word starting at `start` that does not
+    /// cross a fixed boundary.
+    fn max_stop(&self, start: usize, stop: usize) -> usize {
+        self.next_boundary
+            .get(start)
+            .map(|&next_boundary| stop.min(next_boundary))
+            .unwrap_or(stop)
+    }
+
+    /// Returns true if an edge spanning `start..stop` complies with the constraints.
+    fn is_allowed(&self, start: usize, stop: usize) -> bool {
+        if self.next_boundary.is_empty() {
+            return true;
+        }
+        stop <= self.next_boundary[start] && !self.inside_token[stop]
+    }
+
+    /// Builds the lattice of `text`.
+    ///
+    /// Edges crossing the boundaries fixed by `constraints` are dropped, and the
+    /// forced tokens with no matching dictionary word are inserted as
+    /// `EdgeType::INSERTED` edges.
+    ///
+    /// If `typo_tolerance` is set, the words of the dictionary within one edit of
+    /// a span of the text are added as `EdgeType::TYPO` edges.
     #[inline(never)]
+    #[allow(clippy::too_many_arguments)]
     pub fn set_text(
         &mut self,
-        dict: &PrefixDict,
-        char_definitions: &CharacterDefinitions,
-        unknown_dictionary: &UnknownDictionary,
+        dictionary: &Dictionary,
+        user_words: &UserWords,
+        word_mask: &WordMask,
+        cost_adjustments: &ResolvedCostAdjustments,
+        typo_tolerance: Option<&TypoTolerance>,
         text: &str,
         search_mode: &Mode,
+        constraints: &Constraints,
     ) {
+        let dict = &dictionary.dict;
+        let char_definitions = &dictionary.char_definitions;
+        let unknown_dictionary = &dictionary.unknown_dictionary;
         let len = text.len();
         self.set_capacity(len);
+        self.set_constraints(constraints, len);
 
         let start_edge_id = self.add_edge(Edge::default());
         let end_edge_id = self.add_edge(Edge::default());
@@ -109,28 +288,76 @@ impl Lattice {
         for start in 0..len {
             // No arc is ending here.
             // No need to check if a valid word starts here.
-            if self.ends_at[start as usize].is_empty() {
+            if self.ends_at[start].is_empty() {
                 continue;
             }
 
             let suffix = &text[start..];
 
             let mut found: bool = false;
+            let forced_token = self
+                .forced_token
+                .get(start)
+                .cloned()
+                .flatten()
+                .map(|token_ord| &constraints.tokens[token_ord]);
 
             // we check all word starting at start, using the fst, like we would use
             // a prefix trie, and populate the lattice with as many edges
-            for (prefix_len, word_entry) in dict.prefix(suffix) {
-                let edge = Edge {
-                    edge_type: EdgeType::KNOWN,
-                    word_entry,
-                    left_edge: None,
-                    start_index: start as u32,
-                    stop_index: (start + prefix_len) as u32,
-                    path_cost: i32::max_value(),
-                    kanji_only: is_kanji_only(&suffix[..prefix_len]),
+            let mut add_word_edge =
+                |edge_type: EdgeType, prefix_len: usize, mut word_entry: WordEntry| {
+                    if !self.is_allowed(start, start + prefix_len) {
+                        return;
+                    }
+                    if let Some(forced_token) = forced_token {
+                        if !forced_token.matches(dictionary, user_words, word_entry.word_id) {
+                            return;
+                        }
+                    }
+                    cost_adjustments.adjust(&mut word_entry);
+                    let edge = Edge {
+                        edge_type,
+                        word_entry,
+                        left_edge: None,
+                        start_index: start as u32,
+                        stop_index: (start + prefix_len) as u32,
+                        path_cost: i32::MAX,
+                        kanji_only: is_kanji_only(&suffix[..prefix_len]),
+                    };
+                    self.add_edge_in_lattice(edge);
+                    found = true;
                 };
-                self.add_edge_in_lattice(edge);
-                found = true;
+            dict.for_each_prefix(suffix, |prefix_len, word_entry| {
+                if !word_mask.is_masked(dictionary, &suffix[..prefix_len], word_entry.word_id) {
+                    add_word_edge(EdgeType::KNOWN, prefix_len, word_entry)
+                }
+            });
+            user_words.for_each_prefix(suffix, |prefix_len, word_entry| {
+                add_word_edge(EdgeType::USER, prefix_len, word_entry)
+            });
+            if let Some(typo_tolerance) = typo_tolerance {
+                if forced_token.is_none() {
+                    self.add_typo_edges(
+                        dictionary,
+                        word_mask,
+                        cost_adjustments,
+                        typo_tolerance,
+                        start,
+                        suffix,
+                    );
+                }
+            }
+
+            if let Some(forced_token) = forced_token {
+                if !found {
+                    self.insert_forced_token(
+                        dictionary,
+                        &text[forced_token.range.clone()],
+                        &forced_token.pos,
+                        start,
+                    );
+                }
+                continue;
             }
 
             // In the case of normal mode, it doesn't process unknown word greedily.
@@ -147,7 +374,7 @@ impl Lattice {
                             category_ord,
                             unknown_word_end,
                             start,
-                            suffix,
+                            &suffix[..self.max_stop(start, len) - start],
                             found,
                         );
                     }
@@ -156,6 +383,111 @@ impl Lattice {
         }
     }
 
+    /// Adds the edges of the words of the dictionary within one edit of a span
+    /// of `suffix`, starting at `start`.
+    fn add_typo_edges(
+        &mut self,
+        dictionary: &Dictionary,
+        word_mask: &WordMask,
+        cost_adjustments: &ResolvedCostAdjustments,
+        typo_tolerance: &TypoTolerance,
+        start: usize,
+        suffix: &str,
+    ) {
+        let mut typo_automaton = std::mem::take(&mut self.typo_automaton);
+        typo_automaton.reset(suffix);
+        let automaton = KeyAutomaton::typos(typo_automaton);
+        dictionary
+            .dict
+            .for_each_word_matching(&automaton, |surface, mut word_entry| {
+                if surface.chars().count() < typo_tolerance.min_word_len
+                    || word_mask.is_masked(dictionary, surface, word_entry.word_id)
+                {
+                    return;
+                }
+                cost_adjustments.adjust(&mut word_entry);
+                let word_cost = word_entry.word_cost as i32 + typo_tolerance.penalty;
+                word_entry.word_cost = word_cost.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                for num_chars in automaton.typo_spans(surface.as_bytes()) {
+                    let span_len = suffix
+                        .char_indices()
+                        .nth(num_chars)
+                        .map(|(byte_offset, _)| byte_offset)
+                        .unwrap_or(suffix.len());
+                    if !self.is_allowed(start, start + span_len) {
+                        continue;
+                    }
+                    let edge = Edge {
+                        edge_type: EdgeType::TYPO,
+                        word_entry,
+                        left_edge: None,
+                        start_index: start as u32,
+                        stop_index: (start + span_len) as u32,
+                        path_cost: i32::MAX,
+                        kanji_only: is_kanji_only(&suffix[..span_len]),
+                    };
+                    let edge_id = self.add_edge_in_lattice(edge);
+                    self.typo_surfaces.push((edge_id, surface.to_string()));
+                }
+            });
+        self.typo_automaton = automaton.into_typo_automaton().unwrap_or_default();
+    }
+
+    /// Inserts `token` as an unknown word. If a part-of-speech is requested, the
+    /// edge takes the context id of its known levels, and the part-of-speech is
+    /// kept for the details of the token, see `insertions`.
+    fn insert_forced_token(
+        &mut self,
+        dictionary: &Dictionary,
+        token: &str,
+        pos: &[String],
+        start: usize,
+    ) {
+        let unknown_dictionary = &dictionary.unknown_dictionary;
+        let mut word_entry = token
+            .chars()
+            .next()
+            .and_then(|first_char| {
+                dictionary
+                    .char_definitions
+                    .lookup_categories(first_char)
+                    .first()
+                    .cloned()
+            })
+            .and_then(|category| {
+                unknown_dictionary
+                    .lookup_word_ids(category)
+                    .first()
+                    .cloned()
+            })
+            .map(|word_id| unknown_dictionary.word_entry(word_id))
+            .unwrap_or_default();
+        let known_levels: Vec<&str> = pos
+            .iter()
+            .map(String::as_str)
+            .take_while(|&level| level != "*")
+            .collect();
+        if !known_levels.is_empty() {
+            if let Some(context_id) = dictionary.context_id(&known_levels) {
+                word_entry.cost_id = context_id;
+            }
+        }
+        let edge = Edge {
+            edge_type: EdgeType::INSERTED,
+            word_entry,
+            left_edge: None,
+            start_index: start as u32,
+            stop_index: (start + token.len()) as u32,
+            path_cost: i32::MAX,
+            kanji_only: is_kanji_only(token),
+        };
+        let edge_id = self.add_edge_in_lattice(edge);
+        if !pos.is_empty() {
+            self.inserted_pos.push((edge_id, pos.to_vec()));
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn process_unknown_word(
         &mut self,
         char_definitions: &CharacterDefinitions,
@@ -173,7 +505,7 @@ impl Lattice {
             unknown_word_num_chars = 1;
             if category_data.group {
                 for c in suffix.chars().skip(1) {
-                    let mut categories = char_definitions.lookup_categories(c);
+                    let categories = char_definitions.lookup_categories(c);
                     if categories.len() > category_ord && categories[category_ord] == category {
                         unknown_word_num_chars += 1;
                     } else {
@@ -183,11 +515,12 @@ impl Lattice {
             }
         }
         if unknown_word_num_chars > 0 {
-            // optimize
-            let unknown_word = suffix
-                .chars()
-                .take(unknown_word_num_chars)
-                .collect::<String>();
+            let unknown_word_len = suffix
+                .char_indices()
+                .nth(unknown_word_num_chars)
+                .map(|(byte_offset, _)| byte_offset)
+                .unwrap_or(suffix.len());
+            let unknown_word = &suffix[..unknown_word_len];
             for &word_id in unknown_dictionary.lookup_word_ids(category) {
                 let word_entry = unknown_dictionary.word_entry(word_id);
                 let edge = Edge {
@@ -196,8 +529,8 @@ impl Lattice {
                     left_edge: None,
                     start_index: start as u32,
                     stop_index: (start + unknown_word.len()) as u32,
-                    path_cost: i32::max_value(),
-                    kanji_only: is_kanji_only(&unknown_word[..]),
+                    path_cost: i32::MAX,
+                    kanji_only: is_kanji_only(unknown_word),
                 };
                 self.add_edge_in_lattice(edge);
             }
@@ -206,12 +539,13 @@ impl Lattice {
         unknown_word_index
     }
 
-    fn add_edge_in_lattice(&mut self, edge: Edge) {
+    fn add_edge_in_lattice(&mut self, edge: Edge) -> EdgeId {
         let start_index = edge.start_index as usize;
         let stop_index = edge.stop_index as usize;
         let edge_id = self.add_edge(edge);
         self.starts_at[start_index].push(edge_id);
         self.ends_at[stop_index].push(edge_id);
+        edge_id
     }
 
     fn add_edge(&mut self, edge: Edge) -> EdgeId {
@@ -256,7 +590,6 @@ impl Lattice {
     pub fn tokens_offset(&self, offsets: &mut Vec<(usize, WordId)>) {
         offsets.clear();
         let mut edge_id = EOS_NODE;
-        let edge = self.edge(EOS_NODE);
         loop {
             let edge = self.edge(edge_id);
             if let Some(left_edge_id) = edge.left_edge {
@@ -269,4 +602,46 @@ impl Lattice {
         offsets.reverse();
         offsets.pop();
     }
+
+    /// Returns the start offsets and the corrected surface forms of the
+    /// `EdgeType::TYPO` edges of the best path, in the order of the text.
+    pub fn corrections(&self, corrections: &mut Vec<(usize, String)>) {
+        corrections.clear();
+        if self.typo_surfaces.is_empty() {
+            return;
+        }
+        let mut edge_id = EOS_NODE;
+        while let Some(left_edge_id) = self.edge(edge_id).left_edge {
+            edge_id = left_edge_id;
+            if let Ok(typo_ord) = self
+                .typo_surfaces
+                .binary_search_by_key(&edge_id.0, |(typo_edge_id, _)| typo_edge_id.0)
+            {
+                let start = self.edge(edge_id).start_index as usize;
+                corrections.push((start, self.typo_surfaces[typo_ord].1.clone()));
+            }
+        }
+        corrections.reverse();
+    }
+
+    /// Returns the start offsets and the requested parts-of-speech of the
+    /// `EdgeType::INSERTED` edges of the best path, in the order of the text.
+    pub fn insertions(&self, insertions: &mut Vec<(usize, Vec<String>)>) {
+        insertions.clear();
+        if self.inserted_pos.is_empty() {
+            return;
+        }
+        let mut edge_id = EOS_NODE;
+        while let Some(left_edge_id) = self.edge(edge_id).left_edge {
+            edge_id = left_edge_id;
+            if let Ok(inserted_ord) = self
+                .inserted_pos
+                .binary_search_by_key(&edge_id.0, |(inserted_edge_id, _)| inserted_edge_id.0)
+            {
+                let start = self.edge(edge_id).start_index as usize;
+                insertions.push((start, self.inserted_pos[inserted_ord].1.clone()));
+            }
+        }
+        insertions.reverse();
+    }
 }
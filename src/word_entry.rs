@@ -1,31 +1,117 @@
+use crate::{DictData, KuromojiError, WordId};
 use byteorder::WriteBytesExt;
 use byteorder::{ByteOrder, LittleEndian};
 use serde::{Deserialize, Serialize};
 use std::io;
-use crate::WordId;
+use std::ops::Deref;
 
-const WORDS_DATA: &'static [u8] = include_bytes!("../dict/dict.words");
-const WORDS_IDX_DATA: &'static [u8] = include_bytes!("../dict/dict.wordsidx");
+const WORDS_DATA: &[u8] = include_bytes!("../dict/dict.words");
+const WORDS_IDX_DATA: &[u8] = include_bytes!("../dict/dict.wordsidx");
 
-pub struct WordDictionary;
+/// Details of the words of a dictionary, indexed by `WordId`.
+pub struct WordDictionary<Data = DictData> {
+    words_idx_data: Data,
+    words_data: Data,
+}
+
+impl WordDictionary<DictData> {
+    /// Returns the word details embedded in the library.
+    pub fn load_default() -> WordDictionary {
+        WordDictionary {
+            words_idx_data: DictData::from_static(WORDS_IDX_DATA),
+            words_data: DictData::from_static(WORDS_DATA),
+        }
+    }
+}
 
-impl WordDictionary {
-    pub fn load_word_id(word_id: WordId) -> WordDetail {
+impl<Data: Deref<Target = [u8]>> WordDictionary<Data> {
+    /// Loads word details in the format of `dict.wordsidx` and `dict.words`.
+    pub fn from_data(
+        words_idx_data: Data,
+        words_data: Data,
+    ) -> Result<WordDictionary<Data>, KuromojiError> {
+        if !words_idx_data.len().is_multiple_of(4) {
+            return Err(KuromojiError::Format(
+                "Word index length is not a multiple of 4".to_string(),
+            ));
+        }
+        Ok(WordDictionary {
+            words_idx_data,
+            words_data,
+        })
+    }
+
+    /// Returns the details of a word.
+    ///
+    /// The details are not copied: the returned `WordDetail` borrows
+    /// its fields directly from the dictionary data.
+    pub fn load_word_id(&self, word_id: WordId) -> Result<WordDetail<'_>, KuromojiError> {
         if word_id.is_unknown() {
-            return WordDetail {
-                reading: "UNK".to_string(),
-            };
+            return Ok(WordDetail::UNKNOWN);
         }
-        let idx = LittleEndian::read_u32(&WORDS_IDX_DATA[4 * word_id.0 as usize..][..4]);
-        let data = &WORDS_DATA[idx as usize..];
-        let word_entry = bincode::deserialize_from(data).unwrap();
-        word_entry
+        let idx_offset = 4 * word_id.0 as usize;
+        let idx_data = self
+            .words_idx_data
+            .get(idx_offset..idx_offset + 4)
+            .ok_or_else(|| KuromojiError::Format(format!("Word id {} out of range", word_id.0)))?;
+        let idx = LittleEndian::read_u32(idx_data) as usize;
+        let data = self.words_data.get(idx..).ok_or_else(|| {
+            KuromojiError::Format(format!("Word {} has an invalid offset", word_id.0))
+        })?;
+        Ok(bincode::deserialize(data)?)
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct WordDetail {
-    pub reading: String,
+/// Features of a word, as listed in the IPADIC CSV files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WordDetail<'a> {
+    pub pos_level1: &'a str,
+    pub pos_level2: &'a str,
+    pub pos_level3: &'a str,
+    pub pos_level4: &'a str,
+    pub conjugation_type: &'a str,
+    pub conjugate_form: &'a str,
+    pub base_form: &'a str,
+    pub reading: &'a str,
+    pub pronunciation: &'a str,
+}
+
+impl<'a> WordDetail<'a> {
+    /// Names of the fields of a `WordDetail`, in their serialization order.
+    pub const FEATURE_SCHEMA: [&'static str; 9] = [
+        "pos_level1",
+        "pos_level2",
+        "pos_level3",
+        "pos_level4",
+        "conjugation_type",
+        "conjugate_form",
+        "base_form",
+        "reading",
+        "pronunciation",
+    ];
+
+    /// Details of the words that are not in the dictionary.
+    pub const UNKNOWN: WordDetail<'static> = WordDetail {
+        pos_level1: "UNK",
+        pos_level2: "*",
+        pos_level3: "*",
+        pos_level4: "*",
+        conjugation_type: "*",
+        conjugate_form: "*",
+        base_form: "*",
+        reading: "UNK",
+        pronunciation: "UNK",
+    };
+
+    /// Returns the four levels of the part-of-speech.
+    pub fn pos(&self) -> [&'a str; 4] {
+        [
+            self.pos_level1,
+            self.pos_level2,
+            self.pos_level3,
+            self.pos_level4,
+        ]
+    }
 }
 
 #[derive(Default, Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -68,8 +154,8 @@ impl WordEntry {
 #[cfg(test)]
 mod tests {
     use super::WordDictionary;
-    use crate::{WordDetail, WordId};
     use crate::WordEntry;
+    use crate::{WordDetail, WordId};
 
     #[test]
     fn test_word_entry() {
@@ -87,9 +173,25 @@ mod tests {
 
     #[test]
     fn test_dictionary() {
-        let word_detail = WordDictionary::load_word_id(WordId(0u32));
-        assert_eq!(&word_detail.reading, "ティーシャツ");
-        let word_detail = WordDictionary::load_word_id(WordId(1u32));
-        assert_eq!(word_detail.reading, "¨".to_string());
+        let word_dictionary = WordDictionary::load_default();
+        let word_detail = word_dictionary.load_word_id(WordId(0u32)).unwrap();
+        assert_eq!(word_detail.reading, "ティーシャツ");
+        assert_eq!(word_detail.pos(), ["名詞", "一般", "*", "*"]);
+        assert_eq!(word_detail.base_form, "Tシャツ");
+        let word_detail = word_dictionary.load_word_id(WordId(1u32)).unwrap();
+        assert_eq!(word_detail.reading, "¨");
+    }
+
+    #[test]
+    fn test_dictionary_out_of_range() {
+        let word_dictionary = WordDictionary::load_default();
+        assert!(word_dictionary.load_word_id(WordId(u32::MAX - 1)).is_err());
+    }
+
+    #[test]
+    fn test_dictionary_unknown() {
+        let word_dictionary = WordDictionary::load_default();
+        let word_detail = word_dictionary.load_word_id(WordId::default()).unwrap();
+        assert_eq!(word_detail, WordDetail::UNKNOWN);
     }
 }
@@ -0,0 +1,192 @@
+//! Suppression of system dictionary words.
+//!
+//! Word masks are loaded from text files, one rule per line:
+//!
+//! ```text
+//! # masks the word with the given word id
+//! @132629
+//! # masks all of the words with the surface form `東京`
+//! 東京
+//! # masks the words with the surface form `東京` and a part-of-speech starting with `名詞,固有名詞`
+//! 東京,名詞,固有名詞
+//! ```
+//!
+//! `*` matches any level of part-of-speech. Blank lines and lines starting
+//! with `#` are ignored, and whitespace around fields is trimmed. A rule with
+//! an empty surface form is an error.
+use crate::{Dictionary, KuromojiError, WordId};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// Set of system dictionary words that are never used for tokenization.
+///
+/// Words of user dictionaries are never masked, so that a masked word can be
+/// replaced by a user word with the same surface form.
+///
+/// See `Tokenizer::with_word_mask`.
+#[derive(Clone, Debug, Default)]
+pub struct WordMask {
+    word_ids: HashSet<WordId>,
+    // part-of-speech prefixes masked for each surface form.
+    surfaces: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl WordMask {
+    pub fn mask_word_id(&mut self, word_id: WordId) {
+        self.word_ids.insert(word_id);
+    }
+
+    /// Masks the words with the given surface form, and a part-of-speech
+    /// starting with the levels of `pos`. An empty `pos` masks all of them.
+    pub fn mask(&mut self, surface: &str, pos: &[&str]) {
+        self.surfaces
+            .entry(surface.to_string())
+            .or_default()
+            .push(pos.iter().map(|level| level.to_string()).collect());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.word_ids.is_empty() && self.surfaces.is_empty()
+    }
+
+    /// Adds the rules of a word mask file.
+    pub fn load<R: BufRead>(&mut self, reader: R) -> Result<(), KuromojiError> {
+        for (line_ord, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(word_id) = line.strip_prefix('@') {
+                let word_id = u32::from_str(word_id).map_err(|_| {
+                    KuromojiError::Format(format!(
+                        "Line {}: invalid word id {:?}",
+                        line_ord + 1,
+                        word_id
+                    ))
+                })?;
+                self.mask_word_id(WordId(word_id));
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields[0].is_empty() {
+                return Err(KuromojiError::Format(format!(
+                    "Line {}: empty surface form",
+                    line_ord + 1
+                )));
+            }
+            self.mask(fields[0], &fields[1..]);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the system word `word_id`, with the surface form `surface`,
+    /// is masked.
+    pub(crate) fn is_masked(
+        &self,
+        dictionary: &Dictionary,
+        surface: &str,
+        word_id: WordId,
+    ) -> bool {
+        if self.word_ids.contains(&word_id) {
+            return true;
+        }
+        let masked_pos = match self.surfaces.get(surface) {
+            Some(masked_pos) => masked_pos,
+            None => return false,
+        };
+        let word_pos = match dictionary.word_detail(word_id) {
+            Ok(word_detail) => word_detail.pos(),
+            Err(_) => return false,
+        };
+        masked_pos.iter().any(|pos| {
+            pos.iter()
+                .zip(word_pos.iter())
+                .all(|(level, word_level)| level == "*" || level == word_level)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WordMask;
+    use crate::user_dictionary::UserDictionary;
+    use crate::{Dictionary, Mode, Tokenizer};
+    use std::sync::Arc;
+
+    fn tokenize_str(tokenizer: &mut Tokenizer, text: &str) -> Vec<String> {
+        tokenizer
+            .tokenize_str(text)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_word_mask() {
+        let mut tokenizer = Tokenizer::normal().unwrap();
+        let tokens = tokenizer.tokenize("東京に行く");
+        assert_eq!(tokens[0].text, "東京");
+        let tokyo_word_id = tokens[0].word_id;
+
+        let mut word_mask = WordMask::default();
+        word_mask.mask_word_id(tokyo_word_id);
+        let mut masked_tokenizer = tokenizer.clone().with_word_mask(word_mask);
+        let tokens = masked_tokenizer.tokenize("東京に行く");
+        assert!(tokens.iter().all(|token| token.word_id != tokyo_word_id));
+
+        let mut word_mask = WordMask::default();
+        word_mask.mask("東京", &["名詞", "動詞"]);
+        let mut masked_tokenizer = tokenizer.clone().with_word_mask(word_mask);
+        assert_eq!(
+            tokenize_str(&mut masked_tokenizer, "東京に行く"),
+            tokenize_str(&mut tokenizer, "東京に行く")
+        );
+    }
+
+    #[test]
+    fn test_word_mask_load() {
+        let mut word_mask = WordMask::default();
+        assert!(word_mask.is_empty());
+        word_mask
+            .load("# comment\n\n@132629\n東京,名詞,*,地域\n".as_bytes())
+            .unwrap();
+        assert!(!word_mask.is_empty());
+        let mut tokenizer = Tokenizer::normal().unwrap().with_word_mask(word_mask);
+        let tokens = tokenizer.tokenize("僕は東京に行く");
+        assert!(tokens.iter().all(|token| token.word_id.0 != 132629));
+        assert!(tokens.iter().all(|token| token.text != "東京"));
+        assert!(WordMask::default().load("@abc".as_bytes()).is_err());
+        assert!(WordMask::default().load(",名詞\n".as_bytes()).is_err());
+        assert!(WordMask::default().load(" \t,名詞\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_word_mask_load_whitespace() {
+        let mut word_mask = WordMask::default();
+        word_mask
+            .load("  \r\n\t\n東京 , 名詞 ,*,地域\r\n".as_bytes())
+            .unwrap();
+        assert_eq!(word_mask.surfaces.len(), 1);
+        assert_eq!(word_mask.surfaces["東京"], vec![vec!["名詞", "*", "地域"]]);
+    }
+
+    #[test]
+    fn test_word_mask_with_user_dictionary() {
+        let dictionary = Arc::new(Dictionary::load_default().unwrap());
+        let user_dictionary = UserDictionary::new(dictionary.clone());
+        let user_word_id = user_dictionary
+            .add_word("東京", "トーキョー", &["名詞", "固有名詞"], 0)
+            .unwrap();
+        let mut word_mask = WordMask::default();
+        word_mask.mask("東京", &[]);
+        let mut tokenizer = Tokenizer::with_dictionary(dictionary, Mode::Normal)
+            .with_word_mask(word_mask)
+            .with_user_dictionary(user_dictionary);
+        let tokens = tokenizer.tokenize("東京に行く");
+        assert_eq!(tokens[0].text, "東京");
+        assert_eq!(tokens[0].word_id, user_word_id);
+        assert_eq!(tokenizer.detail(&tokens[0]).reading, "トーキョー");
+    }
+}